@@ -0,0 +1,511 @@
+use std::f32::consts::PI;
+
+use crate::{
+    color::{convert_pixel, Pixel, Rgba8},
+    composite::{BlendMode, CompositeOp, PremultipliedRgba},
+    geometry::Point,
+    image::Image,
+    ndc::{ndc_to_pixel, point_to_ndc},
+    paint::Paint,
+    path::{stroke_polylines, Path, Polyline, StrokeStyle, DEFAULT_TOLERANCE},
+};
+
+// Sub-scanlines per pixel row when filling anti-aliased shapes
+const AA_SUBSAMPLES: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Coordinates {
+    // x is the column and y the row, pixel centers are at +0.5
+    Pixels,
+    // -1..1 on both axes, the same mapping as `ndc::pixel_to_ndc`, so points
+    // stretch with the image's aspect ratio. Lengths such as radii and
+    // stroke widths don't: both axes scale them by half the smaller side,
+    // so circles stay round and strokes even.
+    Ndc,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FillRule {
+    // Inside where a ray crosses the outline an odd number of times
+    EvenOdd,
+    // Inside where the outline winds around the point at all
+    NonZero,
+}
+
+// Immediate mode drawing onto an image. Shapes are composited in the order
+// they are drawn, each one onto everything drawn before it, in linear light.
+// The operator and blend mode only act where a shape covers the image, the
+// rest is left alone.
+pub struct Canvas<'a, P: Pixel> {
+    image: &'a mut Image<P>,
+    coordinates: Coordinates,
+    anti_aliasing: bool,
+    composite_op: CompositeOp,
+    blend_mode: BlendMode,
+}
+
+impl<'a, P: Pixel> Canvas<'a, P> {
+    pub fn new(image: &'a mut Image<P>) -> Canvas<'a, P> {
+        Canvas {
+            image,
+            coordinates: Coordinates::Pixels,
+            anti_aliasing: true,
+            composite_op: CompositeOp::Over,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+
+    pub fn with_coordinates(mut self, coordinates: Coordinates) -> Self {
+        self.coordinates = coordinates;
+        self
+    }
+
+    pub fn with_anti_aliasing(mut self, anti_aliasing: bool) -> Self {
+        self.anti_aliasing = anti_aliasing;
+        self
+    }
+
+    pub fn with_composite_op(mut self, op: CompositeOp) -> Self {
+        self.composite_op = op;
+        self
+    }
+
+    pub fn with_blend_mode(mut self, mode: BlendMode) -> Self {
+        self.blend_mode = mode;
+        self
+    }
+
+    // For switching between shapes without rebuilding the canvas
+    pub fn set_composite_op(&mut self, op: CompositeOp) {
+        self.composite_op = op;
+    }
+
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+    }
+
+    pub fn image(&self) -> &Image<P> {
+        self.image
+    }
+
+    pub fn image_mut(&mut self) -> &mut Image<P> {
+        self.image
+    }
+
+    pub fn clear(&mut self, color: Rgba8) {
+        self.image.fill(convert_pixel(color));
+    }
+
+    // One pixel wide line, Xiaolin Wu's algorithm when anti-aliasing and
+    // Bresenham's otherwise
+    pub fn line(&mut self, from: Point, to: Point, color: Rgba8) {
+        let (from, to) = (self.to_pixels(from), self.to_pixels(to));
+        if self.anti_aliasing {
+            self.wu_line(from, to, color);
+        } else {
+            self.bresenham_line(from, to, color);
+        }
+    }
+
+    // Line of the given width with flat ends
+    pub fn stroke_line(&mut self, from: Point, to: Point, width: f32, paint: impl Into<Paint>) {
+        self.polyline(&[from, to], width, paint);
+    }
+
+    // Connected line segments of the given width, with flat ends and
+    // mitered corners
+    pub fn polyline(&mut self, points: &[Point], width: f32, paint: impl Into<Paint>) {
+        self.stroke_points(points, false, width, &paint.into());
+    }
+
+    pub fn fill_rect(&mut self, min: Point, max: Point, paint: impl Into<Paint>) {
+        let contour = rect_contour(self.to_pixels(min), self.to_pixels(max));
+        self.fill_pixel_contours(&[contour], FillRule::NonZero, &paint.into());
+    }
+
+    // The stroke is centered on the rectangle's outline
+    pub fn stroke_rect(&mut self, min: Point, max: Point, width: f32, paint: impl Into<Paint>) {
+        let (min, max) = (self.to_pixels(min), self.to_pixels(max));
+        let (min, max) = (
+            Point::new(min.x.min(max.x), min.y.min(max.y)),
+            Point::new(min.x.max(max.x), min.y.max(max.y)),
+        );
+        let half = self.length_to_pixels(width) * 0.5;
+        let outer = rect_contour(min - Point::new(half, half), max + Point::new(half, half));
+        let mut contours = vec![outer];
+        if max.x - min.x > 2.0 * half && max.y - min.y > 2.0 * half {
+            contours.push(rect_contour(min + Point::new(half, half), max - Point::new(half, half)));
+        }
+        self.fill_pixel_contours(&contours, FillRule::EvenOdd, &paint.into());
+    }
+
+    pub fn fill_ellipse(&mut self, center: Point, radius_x: f32, radius_y: f32, paint: impl Into<Paint>) {
+        let (center, rx, ry) = self.ellipse_to_pixels(center, radius_x, radius_y);
+        let contour = ellipse_contour(center, rx, ry);
+        self.fill_pixel_contours(&[contour], FillRule::NonZero, &paint.into());
+    }
+
+    pub fn stroke_ellipse(&mut self, center: Point, radius_x: f32, radius_y: f32, width: f32, paint: impl Into<Paint>) {
+        let (center, rx, ry) = self.ellipse_to_pixels(center, radius_x, radius_y);
+        let half = self.length_to_pixels(width) * 0.5;
+        let mut contours = vec![ellipse_contour(center, rx + half, ry + half)];
+        if rx > half && ry > half {
+            contours.push(ellipse_contour(center, rx - half, ry - half));
+        }
+        self.fill_pixel_contours(&contours, FillRule::EvenOdd, &paint.into());
+    }
+
+    pub fn fill_circle(&mut self, center: Point, radius: f32, paint: impl Into<Paint>) {
+        self.fill_ellipse(center, radius, radius, paint);
+    }
+
+    pub fn stroke_circle(&mut self, center: Point, radius: f32, width: f32, paint: impl Into<Paint>) {
+        self.stroke_ellipse(center, radius, radius, width, paint);
+    }
+
+    // Any simple or self-intersecting polygon, the last point connects back
+    // to the first
+    pub fn fill_polygon(&mut self, points: &[Point], rule: FillRule, paint: impl Into<Paint>) {
+        self.fill_contours(&[points.to_vec()], rule, paint);
+    }
+
+    pub fn stroke_polygon(&mut self, points: &[Point], width: f32, paint: impl Into<Paint>) {
+        self.stroke_points(points, true, width, &paint.into());
+    }
+
+    fn stroke_points(&mut self, points: &[Point], closed: bool, width: f32, paint: &Paint) {
+        let points = points.iter().map(|&p| self.to_pixels(p)).collect();
+        let style = StrokeStyle::new(self.length_to_pixels(width));
+        let contours = stroke_polylines(&[Polyline { points, closed }], &style);
+        self.fill_pixel_contours(&contours, FillRule::NonZero, paint);
+    }
+
+    // Fills several closed contours at once, so holes and overlaps are
+    // resolved by `rule` across all of them
+    pub fn fill_contours(&mut self, contours: &[Vec<Point>], rule: FillRule, paint: impl Into<Paint>) {
+        let contours: Vec<Vec<Point>> = contours
+            .iter()
+            .map(|contour| contour.iter().map(|&p| self.to_pixels(p)).collect())
+            .collect();
+        self.fill_pixel_contours(&contours, rule, &paint.into());
+    }
+
+    pub fn fill_path(&mut self, path: &Path, rule: FillRule, paint: impl Into<Paint>) {
+        // Flatten in pixel space so the tolerance is in pixels
+        let contours: Vec<Vec<Point>> = path
+            .map_points(|p| self.to_pixels(p))
+            .flatten(DEFAULT_TOLERANCE)
+            .into_iter()
+            .map(|polyline| polyline.points)
+            .collect();
+        self.fill_pixel_contours(&contours, rule, &paint.into());
+    }
+
+    pub fn stroke_path(&mut self, path: &Path, style: &StrokeStyle, paint: impl Into<Paint>) {
+        let polylines = path.map_points(|p| self.to_pixels(p)).flatten(DEFAULT_TOLERANCE);
+        let style = StrokeStyle { width: self.length_to_pixels(style.width), ..*style };
+        let contours = stroke_polylines(&polylines, &style);
+        self.fill_pixel_contours(&contours, FillRule::NonZero, &paint.into());
+    }
+
+    // Blends `color` into a pixel by `coverage`, ignoring pixels outside the
+    // image
+    pub fn blend_pixel(&mut self, row: i64, col: i64, color: Rgba8, coverage: f32) {
+        self.blend_premultiplied(row, col, PremultipliedRgba::from_pixel(color), coverage);
+    }
+
+    fn blend_premultiplied(&mut self, row: i64, col: i64, src: PremultipliedRgba, coverage: f32) {
+        if row < 0 || col < 0 || coverage <= 0.0 {
+            return;
+        }
+        let (op, mode) = (self.composite_op, self.blend_mode);
+        if let Some(pixel) = self.image.get_mut(row as usize, col as usize) {
+            let dst = PremultipliedRgba::from_pixel(*pixel);
+            // Coverage masks the result rather than fading the source, so
+            // operators such as `In` still only reach the covered part
+            let mixed = dst.lerp(src.composite(dst, op, mode), coverage.min(1.0));
+            *pixel = mixed.to_pixel();
+        }
+    }
+
+    pub(crate) fn to_pixels(&self, p: Point) -> Point {
+        match self.coordinates {
+            Coordinates::Pixels => p,
+            Coordinates::Ndc => {
+                let (row, col) = ndc_to_pixel(p.x, p.y, self.image.width(), self.image.height());
+                Point::new(col, row)
+            }
+        }
+    }
+
+    // Inverse of `to_pixels`, where paints are evaluated
+    fn pixels_to_canvas(&self, p: Point) -> Point {
+        match self.coordinates {
+            Coordinates::Pixels => p,
+            Coordinates::Ndc => {
+                let (x, y) = point_to_ndc(p.y, p.x, self.image.width(), self.image.height());
+                Point::new(x, y)
+            }
+        }
+    }
+
+    pub(crate) fn length_to_pixels(&self, length: f32) -> f32 {
+        match self.coordinates {
+            Coordinates::Pixels => length,
+            Coordinates::Ndc => length * 0.5 * self.image.width().min(self.image.height()) as f32,
+        }
+    }
+
+    fn ellipse_to_pixels(&self, center: Point, radius_x: f32, radius_y: f32) -> (Point, f32, f32) {
+        (self.to_pixels(center), self.length_to_pixels(radius_x), self.length_to_pixels(radius_y))
+    }
+
+    fn bresenham_line(&mut self, from: Point, to: Point, color: Rgba8) {
+        let (mut x0, mut y0) = (from.x.floor() as i64, from.y.floor() as i64);
+        let (x1, y1) = (to.x.floor() as i64, to.y.floor() as i64);
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            self.blend_pixel(y0, x0, color, 1.0);
+            if x0 == x1 && y0 == y1 {
+                break;
+            }
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                x0 += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y0 += sy;
+            }
+        }
+    }
+
+    fn wu_line(&mut self, from: Point, to: Point, color: Rgba8) {
+        // Work with pixel centers at integer coordinates
+        let (mut x0, mut y0) = (from.x - 0.5, from.y - 0.5);
+        let (mut x1, mut y1) = (to.x - 0.5, to.y - 0.5);
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        if steep {
+            std::mem::swap(&mut x0, &mut y0);
+            std::mem::swap(&mut x1, &mut y1);
+        }
+        if x0 > x1 {
+            std::mem::swap(&mut x0, &mut x1);
+            std::mem::swap(&mut y0, &mut y1);
+        }
+        let dx = x1 - x0;
+        let gradient = if dx == 0.0 { 1.0 } else { (y1 - y0) / dx };
+
+        let plot = |canvas: &mut Self, x: f32, y: f32, coverage: f32| {
+            let (x, y) = (x as i64, y as i64);
+            if steep {
+                canvas.blend_pixel(x, y, color, coverage);
+            } else {
+                canvas.blend_pixel(y, x, color, coverage);
+            }
+        };
+
+        // First endpoint
+        let x_end = x0.round();
+        let y_end = y0 + gradient * (x_end - x0);
+        let x_gap = 1.0 - (x0 + 0.5).fract();
+        let x_start = x_end;
+        plot(self, x_start, y_end.floor(), (1.0 - y_end.fract()) * x_gap);
+        plot(self, x_start, y_end.floor() + 1.0, y_end.fract() * x_gap);
+        let mut intery = y_end + gradient;
+
+        // Second endpoint
+        let x_end = x1.round();
+        let y_end = y1 + gradient * (x_end - x1);
+        let x_gap = (x1 + 0.5).fract();
+        let x_stop = x_end;
+        plot(self, x_stop, y_end.floor(), (1.0 - y_end.fract()) * x_gap);
+        plot(self, x_stop, y_end.floor() + 1.0, y_end.fract() * x_gap);
+
+        let mut x = x_start + 1.0;
+        while x < x_stop {
+            plot(self, x, intery.floor(), 1.0 - intery.fract());
+            plot(self, x, intery.floor() + 1.0, intery.fract());
+            intery += gradient;
+            x += 1.0;
+        }
+    }
+
+    // Scanline fill of contours already in pixel space. Anti-aliased fills
+    // take several sub-scanlines per row and measure horizontal coverage
+    // exactly, aliased fills include a pixel when its center is inside.
+    pub(crate) fn fill_pixel_contours(&mut self, contours: &[Vec<Point>], rule: FillRule, paint: &Paint) {
+        let edges: Vec<(Point, Point)> = contours
+            .iter()
+            .filter(|contour| contour.len() >= 2)
+            .flat_map(|contour| {
+                contour
+                    .iter()
+                    .zip(contour.iter().cycle().skip(1))
+                    .map(|(&a, &b)| (a, b))
+                    .filter(|(a, b)| a.y != b.y && a.y.is_finite() && b.y.is_finite())
+            })
+            .collect();
+        if edges.is_empty() {
+            return;
+        }
+
+        let (width, height) = (self.image.width(), self.image.height());
+        let min_y = edges.iter().map(|(a, b)| a.y.min(b.y)).fold(f32::MAX, f32::min);
+        let max_y = edges.iter().map(|(a, b)| a.y.max(b.y)).fold(f32::MIN, f32::max);
+        let first_row = min_y.floor().max(0.0) as usize;
+        let last_row = (max_y.ceil().max(0.0) as usize).min(height);
+
+        let subsamples = if self.anti_aliasing { AA_SUBSAMPLES } else { 1 };
+        let mut coverage = vec![0.0f32; width + 1];
+        let mut crossings: Vec<(f32, i32)> = Vec::new();
+
+        for row in first_row..last_row {
+            coverage.iter_mut().for_each(|c| *c = 0.0);
+            let mut touched = false;
+
+            for sub in 0..subsamples {
+                let y = row as f32 + (sub as f32 + 0.5) / subsamples as f32;
+                crossings.clear();
+                for &(a, b) in &edges {
+                    let (top, bottom, direction) = if a.y < b.y { (a, b, 1) } else { (b, a, -1) };
+                    if y < top.y || y >= bottom.y {
+                        continue;
+                    }
+                    let t = (y - top.y) / (bottom.y - top.y);
+                    crossings.push((top.x + (bottom.x - top.x) * t, direction));
+                }
+                crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                let mut winding = 0;
+                for pair in crossings.windows(2) {
+                    winding += pair[0].1;
+                    let inside = match rule {
+                        FillRule::EvenOdd => winding % 2 != 0,
+                        FillRule::NonZero => winding != 0,
+                    };
+                    if inside {
+                        touched = true;
+                        let weight = 1.0 / subsamples as f32;
+                        if self.anti_aliasing {
+                            add_span_coverage(&mut coverage, pair[0].0, pair[1].0, weight);
+                        } else {
+                            add_span_centers(&mut coverage, pair[0].0, pair[1].0, weight);
+                        }
+                    }
+                }
+            }
+
+            if touched {
+                for (col, &c) in coverage.iter().take(width).enumerate() {
+                    if c > 0.0 {
+                        let center = self.pixels_to_canvas(Point::new(col as f32 + 0.5, row as f32 + 0.5));
+                        self.blend_premultiplied(row as i64, col as i64, paint.color_at(center), c);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Adds the exact overlap of [x0, x1) with every pixel column
+fn add_span_coverage(coverage: &mut [f32], x0: f32, x1: f32, weight: f32) {
+    let width = (coverage.len() - 1) as f32;
+    let (x0, x1) = (x0.clamp(0.0, width), x1.clamp(0.0, width));
+    if x1 <= x0 {
+        return;
+    }
+    let first = x0.floor() as usize;
+    let last = (x1.ceil() as usize).min(coverage.len() - 1);
+    for (col, c) in coverage.iter_mut().enumerate().take(last).skip(first) {
+        let left = x0.max(col as f32);
+        let right = x1.min(col as f32 + 1.0);
+        if right > left {
+            *c += (right - left) * weight;
+        }
+    }
+}
+
+// Adds full coverage to every column whose center is in [x0, x1)
+fn add_span_centers(coverage: &mut [f32], x0: f32, x1: f32, weight: f32) {
+    let width = coverage.len() - 1;
+    let first = (x0 - 0.5).ceil().max(0.0) as usize;
+    let last = ((x1 - 0.5).ceil().max(0.0) as usize).min(width);
+    for c in coverage.iter_mut().take(last).skip(first) {
+        *c += weight;
+    }
+}
+
+fn rect_contour(min: Point, max: Point) -> Vec<Point> {
+    vec![min, Point::new(max.x, min.y), max, Point::new(min.x, max.y)]
+}
+
+// Enough segments that the polygon stays within a fraction of a pixel of
+// the true ellipse
+pub(crate) fn ellipse_contour(center: Point, rx: f32, ry: f32) -> Vec<Point> {
+    let radius = rx.abs().max(ry.abs());
+    let segments = ((PI / (1.0 - 0.1 / radius.max(0.2)).clamp(-1.0, 1.0).acos()).ceil() as usize).clamp(8, 4096);
+    (0..segments)
+        .map(|i| {
+            let angle = 2.0 * PI * i as f32 / segments as f32;
+            Point::new(center.x + rx * angle.cos(), center.y + ry * angle.sin())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgba8 = Rgba8::new(255, 255, 255, 255);
+
+    fn covered(image: &Image<Rgba8>, x: usize, y: usize) -> bool {
+        image.get(y, x).unwrap().a == 255
+    }
+
+    #[test]
+    fn thick_polylines_have_mitered_corners() {
+        let mut image = Image::new(40, 40);
+        Canvas::new(&mut image).with_anti_aliasing(false).polyline(
+            &[Point::new(10.0, 10.0), Point::new(30.0, 10.0), Point::new(30.0, 30.0)],
+            8.0,
+            WHITE,
+        );
+        // The outer corner, which separate segments leave notched
+        assert!(covered(&image, 33, 6));
+        assert!(covered(&image, 20, 7) && covered(&image, 32, 20));
+        // Flat ends
+        assert!(!covered(&image, 8, 10) && !covered(&image, 30, 32));
+    }
+
+    #[test]
+    fn polygon_strokes_join_at_the_first_point() {
+        let mut image = Image::new(40, 40);
+        Canvas::new(&mut image).with_anti_aliasing(false).stroke_polygon(
+            &[Point::new(10.0, 10.0), Point::new(30.0, 10.0), Point::new(30.0, 30.0), Point::new(10.0, 30.0)],
+            8.0,
+            WHITE,
+        );
+        for (x, y) in [(6, 6), (33, 6), (33, 33), (6, 33)] {
+            assert!(covered(&image, x, y), "corner at {}, {}", x, y);
+        }
+        assert!(!covered(&image, 20, 20));
+    }
+
+    #[test]
+    fn ndc_lengths_scale_the_same_on_both_axes() {
+        // 200 by 100, so half the smaller side is 50 pixels
+        let mut image = Image::new(200, 100);
+        let mut canvas = Canvas::new(&mut image).with_anti_aliasing(false).with_coordinates(Coordinates::Ndc);
+        assert_eq!(canvas.length_to_pixels(0.5), 25.0);
+        canvas.fill_ellipse(Point::new(0.0, 0.0), 0.5, 0.5, WHITE);
+        // A circle of 25 pixels about the center, not a 50 by 25 ellipse
+        assert!(covered(&image, 100, 50) && covered(&image, 123, 50) && covered(&image, 100, 73));
+        assert!(!covered(&image, 127, 50) && !covered(&image, 73, 50) && !covered(&image, 100, 77));
+    }
+}
@@ -0,0 +1,270 @@
+use std::{fmt::Debug, sync::OnceLock};
+
+// A pixel format an `Image` can store. Every format converts through
+// normalized RGBA, which is also how images are converted between formats.
+pub trait Pixel: Copy + Default + PartialEq + Debug + Send + Sync {
+    const CHANNELS: usize;
+    // Whether the channels hold linear light rather than sRGB encoded
+    // values. The 8-bit formats are sRGB, the float formats linear.
+    const LINEAR: bool = false;
+
+    // Channels as stored, straight (not premultiplied) alpha
+    fn to_rgba(self) -> [f32; 4];
+    fn from_rgba(rgba: [f32; 4]) -> Self;
+
+    fn to_linear_rgba(self) -> [f32; 4] {
+        let rgba = self.to_rgba();
+        if Self::LINEAR {
+            return rgba;
+        }
+        [srgb_to_linear(rgba[0]), srgb_to_linear(rgba[1]), srgb_to_linear(rgba[2]), rgba[3]]
+    }
+
+    fn from_linear_rgba(rgba: [f32; 4]) -> Self {
+        if Self::LINEAR {
+            return Self::from_rgba(rgba);
+        }
+        Self::from_rgba([linear_to_srgb(rgba[0]), linear_to_srgb(rgba[1]), linear_to_srgb(rgba[2]), rgba[3]])
+    }
+}
+
+// Converts between formats, decoding or encoding sRGB only when one side is
+// linear and the other is not
+pub fn convert_pixel<P: Pixel, Q: Pixel>(pixel: P) -> Q {
+    if P::LINEAR == Q::LINEAR {
+        Q::from_rgba(pixel.to_rgba())
+    } else {
+        Q::from_linear_rgba(pixel.to_linear_rgba())
+    }
+}
+
+// The sRGB transfer function, IEC 61966-2-1
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Table lookup for the common case of decoding 8-bit channels
+pub fn srgb8_to_linear(v: u8) -> f32 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|i| srgb_to_linear(i as f32 / 255.0)))[v as usize]
+}
+
+// Björn Ottosson's OKLab, a perceptual space where equal steps look about
+// equally different, from and to linear sRGB
+pub fn linear_srgb_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.4122215 * r + 0.5363325 * g + 0.051446 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.107397 * b).cbrt();
+    let s = (0.0883025 * r + 0.2817188 * g + 0.6299787 * b).cbrt();
+    [
+        0.2104543 * l + 0.7936178 * m - 0.004072 * s,
+        1.977998 * l - 2.428592 * m + 0.4505937 * s,
+        0.025904 * l + 0.7827718 * m - 0.8086758 * s,
+    ]
+}
+
+pub fn oklab_to_linear_srgb([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = (l + 0.3963378 * a + 0.2158038 * b).powi(3);
+    let m_ = (l - 0.1055613 * a - 0.0638542 * b).powi(3);
+    let s_ = (l - 0.0894842 * a - 1.291486 * b).powi(3);
+    [
+        4.076742 * l_ - 3.307712 * m_ + 0.2309699 * s_,
+        -1.268438 * l_ + 2.609757 * m_ - 0.3413194 * s_,
+        -0.0041961 * l_ - 0.7034186 * m_ + 1.707615 * s_,
+    ]
+}
+
+fn to_u8(v: f32) -> u8 {
+    (v * 255.0).round() as u8
+}
+
+fn to_f32(v: u8) -> f32 {
+    v as f32 / 255.0
+}
+
+// Rec. 709 luma weights
+fn luma(rgba: [f32; 4]) -> f32 {
+    0.2126 * rgba[0] + 0.7152 * rgba[1] + 0.0722 * rgba[2]
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Rgb8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb8 {
+    pub const BLACK: Rgb8 = Rgb8::new(0, 0, 0);
+    pub const WHITE: Rgb8 = Rgb8::new(255, 255, 255);
+    pub const RED: Rgb8 = Rgb8::new(255, 0, 0);
+    pub const GREEN: Rgb8 = Rgb8::new(0, 255, 0);
+    pub const BLUE: Rgb8 = Rgb8::new(0, 0, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb8 {
+        Rgb8 { r, g, b }
+    }
+
+    // Channels in 0.0..=1.0, anything outside is clamped
+    pub fn from_f32(r: f32, g: f32, b: f32) -> Rgb8 {
+        Rgb8 { r: to_u8(r), g: to_u8(g), b: to_u8(b) }
+    }
+
+    pub fn to_f32(self) -> [f32; 3] {
+        [to_f32(self.r), to_f32(self.g), to_f32(self.b)]
+    }
+
+    // Blends toward `other` by `t` in 0.0..=1.0
+    pub fn lerp(self, other: Rgb8, t: f32) -> Rgb8 {
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Rgb8::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b))
+    }
+}
+
+impl Pixel for Rgb8 {
+    const CHANNELS: usize = 3;
+
+    fn to_rgba(self) -> [f32; 4] {
+        [to_f32(self.r), to_f32(self.g), to_f32(self.b), 1.0]
+    }
+
+    fn to_linear_rgba(self) -> [f32; 4] {
+        [srgb8_to_linear(self.r), srgb8_to_linear(self.g), srgb8_to_linear(self.b), 1.0]
+    }
+
+    fn from_rgba(rgba: [f32; 4]) -> Self {
+        Rgb8::from_f32(rgba[0], rgba[1], rgba[2])
+    }
+}
+
+// Same channels as `Rgb8` in the order BMP files store them
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct Bgr8 {
+    pub b: u8,
+    pub g: u8,
+    pub r: u8,
+}
+
+impl Bgr8 {
+    pub const fn new(b: u8, g: u8, r: u8) -> Bgr8 {
+        Bgr8 { b, g, r }
+    }
+}
+
+impl Pixel for Bgr8 {
+    const CHANNELS: usize = 3;
+
+    fn to_rgba(self) -> [f32; 4] {
+        [to_f32(self.r), to_f32(self.g), to_f32(self.b), 1.0]
+    }
+
+    fn to_linear_rgba(self) -> [f32; 4] {
+        [srgb8_to_linear(self.r), srgb8_to_linear(self.g), srgb8_to_linear(self.b), 1.0]
+    }
+
+    fn from_rgba(rgba: [f32; 4]) -> Self {
+        Bgr8 { b: to_u8(rgba[2]), g: to_u8(rgba[1]), r: to_u8(rgba[0]) }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Rgba8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba8 {
+    pub const TRANSPARENT: Rgba8 = Rgba8::new(0, 0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Rgba8 {
+        Rgba8 { r, g, b, a }
+    }
+}
+
+impl Pixel for Rgba8 {
+    const CHANNELS: usize = 4;
+
+    fn to_rgba(self) -> [f32; 4] {
+        [to_f32(self.r), to_f32(self.g), to_f32(self.b), to_f32(self.a)]
+    }
+
+    fn to_linear_rgba(self) -> [f32; 4] {
+        [srgb8_to_linear(self.r), srgb8_to_linear(self.g), srgb8_to_linear(self.b), to_f32(self.a)]
+    }
+
+    fn from_rgba(rgba: [f32; 4]) -> Self {
+        Rgba8 { r: to_u8(rgba[0]), g: to_u8(rgba[1]), b: to_u8(rgba[2]), a: to_u8(rgba[3]) }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct RgbF32 {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+impl RgbF32 {
+    pub const fn new(r: f32, g: f32, b: f32) -> RgbF32 {
+        RgbF32 { r, g, b }
+    }
+}
+
+impl Pixel for RgbF32 {
+    const CHANNELS: usize = 3;
+    const LINEAR: bool = true;
+
+    fn to_rgba(self) -> [f32; 4] {
+        [self.r, self.g, self.b, 1.0]
+    }
+
+    fn from_rgba(rgba: [f32; 4]) -> Self {
+        RgbF32 { r: rgba[0], g: rgba[1], b: rgba[2] }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Gray8(pub u8);
+
+impl Pixel for Gray8 {
+    const CHANNELS: usize = 1;
+
+    fn to_rgba(self) -> [f32; 4] {
+        let v = to_f32(self.0);
+        [v, v, v, 1.0]
+    }
+
+    fn from_rgba(rgba: [f32; 4]) -> Self {
+        Gray8(to_u8(luma(rgba)))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct GrayF32(pub f32);
+
+impl Pixel for GrayF32 {
+    const CHANNELS: usize = 1;
+    const LINEAR: bool = true;
+
+    fn to_rgba(self) -> [f32; 4] {
+        [self.0, self.0, self.0, 1.0]
+    }
+
+    fn from_rgba(rgba: [f32; 4]) -> Self {
+        GrayF32(luma(rgba))
+    }
+}
@@ -0,0 +1,284 @@
+use std::{fmt, str::FromStr};
+
+use crate::color::Pixel;
+
+// Porter–Duff operators: which parts of the source and the destination
+// survive where they are combined
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CompositeOp {
+    // Source on top of the destination
+    #[default]
+    Over,
+    // Source only where the destination is
+    In,
+    // Source only where the destination is not
+    Out,
+    // Source on top, but only where the destination is
+    Atop,
+    // Each only where the other is not
+    Xor,
+}
+
+impl CompositeOp {
+    // How much of the source and of the destination end up in the result
+    fn factors(self, src_alpha: f32, dst_alpha: f32) -> (f32, f32) {
+        match self {
+            CompositeOp::Over => (1.0, 1.0 - src_alpha),
+            CompositeOp::In => (dst_alpha, 0.0),
+            CompositeOp::Out => (1.0 - dst_alpha, 0.0),
+            CompositeOp::Atop => (dst_alpha, 1.0 - src_alpha),
+            CompositeOp::Xor => (1.0 - dst_alpha, 1.0 - src_alpha),
+        }
+    }
+}
+
+// Separable blend modes, how the source color mixes with the destination
+// color where both are present
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BlendMode {
+    // The source color replaces the destination's
+    #[default]
+    Normal,
+    // Darkens, white leaves the destination unchanged
+    Multiply,
+    // Lightens, black leaves the destination unchanged
+    Screen,
+    // Multiply on the destination's darks, screen on its lights
+    Overlay,
+    // Light adds up, useful for glows; not clamped so HDR targets keep it
+    Additive,
+}
+
+impl BlendMode {
+    // `dst` and `src` are straight linear channel values
+    fn blend(self, dst: f32, src: f32) -> f32 {
+        match self {
+            BlendMode::Normal => src,
+            BlendMode::Multiply => dst * src,
+            BlendMode::Screen => dst + src - dst * src,
+            BlendMode::Overlay => {
+                if dst <= 0.5 {
+                    2.0 * dst * src
+                } else {
+                    let dst = 2.0 * dst - 1.0;
+                    dst + src - dst * src
+                }
+            }
+            BlendMode::Additive => dst + src,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCompositeOpError(String);
+
+impl fmt::Display for ParseCompositeOpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown composite operator `{}`, expected over, in, out, atop or xor", self.0)
+    }
+}
+
+impl std::error::Error for ParseCompositeOpError {}
+
+impl FromStr for CompositeOp {
+    type Err = ParseCompositeOpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "over" => Ok(CompositeOp::Over),
+            "in" => Ok(CompositeOp::In),
+            "out" => Ok(CompositeOp::Out),
+            "atop" => Ok(CompositeOp::Atop),
+            "xor" => Ok(CompositeOp::Xor),
+            _ => Err(ParseCompositeOpError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBlendModeError(String);
+
+impl fmt::Display for ParseBlendModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown blend mode `{}`, expected normal, multiply, screen, overlay or additive", self.0)
+    }
+}
+
+impl std::error::Error for ParseBlendModeError {}
+
+impl FromStr for BlendMode {
+    type Err = ParseBlendModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(BlendMode::Normal),
+            "multiply" => Ok(BlendMode::Multiply),
+            "screen" => Ok(BlendMode::Screen),
+            "overlay" => Ok(BlendMode::Overlay),
+            "additive" => Ok(BlendMode::Additive),
+            _ => Err(ParseBlendModeError(s.to_string())),
+        }
+    }
+}
+
+// Linear light RGBA with the color already multiplied by alpha. In this
+// form compositing is a weighted sum and filtering does not bleed the color
+// of transparent pixels into their neighbours.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct PremultipliedRgba {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl PremultipliedRgba {
+    pub const TRANSPARENT: PremultipliedRgba = PremultipliedRgba::new(0.0, 0.0, 0.0, 0.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> PremultipliedRgba {
+        PremultipliedRgba { r, g, b, a }
+    }
+
+    // Decodes sRGB formats and premultiplies
+    pub fn from_pixel<P: Pixel>(pixel: P) -> PremultipliedRgba {
+        PremultipliedRgba::from_rgba(pixel.to_linear_rgba())
+    }
+
+    pub fn to_pixel<P: Pixel>(self) -> P {
+        P::from_linear_rgba(self.to_rgba())
+    }
+
+    // Fades the color out, as partial coverage does
+    pub fn scale(self, k: f32) -> PremultipliedRgba {
+        PremultipliedRgba::new(self.r * k, self.g * k, self.b * k, self.a * k)
+    }
+
+    pub fn lerp(self, other: PremultipliedRgba, t: f32) -> PremultipliedRgba {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        PremultipliedRgba::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b), mix(self.a, other.a))
+    }
+
+    // `self` drawn onto `dst`. The blend mode decides the color where the
+    // two overlap and the operator how much of each is kept, following the
+    // W3C compositing and blending spec.
+    pub fn composite(self, dst: PremultipliedRgba, op: CompositeOp, mode: BlendMode) -> PremultipliedRgba {
+        let (src_alpha, dst_alpha) = (self.a, dst.a);
+        let src = match mode {
+            BlendMode::Normal => self,
+            _ => {
+                let unpremultiply = |c: f32, a: f32| if a > 0.0 { c / a } else { 0.0 };
+                let blend = |s: f32, d: f32| {
+                    let mixed = mode.blend(unpremultiply(d, dst_alpha), unpremultiply(s, src_alpha));
+                    s * (1.0 - dst_alpha) + src_alpha * dst_alpha * mixed
+                };
+                PremultipliedRgba::new(blend(self.r, dst.r), blend(self.g, dst.g), blend(self.b, dst.b), src_alpha)
+            }
+        };
+        let (fs, fd) = op.factors(src_alpha, dst_alpha);
+        PremultipliedRgba::new(
+            src.r * fs + dst.r * fd,
+            src.g * fs + dst.g * fd,
+            src.b * fs + dst.b * fd,
+            src_alpha * fs + dst_alpha * fd,
+        )
+    }
+
+    pub fn over(self, dst: PremultipliedRgba) -> PremultipliedRgba {
+        self.composite(dst, CompositeOp::Over, BlendMode::Normal)
+    }
+}
+
+// As a pixel format it stores linear light, and converts to and from
+// straight alpha at the boundary
+impl Pixel for PremultipliedRgba {
+    const CHANNELS: usize = 4;
+    const LINEAR: bool = true;
+
+    fn to_rgba(self) -> [f32; 4] {
+        if self.a <= 0.0 {
+            return [0.0; 4];
+        }
+        [self.r / self.a, self.g / self.a, self.b / self.a, self.a]
+    }
+
+    fn from_rgba(rgba: [f32; 4]) -> Self {
+        let a = rgba[3];
+        PremultipliedRgba::new(rgba[0] * a, rgba[1] * a, rgba[2] * a, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: PremultipliedRgba, expected: PremultipliedRgba) {
+        let channels = |p: PremultipliedRgba| [p.r, p.g, p.b, p.a];
+        let (a, e) = (channels(actual), channels(expected));
+        assert!((0..4).all(|i| (a[i] - e[i]).abs() < 1e-5), "{:?} against {:?}", actual, expected);
+    }
+
+    fn gray(value: f32, alpha: f32) -> PremultipliedRgba {
+        PremultipliedRgba::new(value, value, value, alpha)
+    }
+
+    // Straight 0.5 at alpha 0.5 onto straight 0.4 at alpha 0.8
+    const SRC: PremultipliedRgba = PremultipliedRgba::new(0.25, 0.25, 0.25, 0.5);
+    const DST: PremultipliedRgba = PremultipliedRgba::new(0.32, 0.32, 0.32, 0.8);
+
+    #[test]
+    fn porter_duff_operators() {
+        assert_eq!(CompositeOp::Over.factors(0.5, 0.8), (1.0, 0.5));
+        assert_eq!(CompositeOp::In.factors(0.5, 0.8), (0.8, 0.0));
+        assert!((CompositeOp::Out.factors(0.5, 0.8).0 - 0.2).abs() < 1e-6);
+        assert_eq!(CompositeOp::Atop.factors(0.5, 0.8), (0.8, 0.5));
+        assert_eq!(CompositeOp::Xor.factors(0.5, 0.8).1, 0.5);
+
+        let cases = [
+            (CompositeOp::Over, gray(0.41, 0.9)),
+            (CompositeOp::In, gray(0.2, 0.4)),
+            (CompositeOp::Out, gray(0.05, 0.1)),
+            (CompositeOp::Atop, gray(0.36, 0.8)),
+            (CompositeOp::Xor, gray(0.21, 0.5)),
+        ];
+        for (op, expected) in cases {
+            assert_close(SRC.composite(DST, op, BlendMode::Normal), expected);
+        }
+        assert_eq!(SRC.over(DST), SRC.composite(DST, CompositeOp::Over, BlendMode::Normal));
+    }
+
+    #[test]
+    fn blend_modes() {
+        assert_eq!(BlendMode::Normal.blend(0.4, 0.5), 0.5);
+        assert!((BlendMode::Multiply.blend(0.4, 0.5) - 0.2).abs() < 1e-6);
+        assert!((BlendMode::Screen.blend(0.4, 0.5) - 0.7).abs() < 1e-6);
+        // Multiply below half the destination, screen above it
+        assert!((BlendMode::Overlay.blend(0.4, 0.5) - 0.4).abs() < 1e-6);
+        assert!((BlendMode::Overlay.blend(0.8, 0.5) - 0.8).abs() < 1e-6);
+        assert!((BlendMode::Additive.blend(0.8, 0.5) - 1.3).abs() < 1e-6);
+
+        // The W3C formula: αs((1 - αb)Cs + αb B(Cb, Cs)) + (1 - αs)αb Cb,
+        // here 0.05 + 0.4 B + 0.16
+        let cases = [
+            (BlendMode::Normal, 0.41),
+            (BlendMode::Multiply, 0.29),
+            (BlendMode::Screen, 0.49),
+            (BlendMode::Overlay, 0.37),
+            (BlendMode::Additive, 0.57),
+        ];
+        for (mode, expected) in cases {
+            assert_close(SRC.composite(DST, CompositeOp::Over, mode), gray(expected, 0.9));
+        }
+
+        // Onto nothing the source shows as it is, whatever the mode
+        for mode in [BlendMode::Multiply, BlendMode::Screen, BlendMode::Overlay, BlendMode::Additive] {
+            assert_close(SRC.composite(PremultipliedRgba::TRANSPARENT, CompositeOp::Over, mode), SRC);
+        }
+    }
+
+    #[test]
+    fn straight_alpha_at_the_boundary() {
+        assert_eq!(PremultipliedRgba::from_rgba([0.5, 1.0, 0.0, 0.5]), PremultipliedRgba::new(0.25, 0.5, 0.0, 0.5));
+        assert_eq!(PremultipliedRgba::new(0.25, 0.5, 0.0, 0.5).to_rgba(), [0.5, 1.0, 0.0, 0.5]);
+        assert_eq!(PremultipliedRgba::new(0.3, 0.2, 0.1, 0.0).to_rgba(), [0.0; 4]);
+    }
+}
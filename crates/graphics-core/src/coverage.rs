@@ -0,0 +1,99 @@
+use std::{f32::consts::PI, fmt, str::FromStr};
+
+// Where inside a pixel the samples go, as offsets in 0..1 from the pixel's
+// top-left corner.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SamplePattern {
+    Center,
+    // N×N samples on a regular grid
+    Grid(usize),
+    // N×N grid rotated so no two samples share a row or column, which
+    // handles near horizontal and vertical edges much better
+    RotatedGrid(usize),
+    Custom(Vec<(f32, f32)>),
+}
+
+impl SamplePattern {
+    pub fn offsets(&self) -> Vec<(f32, f32)> {
+        match self {
+            SamplePattern::Center => vec![(0.5, 0.5)],
+            SamplePattern::Grid(n) => grid(*n).collect(),
+            SamplePattern::RotatedGrid(n) => {
+                // atan(1/2) puts every sample on its own row and column
+                let angle = 0.5f32.atan();
+                let (sin, cos) = angle.sin_cos();
+                grid(*n)
+                    .map(|(x, y)| {
+                        let (x, y) = (x - 0.5, y - 0.5);
+                        let rx = x * cos - y * sin + 0.5;
+                        let ry = x * sin + y * cos + 0.5;
+                        (rx.rem_euclid(1.0), ry.rem_euclid(1.0))
+                    })
+                    .collect()
+            }
+            SamplePattern::Custom(offsets) => offsets.clone(),
+        }
+    }
+}
+
+fn grid(n: usize) -> impl Iterator<Item = (f32, f32)> {
+    let n = n.max(1);
+    (0..n * n).map(move |i| {
+        let x = ((i % n) as f32 + 0.5) / n as f32;
+        let y = ((i / n) as f32 + 0.5) / n as f32;
+        (x, y)
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsePatternError(String);
+
+impl fmt::Display for ParsePatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown sample pattern `{}`, expected center, grid:N or rotated:N", self.0)
+    }
+}
+
+impl std::error::Error for ParsePatternError {}
+
+impl FromStr for SamplePattern {
+    type Err = ParsePatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePatternError(s.to_string());
+        let (name, n) = match s.split_once(':') {
+            Some((name, n)) => (name, Some(n.parse::<usize>().map_err(|_| err())?)),
+            None => (s, None),
+        };
+        match (name, n) {
+            ("center", None) => Ok(SamplePattern::Center),
+            ("grid", Some(n)) if n > 0 => Ok(SamplePattern::Grid(n)),
+            ("rotated", Some(n)) if n > 0 => Ok(SamplePattern::RotatedGrid(n)),
+            _ => Err(err()),
+        }
+    }
+}
+
+// Fraction of `offsets` for which `inside(col, row)` holds, where the sample
+// positions are in fractional pixel coordinates
+pub fn supersample(row: usize, col: usize, offsets: &[(f32, f32)], inside: impl Fn(f32, f32) -> bool) -> f32 {
+    if offsets.is_empty() {
+        return 0.0;
+    }
+    let hits = offsets
+        .iter()
+        .filter(|(dx, dy)| inside(col as f32 + dx, row as f32 + dy))
+        .count();
+    hits as f32 / offsets.len() as f32
+}
+
+// Approximate area coverage of a pixel by a shape, given the signed distance
+// in pixels from the pixel center to the shape's edge (negative inside).
+// Exact for a straight edge through a pixel-sized disc.
+pub fn coverage_from_distance(signed_distance: f32) -> f32 {
+    // Area of a unit-area disc (radius 1/sqrt(pi)) cut by a line at `d`
+    let radius = 1.0 / PI.sqrt();
+    let d = (-signed_distance / radius).clamp(-1.0, 1.0);
+    let segment = d * (1.0 - d * d).sqrt() + d.asin();
+    0.5 + segment / PI
+}
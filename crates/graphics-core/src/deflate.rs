@@ -0,0 +1,264 @@
+// Just enough of zlib (RFC 1950) and deflate (RFC 1951) to write PNG files:
+// LZ77 matching with fixed Huffman codes. Not the smallest output, but
+// simple and much better than storing the bytes raw.
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { bytes: Vec::new(), buffer: 0, bits: 0 }
+    }
+
+    // Deflate packs values least significant bit first
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.bits;
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    // Huffman codes are defined most significant bit first
+    fn write_code(&mut self, code: u32, bits: u32) {
+        let reversed = code.reverse_bits() >> (32 - bits);
+        self.write(reversed, bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn write_literal(out: &mut BitWriter, value: u16) {
+    match value {
+        0..=143 => out.write_code(0x30 + value as u32, 8),
+        144..=255 => out.write_code(0x190 + (value as u32 - 144), 9),
+        256..=279 => out.write_code(value as u32 - 256, 7),
+        _ => out.write_code(0xc0 + (value as u32 - 280), 8),
+    }
+}
+
+fn write_match(out: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+    write_literal(out, 257 + code as u16);
+    out.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+
+    let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+    out.write_code(code as u32, 5);
+    out.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+}
+
+fn hash(data: &[u8]) -> usize {
+    let v = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+// Raw deflate stream, a single block with the fixed Huffman codes
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    out.write(1, 1); // final block
+    out.write(1, 2); // fixed Huffman codes
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |head: &mut [usize], prev: &mut [usize], i: usize| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(&data[i..]);
+            prev[i % WINDOW_SIZE] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(&data[i..])];
+            let max_length = MAX_MATCH.min(data.len() - i);
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            write_match(&mut out, best_length, best_distance);
+            for j in i..i + best_length {
+                insert(&mut head, &mut prev, j);
+            }
+            i += best_length;
+        } else {
+            write_literal(&mut out, data[i] as u16);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+    write_literal(&mut out, 256); // end of block
+    out.finish()
+}
+
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+// zlib wrapped deflate stream, what PNG's IDAT chunks hold
+pub(crate) fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+pub(crate) struct Crc32 {
+    table: [u32; 256],
+}
+
+impl Crc32 {
+    pub(crate) fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        Crc32 { table }
+    }
+
+    pub(crate) fn checksum(&self, parts: &[&[u8]]) -> u32 {
+        let mut c = 0xffff_ffffu32;
+        for part in parts {
+            for &byte in *part {
+                c = self.table[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8);
+            }
+        }
+        c ^ 0xffff_ffff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::inflate::{decompress_to_vec, decompress_to_vec_zlib};
+
+    // Deterministic bytes that don't compress
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adler32_matches_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"a"), 0x0062_0062);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        // Long enough for the sums to need reducing along the way
+        let data = vec![0xff; 100_000];
+        let (mut a, mut b) = (1u64, 0u64);
+        for &byte in &data {
+            a = (a + byte as u64) % 65521;
+            b = (b + a) % 65521;
+        }
+        assert_eq!(adler32(&data), (b << 16 | a) as u32);
+    }
+
+    #[test]
+    fn crc32_matches_known_values() {
+        let crc = Crc32::new();
+        assert_eq!(crc.checksum(&[]), 0);
+        assert_eq!(crc.checksum(&[b"123456789"]), 0xcbf4_3926);
+        assert_eq!(crc.checksum(&[b"1234", b"", b"56789"]), 0xcbf4_3926);
+        // The CRC every PNG ends with
+        assert_eq!(crc.checksum(&[b"IEND"]), 0xae42_6082);
+    }
+
+    #[test]
+    fn deflate_round_trips_through_inflate() {
+        let mut long_runs = vec![7u8; 1000];
+        long_runs.extend(noise(40_000));
+        // Repeats from further back than the window reaches
+        long_runs.extend(long_runs[..1000].to_vec());
+        long_runs.extend(long_runs[1000..2000].to_vec());
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabcabc".to_vec(),
+            (0..=255).collect(),
+            b"the quick brown fox jumps over the lazy dog. ".repeat(100),
+            noise(5000),
+            long_runs,
+        ];
+        for input in &inputs {
+            assert_eq!(&decompress_to_vec(&deflate(input)).unwrap(), input);
+            assert_eq!(&decompress_to_vec_zlib(&zlib_compress(input)).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn deflate_finds_matches() {
+        let input = vec![b'x'; 10_000];
+        assert!(deflate(&input).len() < 100);
+    }
+}
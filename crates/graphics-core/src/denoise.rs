@@ -0,0 +1,173 @@
+use crate::{
+    color::{GrayF32, RgbF32},
+    image::Image,
+};
+
+// Per-pixel features of the first surface each pixel sees, averaged over
+// its samples. Unlike the color they are nearly noise free, so they tell
+// the denoiser where the edges are.
+pub struct FeatureBuffers<'a> {
+    pub albedo: &'a Image<RgbF32>,
+    // World space, zero where nothing was hit
+    pub normal: &'a Image<RgbF32>,
+    // Distance along the camera ray, infinite where nothing was hit
+    pub depth: &'a Image<GrayF32>,
+}
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010): a 5×5 joint
+// bilateral filter applied several times with the taps spread twice as far
+// apart each time, so a few passes cover a wide area. Each tap is weighed by
+// how alike the two pixels' colors and features are.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Denoiser {
+    pub iterations: u32,
+    // Larger sigmas smooth more across differences, the color one is halved
+    // every pass as the noise goes down
+    pub sigma_color: f32,
+    pub sigma_albedo: f32,
+    pub sigma_normal: f32,
+    // Relative to the depth, so it works the same near and far
+    pub sigma_depth: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser { iterations: 5, sigma_color: 1.0, sigma_albedo: 0.1, sigma_normal: 0.3, sigma_depth: 0.05 }
+    }
+}
+
+// B3 spline, the usual à-trous kernel
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+fn distance2(a: RgbF32, b: RgbF32) -> f32 {
+    let (dr, dg, db) = (a.r - b.r, a.g - b.g, a.b - b.b);
+    dr * dr + dg * dg + db * db
+}
+
+impl Denoiser {
+    pub fn new() -> Denoiser {
+        Denoiser::default()
+    }
+
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn denoise(&self, color: &Image<RgbF32>, features: &FeatureBuffers) -> Image<RgbF32> {
+        let (width, height) = (color.width(), color.height());
+        assert!(
+            [features.albedo.width(), features.normal.width(), features.depth.width()].iter().all(|&w| w == width)
+                && [features.albedo.height(), features.normal.height(), features.depth.height()]
+                    .iter()
+                    .all(|&h| h == height),
+            "feature buffers must be the size of the image"
+        );
+        // Once the taps are spread as far as the image is wide or high only
+        // the center one is left inside it, and further passes change nothing
+        let reaching = usize::BITS - (width.max(height).max(1) - 1).leading_zeros();
+        let mut current = color.clone();
+        for iteration in 0..self.iterations.min(reaching) {
+            let step = 1i64 << iteration;
+            let sigma_color = self.sigma_color / 2f32.powi(iteration as i32);
+            let source = &current;
+            let mut next = Image::new(width, height);
+            next.par_rows_mut(|row, pixels| {
+                for (col, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = self.filter_pixel(source, features, row, col, step, sigma_color);
+                }
+            });
+            current = next;
+        }
+        current
+    }
+
+    fn filter_pixel(
+        &self,
+        color: &Image<RgbF32>,
+        features: &FeatureBuffers,
+        row: usize,
+        col: usize,
+        step: i64,
+        sigma_color: f32,
+    ) -> RgbF32 {
+        let (width, height) = (color.width() as i64, color.height() as i64);
+        let get = |image: &Image<RgbF32>, row: usize, col: usize| image.get(row, col).unwrap();
+        let center_color = get(color, row, col);
+        let center_albedo = get(features.albedo, row, col);
+        let center_normal = get(features.normal, row, col);
+        let center_depth = features.depth.get(row, col).unwrap().0;
+
+        let mut sum = RgbF32::new(0.0, 0.0, 0.0);
+        let mut total = 0.0;
+        for (i, ky) in KERNEL.iter().enumerate() {
+            let r = row as i64 + (i as i64 - 2) * step;
+            if r < 0 || r >= height {
+                continue;
+            }
+            for (j, kx) in KERNEL.iter().enumerate() {
+                let c = col as i64 + (j as i64 - 2) * step;
+                if c < 0 || c >= width {
+                    continue;
+                }
+                let (r, c) = (r as usize, c as usize);
+                let depth = features.depth.get(r, c).unwrap().0;
+                // Background only mixes with background
+                let depth_weight = match (center_depth.is_finite(), depth.is_finite()) {
+                    (true, true) => {
+                        let relative = (depth - center_depth).abs() / center_depth.max(1e-4);
+                        (-relative / self.sigma_depth).exp()
+                    }
+                    (false, false) => 1.0,
+                    _ => continue,
+                };
+                let sample = get(color, r, c);
+                let weight = ky
+                    * kx
+                    * depth_weight
+                    * (-distance2(sample, center_color) / (sigma_color * sigma_color)).exp()
+                    * (-distance2(get(features.albedo, r, c), center_albedo) / (self.sigma_albedo * self.sigma_albedo)).exp()
+                    * (-distance2(get(features.normal, r, c), center_normal) / (self.sigma_normal * self.sigma_normal)).exp();
+                sum = RgbF32::new(sum.r + sample.r * weight, sum.g + sample.g * weight, sum.b + sample.b * weight);
+                total += weight;
+            }
+        }
+        // The center pixel always has weight, unless it is alone and its
+        // own depth is odd, then it stays as it was
+        if total <= 0.0 {
+            return center_color;
+        }
+        RgbF32::new(sum.r / total, sum.g / total, sum.b / total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_beyond_the_image_change_nothing() {
+        let (width, height) = (5, 3);
+        let color = Image::from_pixels(width, height, (0..15).map(|i| RgbF32::new(i as f32 / 15.0, 0.5, 0.2)).collect())
+            .unwrap();
+        let albedo = Image::from_pixels(width, height, vec![RgbF32::new(0.5, 0.5, 0.5); 15]).unwrap();
+        let normal = Image::from_pixels(width, height, vec![RgbF32::new(0.0, 0.0, 1.0); 15]).unwrap();
+        let depth = Image::from_pixels(width, height, vec![GrayF32(2.0); 15]).unwrap();
+        let features = FeatureBuffers { albedo: &albedo, normal: &normal, depth: &depth };
+
+        // Steps of 1, 2 and 4 reach into a 5 wide image, 8 doesn't
+        let reaching = Denoiser::new().with_iterations(3).denoise(&color, &features);
+        assert_ne!(reaching.pixels(), Denoiser::new().with_iterations(2).denoise(&color, &features).pixels());
+        for iterations in [4, 40, 64, 100, u32::MAX] {
+            let denoised = Denoiser::new().with_iterations(iterations).denoise(&color, &features);
+            assert_eq!(denoised.pixels(), reaching.pixels(), "{} iterations", iterations);
+        }
+
+        let pixel = Image::from_pixels(1, 1, vec![RgbF32::new(0.3, 0.2, 0.1)]).unwrap();
+        let one = |image: &Image<RgbF32>| Image::from_pixels(1, 1, vec![image.pixels()[0]]).unwrap();
+        let (albedo, normal) = (one(&albedo), one(&normal));
+        let depth = Image::from_pixels(1, 1, vec![GrayF32(2.0)]).unwrap();
+        let features = FeatureBuffers { albedo: &albedo, normal: &normal, depth: &depth };
+        assert_eq!(Denoiser::new().with_iterations(70).denoise(&pixel, &features).pixels(), pixel.pixels());
+    }
+}
@@ -0,0 +1,158 @@
+use std::{fs, io::{self, Write}, path::Path};
+
+use byteorder::{WriteBytesExt, LE};
+
+use crate::{
+    color::{GrayF32, Pixel},
+    files::{ImageError, RowOrder},
+    image::Image,
+};
+
+const MAGIC: u32 = 20000630;
+// Single part, scan lines, names up to 31 bytes
+const VERSION: u32 = 2;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+const NO_COMPRESSION: u8 = 0;
+const INCREASING_Y: u8 = 0;
+const MAX_NAME_LENGTH: usize = 31;
+
+// Named float channels of the same size, written together as one OpenEXR
+// file. Layers follow the usual `layer.channel` naming, so compositors show
+// `depth.Z` or `albedo.R` grouped by layer. Values are stored as they are,
+// the way renderers work in linear light.
+#[derive(Clone, Debug)]
+pub struct ExrImage {
+    width: usize,
+    height: usize,
+    row_order: RowOrder,
+    channels: Vec<(String, Vec<f32>)>,
+}
+
+impl ExrImage {
+    pub fn new(width: usize, height: usize) -> ExrImage {
+        ExrImage { width, height, row_order: RowOrder::TopDown, channels: Vec::new() }
+    }
+
+    // Which way the images added are stored, EXR itself is always top down
+    pub fn with_row_order(mut self, row_order: RowOrder) -> Self {
+        self.row_order = row_order;
+        self
+    }
+
+    // The R, G, B and, if the format has one, A channels of `image`, as
+    // `layer.R` and so on, or plain `R` for the unnamed main layer
+    pub fn with_layer<P: Pixel>(mut self, layer: &str, image: &Image<P>) -> Self {
+        let names = &["R", "G", "B", "A"][..if P::CHANNELS == 4 { 4 } else { 3 }];
+        for (i, name) in names.iter().enumerate() {
+            let name = if layer.is_empty() { name.to_string() } else { format!("{}.{}", layer, name) };
+            let values = image.pixels().iter().map(|p| p.to_rgba()[i]).collect();
+            self.add_channel(name, values, image.width(), image.height());
+        }
+        self
+    }
+
+    // A single channel under its full name, like `depth.Z`
+    pub fn with_channel(mut self, name: &str, image: &Image<GrayF32>) -> Self {
+        let values = image.pixels().iter().map(|p| p.0).collect();
+        self.add_channel(name.to_string(), values, image.width(), image.height());
+        self
+    }
+
+    fn add_channel(&mut self, name: String, values: Vec<f32>, width: usize, height: usize) {
+        assert!(width == self.width && height == self.height, "layer `{}` is not the size of the image", name);
+        assert!(!name.is_empty() && name.len() <= MAX_NAME_LENGTH, "invalid channel name `{}`", name);
+        // A layer added again replaces the old one
+        self.channels.retain(|(other, _)| *other != name);
+        self.channels.push((name, values));
+    }
+
+    pub fn channel_names(&self) -> impl Iterator<Item = &str> {
+        self.channels.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+pub fn write_exr_file(path: impl AsRef<Path>, image: &ExrImage) -> Result<(), ImageError> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = io::BufWriter::new(file);
+    write_exr(&mut writer, image)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for text in [name, kind] {
+        header.extend(text.as_bytes());
+        header.push(0);
+    }
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+// Uncompressed, one scan line per chunk, every channel 32 bit float
+pub fn write_exr(mut writer: impl Write, image: &ExrImage) -> Result<(), ImageError> {
+    let (width, height) = (image.width, image.height);
+    if width == 0 || height == 0 || width > i32::MAX as usize || height > i32::MAX as usize {
+        return Err(ImageError::InvalidDimensions { width: width as i64, height: height as i64 });
+    }
+    // Readers expect the channels in alphabetical order, in the list and in
+    // the pixel data
+    let mut channels: Vec<&(String, Vec<f32>)> = image.channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut list = Vec::new();
+    for (name, _) in &channels {
+        list.extend(name.as_bytes());
+        list.push(0);
+        list.extend(PIXEL_TYPE_FLOAT.to_le_bytes());
+        // Not perceptually linear, then three reserved bytes
+        list.extend([0, 0, 0, 0]);
+        // No subsampling
+        list.extend(1i32.to_le_bytes());
+        list.extend(1i32.to_le_bytes());
+    }
+    list.push(0);
+
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+    let mut header = Vec::new();
+    header.extend(MAGIC.to_le_bytes());
+    header.extend(VERSION.to_le_bytes());
+    attribute(&mut header, "channels", "chlist", &list);
+    attribute(&mut header, "compression", "compression", &[NO_COMPRESSION]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[INCREASING_Y]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0.0f32.to_le_bytes(), 0.0f32.to_le_bytes()].concat());
+    attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+    writer.write_all(&header)?;
+
+    // Where each scan line starts in the file
+    let line_size = width * 4 * channels.len();
+    let first_line = header.len() + 8 * height;
+    for y in 0..height {
+        writer.write_u64::<LE>((first_line + y * (8 + line_size)) as u64)?;
+    }
+
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height {
+        let row = match image.row_order {
+            RowOrder::TopDown => y,
+            RowOrder::BottomUp => height - 1 - y,
+        };
+        line.clear();
+        for (_, values) in &channels {
+            for value in &values[row * width..(row + 1) * width] {
+                line.extend(value.to_le_bytes());
+            }
+        }
+        writer.write_i32::<LE>(y as i32)?;
+        writer.write_i32::<LE>(line_size as i32)?;
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
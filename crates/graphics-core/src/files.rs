@@ -0,0 +1,500 @@
+use std::{fmt, fs, io::{self, Read, Write}, path::Path};
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    color::{convert_pixel, Pixel, Rgba8},
+    image::Image,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RowOrder {
+    // The first row of the buffer is the bottom of the image
+    BottomUp,
+    // The first row of the buffer is the top of the image
+    TopDown,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    Bgr24,
+    Bgra32,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Bgr24 => 3,
+            PixelFormat::Bgra32 => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BmpOptions {
+    pub row_order: RowOrder,
+    pub format: PixelFormat,
+    pub dpi: u32,
+}
+
+impl Default for BmpOptions {
+    fn default() -> Self {
+        BmpOptions {
+            row_order: RowOrder::TopDown,
+            format: PixelFormat::Bgr24,
+            dpi: 72,
+        }
+    }
+}
+
+const FILE_HEADER_SIZE: u32 = 14;
+const INFO_HEADER_SIZE: u32 = 40;
+const V4_HEADER_SIZE: u32 = 108;
+const LCS_SRGB: u32 = 0x7352_4742; // 'sRGB'
+
+pub fn write_image_file<P: Pixel>(path: impl AsRef<Path>, image: &Image<P>, options: BmpOptions) -> Result<(), ImageError> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = io::BufWriter::new(file);
+    write_image(&mut writer, image, options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_image<P: Pixel>(writer: impl Write, image: &Image<P>, options: BmpOptions) -> Result<(), ImageError> {
+    let bytes: Vec<u8> = match options.format {
+        PixelFormat::Bgr24 => image
+            .pixels()
+            .iter()
+            .flat_map(|&p| {
+                let c: Rgba8 = convert_pixel(p);
+                [c.b, c.g, c.r]
+            })
+            .collect(),
+        PixelFormat::Bgra32 => image
+            .pixels()
+            .iter()
+            .flat_map(|&p| {
+                let c: Rgba8 = convert_pixel(p);
+                [c.b, c.g, c.r, c.a]
+            })
+            .collect(),
+    };
+    write_bmp(writer, image.width(), image.height(), bytes, options)
+}
+
+// `image_bytes` is tightly packed, `options.format` bytes per pixel with no
+// row padding. Padding is added here as the format requires.
+pub fn write_bmp(
+    mut writer: impl Write,
+    width: usize,
+    height: usize,
+    image_bytes: impl AsRef<[u8]>,
+    options: BmpOptions,
+) -> Result<(), ImageError> {
+    let image_bytes = image_bytes.as_ref();
+    let bytes_per_pixel = options.format.bytes_per_pixel();
+    let row_len = width * bytes_per_pixel;
+    if image_bytes.len() != row_len * height {
+        return Err(ImageError::BufferSizeMismatch {
+            expected: row_len * height,
+            actual: image_bytes.len(),
+        });
+    }
+
+    // Rows are padded to a multiple of 4 bytes
+    let stride = (row_len + 3) & !3;
+    let padding = [0u8; 3];
+
+    let info_header_size = match options.format {
+        PixelFormat::Bgr24 => INFO_HEADER_SIZE,
+        PixelFormat::Bgra32 => V4_HEADER_SIZE,
+    };
+    let content_offset = FILE_HEADER_SIZE + info_header_size;
+
+    let invalid_dimensions = || ImageError::InvalidDimensions { width: width as i64, height: height as i64 };
+    if width == 0 || height == 0 || width > i32::MAX as usize || height > i32::MAX as usize {
+        return Err(invalid_dimensions());
+    }
+    let pixel_len = u32::try_from(stride * height)
+        .ok()
+        .filter(|len| len.checked_add(content_offset).is_some())
+        .ok_or_else(invalid_dimensions)?;
+
+    // A negative height tells readers the rows are stored top-down
+    let stored_height = match options.row_order {
+        RowOrder::BottomUp => height as i32,
+        RowOrder::TopDown => -(height as i32),
+    };
+    let pixels_per_meter = (options.dpi as f64 / 0.0254).round() as i32;
+
+    // File header
+    writer.write_all(b"BM")?;
+    writer.write_u32::<byteorder::LE>(content_offset + pixel_len)?; // file size
+    writer.write_u32::<byteorder::LE>(0)?; // reserved
+    writer.write_u32::<byteorder::LE>(content_offset)?; // content offset
+
+    // Info header
+    writer.write_u32::<byteorder::LE>(info_header_size)?; // info header size
+    writer.write_i32::<byteorder::LE>(width as i32)?; // width
+    writer.write_i32::<byteorder::LE>(stored_height)?; // height
+    writer.write_u16::<byteorder::LE>(1)?; // number of color planes
+    writer.write_u16::<byteorder::LE>(bytes_per_pixel as u16 * 8)?; // bits per pixel
+    let compression = match options.format {
+        PixelFormat::Bgr24 => BI_RGB,
+        PixelFormat::Bgra32 => BI_BITFIELDS,
+    };
+    writer.write_u32::<byteorder::LE>(compression)?; // compression
+    writer.write_u32::<byteorder::LE>(pixel_len)?; // image size
+    writer.write_i32::<byteorder::LE>(pixels_per_meter)?; // horizontal res
+    writer.write_i32::<byteorder::LE>(pixels_per_meter)?; // vertical res
+    writer.write_u32::<byteorder::LE>(0)?; // colors in color table
+    writer.write_u32::<byteorder::LE>(0)?; // important color count
+
+    if options.format == PixelFormat::Bgra32 {
+        // V4 header: channel masks, color space, endpoints and gamma
+        for mask in [0x00ff0000, 0x0000ff00, 0x000000ff, 0xff000000] {
+            writer.write_u32::<byteorder::LE>(mask)?;
+        }
+        writer.write_u32::<byteorder::LE>(LCS_SRGB)?;
+        writer.write_all(&[0u8; 36 + 12])?;
+    }
+
+    for row in image_bytes.chunks_exact(row_len) {
+        writer.write_all(row)?;
+        writer.write_all(&padding[..stride - row_len])?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    Truncated,
+    InvalidSignature([u8; 2]),
+    UnsupportedHeaderSize(u32),
+    UnsupportedBitsPerPixel(u16),
+    UnsupportedCompression(u32),
+    InvalidDimensions { width: i64, height: i64 },
+    InvalidPixelOffset(u32),
+    InvalidPalette { colors: u32, bits_per_pixel: u16 },
+    InvalidPaletteIndex(u8),
+    InvalidBitMask(u32),
+    BufferSizeMismatch { expected: usize, actual: usize },
+    NoFrames,
+    InvalidFrameRate { numerator: u32, denominator: u32 },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(err) => write!(f, "i/o error: {}", err),
+            ImageError::Truncated => write!(f, "image data ends unexpectedly"),
+            ImageError::InvalidSignature(sig) => write!(f, "not a bmp file (signature {:02x?})", sig),
+            ImageError::UnsupportedHeaderSize(size) => write!(f, "unsupported bmp info header size {}", size),
+            ImageError::UnsupportedBitsPerPixel(bpp) => write!(f, "unsupported bits per pixel {}", bpp),
+            ImageError::UnsupportedCompression(c) => write!(f, "unsupported bmp compression {}", c),
+            ImageError::InvalidDimensions { width, height } => write!(f, "invalid image dimensions {}x{}", width, height),
+            ImageError::InvalidPixelOffset(offset) => write!(f, "pixel data offset {} overlaps the headers", offset),
+            ImageError::InvalidPalette { colors, bits_per_pixel } => {
+                write!(f, "palette of {} colors is invalid for {} bits per pixel", colors, bits_per_pixel)
+            }
+            ImageError::InvalidPaletteIndex(index) => write!(f, "palette index {} is out of range", index),
+            ImageError::InvalidBitMask(mask) => write!(f, "bit mask {:#010x} is not contiguous", mask),
+            ImageError::BufferSizeMismatch { expected, actual } => {
+                write!(f, "image buffer is {} bytes but the dimensions need {}", actual, expected)
+            }
+            ImageError::NoFrames => write!(f, "animation has no frames"),
+            ImageError::InvalidFrameRate { numerator, denominator } => {
+                write!(f, "invalid frame rate {}/{}", numerator, denominator)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImageError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            ImageError::Truncated
+        } else {
+            ImageError::Io(err)
+        }
+    }
+}
+
+// Decoded images have the top row first. Files without an alpha channel
+// come back fully opaque.
+pub fn read_image_file(path: impl AsRef<Path>) -> Result<Image<Rgba8>, ImageError> {
+    let file = fs::File::open(path)?;
+    read_bmp(io::BufReader::new(file))
+}
+
+// Largest width and height we accept. The pixels are only allocated as rows
+// are read, so a corrupt header can't ask for gigabytes the file doesn't
+// hold.
+const MAX_DIMENSION: i64 = 1 << 16;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+struct BitMask {
+    mask: u32,
+    shift: u32,
+    bits: u32,
+}
+
+impl BitMask {
+    fn new(mask: u32) -> Result<BitMask, ImageError> {
+        if mask == 0 {
+            return Ok(BitMask { mask, shift: 0, bits: 0 });
+        }
+        let shift = mask.trailing_zeros();
+        let bits = (mask >> shift).trailing_ones();
+        if (mask >> shift) >> bits != 0 {
+            return Err(ImageError::InvalidBitMask(mask));
+        }
+        Ok(BitMask { mask, shift, bits })
+    }
+
+    fn extract(&self, value: u32) -> Option<u8> {
+        if self.bits == 0 {
+            return None;
+        }
+        let v = (value & self.mask) >> self.shift;
+        let max = (1u64 << self.bits) - 1;
+        Some(((v as u64 * 255 + max / 2) / max) as u8)
+    }
+}
+
+pub fn read_bmp(mut reader: impl Read) -> Result<Image<Rgba8>, ImageError> {
+    // File header
+    let mut signature = [0u8; 2];
+    reader.read_exact(&mut signature)?;
+    if &signature != b"BM" {
+        return Err(ImageError::InvalidSignature(signature));
+    }
+    let _file_size = reader.read_u32::<byteorder::LE>()?;
+    let _reserved = reader.read_u32::<byteorder::LE>()?;
+    let pixel_offset = reader.read_u32::<byteorder::LE>()?;
+
+    // Info header
+    let header_size = reader.read_u32::<byteorder::LE>()?;
+    let (width, height, bits_per_pixel, compression, colors_used) = match header_size {
+        12 => {
+            // BITMAPCOREHEADER
+            let width = reader.read_u16::<byteorder::LE>()? as i64;
+            let height = reader.read_u16::<byteorder::LE>()? as i64;
+            let _planes = reader.read_u16::<byteorder::LE>()?;
+            let bits_per_pixel = reader.read_u16::<byteorder::LE>()?;
+            (width, height, bits_per_pixel, BI_RGB, 0)
+        }
+        40 | 52 | 56 | 64 | 108 | 124 => {
+            let width = reader.read_i32::<byteorder::LE>()? as i64;
+            let height = reader.read_i32::<byteorder::LE>()? as i64;
+            let _planes = reader.read_u16::<byteorder::LE>()?;
+            let bits_per_pixel = reader.read_u16::<byteorder::LE>()?;
+            let compression = reader.read_u32::<byteorder::LE>()?;
+            let _image_size = reader.read_u32::<byteorder::LE>()?;
+            let _horizontal_res = reader.read_i32::<byteorder::LE>()?;
+            let _vertical_res = reader.read_i32::<byteorder::LE>()?;
+            let colors_used = reader.read_u32::<byteorder::LE>()?;
+            let _important_colors = reader.read_u32::<byteorder::LE>()?;
+            (width, height, bits_per_pixel, compression, colors_used)
+        }
+        size => return Err(ImageError::UnsupportedHeaderSize(size)),
+    };
+    let mut consumed: u64 = 14 + header_size as u64;
+
+    // A negative height means rows are stored top-down
+    let top_down = height < 0;
+    let height = height.abs();
+    if width <= 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(ImageError::InvalidDimensions { width, height });
+    }
+    let width = width as usize;
+    let height = height as usize;
+
+    match bits_per_pixel {
+        1 | 4 | 8 | 24 | 32 => {}
+        bpp => return Err(ImageError::UnsupportedBitsPerPixel(bpp)),
+    }
+
+    // Channel masks, only meaningful for 32 bit images
+    let mut masks = [0x00ff0000, 0x0000ff00, 0x000000ff, 0xff000000];
+    match compression {
+        BI_RGB => {}
+        BI_BITFIELDS | BI_ALPHABITFIELDS if bits_per_pixel == 32 => {
+            let mask_count = if compression == BI_ALPHABITFIELDS { 4 } else { 3 };
+            if header_size == 40 {
+                // Masks follow the 40 byte header
+                for mask in masks.iter_mut().take(mask_count) {
+                    *mask = reader.read_u32::<byteorder::LE>()?;
+                }
+                if mask_count == 3 {
+                    masks[3] = 0;
+                }
+                consumed += mask_count as u64 * 4;
+            } else {
+                // Masks are part of the larger headers
+                let mut extra = vec![0u8; header_size as usize - 40];
+                reader.read_exact(&mut extra)?;
+                let mut extra = &extra[..];
+                for mask in masks.iter_mut() {
+                    *mask = extra.read_u32::<byteorder::LE>().unwrap_or(0);
+                }
+                if mask_count == 3 && header_size < 56 {
+                    masks[3] = 0;
+                }
+            }
+        }
+        c => return Err(ImageError::UnsupportedCompression(c)),
+    }
+    if header_size > 40 && compression == BI_RGB {
+        let mut extra = vec![0u8; header_size as usize - 40];
+        reader.read_exact(&mut extra)?;
+    }
+
+    // Color table
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    if bits_per_pixel <= 8 {
+        let max_colors = 1u32 << bits_per_pixel;
+        let colors = if colors_used == 0 { max_colors } else { colors_used };
+        if colors > max_colors {
+            return Err(ImageError::InvalidPalette { colors, bits_per_pixel });
+        }
+        let entry_size = if header_size == 12 { 3 } else { 4 };
+        for _ in 0..colors {
+            let mut entry = [0u8; 4];
+            reader.read_exact(&mut entry[..entry_size])?;
+            palette.push([entry[0], entry[1], entry[2]]);
+        }
+        consumed += colors as u64 * entry_size as u64;
+    }
+
+    // Skip anything between the headers and the pixel data
+    if (pixel_offset as u64) < consumed {
+        return Err(ImageError::InvalidPixelOffset(pixel_offset));
+    }
+    io::copy(&mut (&mut reader).take(pixel_offset as u64 - consumed), &mut io::sink())?;
+
+    let bit_masks = [
+        BitMask::new(masks[0])?,
+        BitMask::new(masks[1])?,
+        BitMask::new(masks[2])?,
+        BitMask::new(masks[3])?,
+    ];
+    let has_alpha = bits_per_pixel == 32 && bit_masks[3].bits > 0;
+
+    // Rows are padded to a multiple of 4 bytes
+    let stride = (bits_per_pixel as usize * width).div_ceil(32) * 4;
+    let mut row_bytes = vec![0u8; stride];
+    let mut row = vec![Rgba8::default(); width];
+    let mut pixels = Vec::new();
+
+    for _ in 0..height {
+        reader.read_exact(&mut row_bytes)?;
+
+        for (col, pixel) in row.iter_mut().enumerate() {
+            *pixel = match bits_per_pixel {
+                24 => Rgba8::new(row_bytes[col * 3 + 2], row_bytes[col * 3 + 1], row_bytes[col * 3], 255),
+                32 => {
+                    let value = u32::from_le_bytes([
+                        row_bytes[col * 4],
+                        row_bytes[col * 4 + 1],
+                        row_bytes[col * 4 + 2],
+                        row_bytes[col * 4 + 3],
+                    ]);
+                    Rgba8::new(
+                        bit_masks[0].extract(value).unwrap_or(0),
+                        bit_masks[1].extract(value).unwrap_or(0),
+                        bit_masks[2].extract(value).unwrap_or(0),
+                        bit_masks[3].extract(value).unwrap_or(255),
+                    )
+                }
+                bpp => {
+                    // Palettized, pixels packed from the high bits down
+                    let bpp = bpp as usize;
+                    let bit = col * bpp;
+                    let byte = row_bytes[bit / 8];
+                    let index = (byte >> (8 - bpp - bit % 8)) & ((1u16 << bpp) - 1) as u8;
+                    let [b, g, r] = *palette
+                        .get(index as usize)
+                        .ok_or(ImageError::InvalidPaletteIndex(index))?;
+                    Rgba8::new(r, g, b, 255)
+                }
+            };
+        }
+        pixels.extend_from_slice(&row);
+    }
+    if !top_down {
+        pixels = pixels.chunks_exact(width).rev().flatten().copied().collect();
+    }
+    let mut image = Image::from_pixels(width, height, pixels).unwrap();
+
+    // Plenty of writers fill the alpha byte with zeros, treat that as opaque
+    if has_alpha && image.pixels().iter().all(|pixel| pixel.a == 0) {
+        for pixel in image.pixels_mut() {
+            pixel.a = 255;
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image() -> Image<Rgba8> {
+        let mut image = Image::new(5, 3);
+        for (y, row) in image.rows_mut().enumerate() {
+            for (x, p) in row.iter_mut().enumerate() {
+                *p = Rgba8::new((x * 50) as u8, (y * 100) as u8, 7, 255);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn bmp_round_trips_in_either_row_order() {
+        let image = test_image();
+        for format in [PixelFormat::Bgr24, PixelFormat::Bgra32] {
+            let mut bytes = Vec::new();
+            write_image(&mut bytes, &image, BmpOptions { format, ..Default::default() }).unwrap();
+            assert_eq!(read_bmp(&bytes[..]).unwrap().pixels(), image.pixels());
+
+            // The buffer's first row is the bottom of this one
+            let mut bytes = Vec::new();
+            write_image(&mut bytes, &image, BmpOptions { format, row_order: RowOrder::BottomUp, ..Default::default() }).unwrap();
+            let flipped: Vec<Rgba8> = image.rows().rev().flatten().copied().collect();
+            assert_eq!(read_bmp(&bytes[..]).unwrap().pixels(), flipped);
+        }
+    }
+
+    #[test]
+    fn huge_header_without_pixels_is_truncated() {
+        let mut bytes = Vec::new();
+        let image = Image::filled(1, 1, Rgba8::new(1, 2, 3, 255));
+        write_image(&mut bytes, &image, BmpOptions::default()).unwrap();
+        // Claim 65536 by 65536 pixels, some 17 GB as RGBA, with one pixel of
+        // data behind it
+        bytes[18..22].copy_from_slice(&(MAX_DIMENSION as i32).to_le_bytes());
+        bytes[22..26].copy_from_slice(&(-MAX_DIMENSION as i32).to_le_bytes());
+        assert!(matches!(read_bmp(&bytes[..]), Err(ImageError::Truncated)));
+
+        bytes[18..22].copy_from_slice(&(MAX_DIMENSION as i32 + 1).to_le_bytes());
+        assert!(matches!(read_bmp(&bytes[..]), Err(ImageError::InvalidDimensions { .. })));
+    }
+}
@@ -0,0 +1,242 @@
+use std::{f32::consts::PI, fmt, str::FromStr, sync::Mutex, thread};
+
+use crate::{color::Pixel, image::Image};
+
+// Pixel reconstruction filters: how much a sample counts toward a pixel
+// given its offset from the pixel's center. All are separable.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Filter {
+    // Plain average of the samples inside the pixel
+    #[default]
+    Box,
+    // Linear falloff over one pixel, slightly soft
+    Tent,
+    // Soft, no ringing
+    Gaussian,
+    // Mitchell–Netravali with B = C = 1/3, the usual compromise between
+    // blur and ringing
+    Mitchell,
+    // Windowed sinc with 2 lobes, the sharpest, rings a little at edges
+    Lanczos,
+}
+
+impl Filter {
+    // Half the width of the filter's support, in pixels
+    pub fn radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell | Filter::Lanczos => 2.0,
+        }
+    }
+
+    fn weight_1d(self, x: f32) -> f32 {
+        // Half open so a sample on the edge between two pixels counts once
+        if self == Filter::Box {
+            return if (-0.5..0.5).contains(&x) { 1.0 } else { 0.0 };
+        }
+        let x = x.abs();
+        if x >= self.radius() {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x,
+            Filter::Gaussian => {
+                // Shifted down so it reaches zero at the radius
+                const SIGMA: f32 = 0.5;
+                let gaussian = |x: f32| (-x * x / (2.0 * SIGMA * SIGMA)).exp();
+                gaussian(x) - gaussian(self.radius())
+            }
+            Filter::Mitchell => {
+                const B: f32 = 1.0 / 3.0;
+                const C: f32 = 1.0 / 3.0;
+                let (x2, x3) = (x * x, x * x * x);
+                let value = if x < 1.0 {
+                    (12.0 - 9.0 * B - 6.0 * C) * x3 + (-18.0 + 12.0 * B + 6.0 * C) * x2 + (6.0 - 2.0 * B)
+                } else {
+                    (-B - 6.0 * C) * x3 + (6.0 * B + 30.0 * C) * x2 + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)
+                };
+                value / 6.0
+            }
+            Filter::Lanczos => {
+                let sinc = |x: f32| if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                sinc(x) * sinc(x / self.radius())
+            }
+        }
+    }
+
+    // `dx` and `dy` are the sample's offset from the pixel center
+    pub fn weight(self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFilterError(String);
+
+impl fmt::Display for ParseFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown filter `{}`, expected box, tent, gaussian, mitchell or lanczos", self.0)
+    }
+}
+
+impl std::error::Error for ParseFilterError {}
+
+impl FromStr for Filter {
+    type Err = ParseFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            "lanczos" => Ok(Filter::Lanczos),
+            _ => Err(ParseFilterError(s.to_string())),
+        }
+    }
+}
+
+// Accumulates samples at arbitrary positions. Each sample is splatted onto
+// every pixel within the filter's radius, weighted by the filter, and a
+// pixel's value is its weighted average. Colors are kept as given, in
+// whatever space the renderer works in.
+#[derive(Clone, Debug)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    // Rows first_row..first_row + rows of the image, for the bands
+    // `par_rows` hands out
+    first_row: usize,
+    rows: usize,
+    // Weighted color sums and the weight sum
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Film {
+        Film::band(width, height, filter, 0, height)
+    }
+
+    fn band(width: usize, height: usize, filter: Filter, first_row: usize, rows: usize) -> Film {
+        Film { width, height, filter, first_row, rows, pixels: vec![[0.0; 4]; width * rows] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    // `x` and `y` are in pixel space, x the column and y the row with pixel
+    // centers at +0.5
+    pub fn add_sample(&mut self, x: f32, y: f32, color: [f32; 3]) {
+        // By far the most common case, a box sample lands in one pixel
+        if self.filter == Filter::Box {
+            let (col, row) = (x.floor(), y.floor());
+            if col >= 0.0 && row >= self.first_row as f32 {
+                let (col, row) = (col as usize, row as usize);
+                if col < self.width && row < self.first_row + self.rows {
+                    let pixel = &mut self.pixels[(row - self.first_row) * self.width + col];
+                    *pixel = [pixel[0] + color[0], pixel[1] + color[1], pixel[2] + color[2], pixel[3] + 1.0];
+                }
+            }
+            return;
+        }
+        let radius = self.filter.radius();
+        let last_row = (self.first_row + self.rows) as f32;
+        // Pixels whose centers are within the radius
+        let first_col = (x - 0.5 - radius).ceil().max(0.0) as usize;
+        let end_col = ((x - 0.5 + radius).floor() + 1.0).clamp(0.0, self.width as f32) as usize;
+        let first = (y - 0.5 - radius).ceil().max(self.first_row as f32) as usize;
+        let end = ((y - 0.5 + radius).floor() + 1.0).clamp(self.first_row as f32, last_row) as usize;
+        // Separable, so the weights along each axis are worked out once
+        let mut col_weights = [0.0; 8];
+        let cols = (first_col..end_col).take(col_weights.len());
+        for (weight, col) in col_weights.iter_mut().zip(cols) {
+            *weight = self.filter.weight_1d(x - (col as f32 + 0.5));
+        }
+        for row in first..end {
+            let row_weight = self.filter.weight_1d(y - (row as f32 + 0.5));
+            if row_weight == 0.0 {
+                continue;
+            }
+            let start = (row - self.first_row) * self.width;
+            for (col, &col_weight) in (first_col..end_col).zip(&col_weights) {
+                let weight = row_weight * col_weight;
+                if weight == 0.0 {
+                    continue;
+                }
+                let pixel = &mut self.pixels[start + col];
+                pixel[0] += color[0] * weight;
+                pixel[1] += color[1] * weight;
+                pixel[2] += color[2] * weight;
+                pixel[3] += weight;
+            }
+        }
+    }
+
+    fn merge(&mut self, band: &Film) {
+        let start = (band.first_row - self.first_row) * self.width;
+        for (pixel, other) in self.pixels[start..start + band.pixels.len()].iter_mut().zip(&band.pixels) {
+            for (sum, value) in pixel.iter_mut().zip(other) {
+                *sum += value;
+            }
+        }
+    }
+
+    // Calls `f(row, band)` for every row, spread over all available cores
+    // like `Image::par_rows_mut`. Samples for the row go into `band`, which
+    // also holds the neighbouring rows the filter reaches, and is merged
+    // back once `f` returns.
+    pub fn par_rows<F>(&mut self, f: F)
+    where
+        F: Fn(usize, &mut Film) + Sync,
+    {
+        let reach = self.filter.radius().ceil() as usize;
+        let (width, height, filter) = (self.width, self.height, self.filter);
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let next_row = Mutex::new(self.first_row..self.first_row + self.rows);
+        let film = Mutex::new(self);
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let next = next_row.lock().unwrap().next();
+                    let Some(row) = next else {
+                        break;
+                    };
+                    let first_row = row.saturating_sub(reach);
+                    let rows = (row + reach + 1).min(height) - first_row;
+                    let mut band = Film::band(width, height, filter, first_row, rows);
+                    f(row, &mut band);
+                    film.lock().unwrap().merge(&band);
+                });
+            }
+        });
+    }
+
+    // The weighted averages. Filters with negative lobes can leave a pixel
+    // with no weight or a negative value, those come out black.
+    pub fn develop<P: Pixel>(&self) -> Image<P> {
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|&[r, g, b, weight]| {
+                if weight <= 0.0 {
+                    return P::from_rgba([0.0, 0.0, 0.0, 1.0]);
+                }
+                P::from_rgba([(r / weight).max(0.0), (g / weight).max(0.0), (b / weight).max(0.0), 1.0])
+            })
+            .collect();
+        Image::from_pixels(self.width, self.rows, pixels).unwrap()
+    }
+}
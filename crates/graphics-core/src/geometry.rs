@@ -0,0 +1,148 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+// A 2D point or vector. In pixel space x is the column and y is the row,
+// with pixel centers at half-integer coordinates.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub const fn new(x: f32, y: f32) -> Point {
+        Point { x, y }
+    }
+
+    pub fn dot(self, other: Point) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+    // z component of the 3D cross product
+    pub fn cross(self, other: Point) -> f32 {
+        self.x * other.y - self.y * other.x
+    }
+
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn distance(self, other: Point) -> f32 {
+        (self - other).length()
+    }
+
+    pub fn lerp(self, other: Point, t: f32) -> Point {
+        self + (other - self) * t
+    }
+}
+
+impl Add for Point {
+    type Output = Point;
+
+    fn add(self, other: Point) -> Point {
+        Point::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Point {
+    type Output = Point;
+
+    fn sub(self, other: Point) -> Point {
+        Point::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Mul<f32> for Point {
+    type Output = Point;
+
+    fn mul(self, s: f32) -> Point {
+        Point::new(self.x * s, self.y * s)
+    }
+}
+
+impl Neg for Point {
+    type Output = Point;
+
+    fn neg(self) -> Point {
+        Point::new(-self.x, -self.y)
+    }
+}
+
+// 2D affine transform in the SVG convention: the matrix
+//   | a c e |
+//   | b d f |
+// maps (x, y) to (a x + c y + e, b x + d y + f)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 };
+
+    pub fn translate(tx: f32, ty: f32) -> Transform {
+        Transform { e: tx, f: ty, ..Transform::IDENTITY }
+    }
+
+    pub fn scale(sx: f32, sy: f32) -> Transform {
+        Transform { a: sx, d: sy, ..Transform::IDENTITY }
+    }
+
+    pub fn rotate(radians: f32) -> Transform {
+        let (sin, cos) = radians.sin_cos();
+        Transform { a: cos, b: sin, c: -sin, d: cos, e: 0.0, f: 0.0 }
+    }
+
+    pub fn skew_x(radians: f32) -> Transform {
+        Transform { c: radians.tan(), ..Transform::IDENTITY }
+    }
+
+    pub fn skew_y(radians: f32) -> Transform {
+        Transform { b: radians.tan(), ..Transform::IDENTITY }
+    }
+
+    // The transform that applies `other` first and then `self`
+    pub fn multiply(self, other: Transform) -> Transform {
+        Transform {
+            a: self.a * other.a + self.c * other.b,
+            b: self.b * other.a + self.d * other.b,
+            c: self.a * other.c + self.c * other.d,
+            d: self.b * other.c + self.d * other.d,
+            e: self.a * other.e + self.c * other.f + self.e,
+            f: self.b * other.e + self.d * other.f + self.f,
+        }
+    }
+
+    pub fn apply(&self, p: Point) -> Point {
+        Point::new(self.a * p.x + self.c * p.y + self.e, self.b * p.x + self.d * p.y + self.f)
+    }
+
+    pub fn determinant(&self) -> f32 {
+        self.a * self.d - self.b * self.c
+    }
+
+    // None when the transform collapses the plane onto a line or a point
+    pub fn invert(&self) -> Option<Transform> {
+        let det = self.determinant();
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let (a, b, c, d) = (self.d / det, -self.b / det, -self.c / det, self.a / det);
+        Some(Transform { a, b, c, d, e: -(a * self.e + c * self.f), f: -(b * self.e + d * self.f) })
+    }
+
+    // How much the transform scales lengths, on average over directions
+    pub fn mean_scale(&self) -> f32 {
+        self.determinant().abs().sqrt()
+    }
+}
@@ -0,0 +1,329 @@
+use std::{collections::HashMap, fs, io::{self, Write}, path::Path};
+
+use crate::{
+    color::{Pixel, Rgb8},
+    files::ImageError,
+    image::Image,
+    quantize::{Dither, Palette, MAX_COLORS},
+    sequence::FrameSequence,
+};
+
+const MAX_CODE_SIZE: u32 = 12;
+// Leave each frame in place for the next one to cover
+const DISPOSE_NONE: u8 = 1 << 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GifOptions {
+    // Palette size, 2 to 256
+    pub colors: usize,
+    pub dither: Dither,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        GifOptions { colors: MAX_COLORS, dither: Dither::None }
+    }
+}
+
+pub fn write_gif_file<P: Pixel>(path: impl AsRef<Path>, sequence: &FrameSequence<P>, options: GifOptions) -> Result<(), ImageError> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = io::BufWriter::new(file);
+    write_gif(&mut writer, sequence, options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// GIF89a with one palette for all the frames, so colors don't shift from
+// frame to frame. Delays are rounded to the hundredths of a second GIF
+// counts in.
+pub fn write_gif<P: Pixel>(mut writer: impl Write, sequence: &FrameSequence<P>, options: GifOptions) -> Result<(), ImageError> {
+    let (width, height) = (sequence.width(), sequence.height());
+    if width == 0 || height == 0 || width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(ImageError::InvalidDimensions { width: width as i64, height: height as i64 });
+    }
+    if sequence.frames().is_empty() {
+        return Err(ImageError::NoFrames);
+    }
+    let frames: Vec<Image<Rgb8>> = sequence.frames().iter().map(|(image, _)| image.convert()).collect();
+    let palette = Palette::median_cut(&frames, options.colors.clamp(2, MAX_COLORS));
+    // The color table has a power of two entries, at least two
+    let bits = palette.colors().len().max(2).next_power_of_two().trailing_zeros();
+
+    writer.write_all(b"GIF89a")?;
+    writer.write_all(&(width as u16).to_le_bytes())?;
+    writer.write_all(&(height as u16).to_le_bytes())?;
+    // Global color table, 8 bits a channel, then background color and
+    // aspect ratio
+    writer.write_all(&[0x80 | (7 << 4) | (bits - 1) as u8, 0, 0])?;
+    for i in 0..1 << bits {
+        let c = palette.colors().get(i).copied().unwrap_or_default();
+        writer.write_all(&[c.r, c.g, c.b])?;
+    }
+
+    // The Netscape extension counts the repeats after the first play, and
+    // without it the frames play once
+    if sequence.plays() != 1 {
+        let repeats = sequence.plays().saturating_sub(1).min(u16::MAX as u32) as u16;
+        writer.write_all(&[0x21, 0xff, 11])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[3, 1])?;
+        writer.write_all(&repeats.to_le_bytes())?;
+        writer.write_all(&[0])?;
+    }
+
+    let min_code_size = bits.max(2);
+    for (frame, (_, delay)) in frames.iter().zip(sequence.frames()) {
+        let delay = ((delay.as_millis() + 5) / 10).min(u16::MAX as u128) as u16;
+        writer.write_all(&[0x21, 0xf9, 4, DISPOSE_NONE])?;
+        writer.write_all(&delay.to_le_bytes())?;
+        writer.write_all(&[0, 0])?;
+
+        // The whole screen, using the global color table
+        writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        writer.write_all(&(width as u16).to_le_bytes())?;
+        writer.write_all(&(height as u16).to_le_bytes())?;
+        writer.write_all(&[0])?;
+
+        writer.write_all(&[min_code_size as u8])?;
+        for block in lzw_compress(&palette.index(frame, options.dither), min_code_size).chunks(255) {
+            writer.write_all(&[block.len() as u8])?;
+            writer.write_all(block)?;
+        }
+        writer.write_all(&[0])?;
+    }
+    writer.write_all(&[0x3b])?;
+    Ok(())
+}
+
+// Codes packed from the lowest bit up
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.bits |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+// GIF's variable width LZW: codes start a bit wider than the indices, grow
+// as the table fills up to 12 bits, and the table starts over when full
+fn lzw_compress(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = BitWriter { bytes: Vec::new(), bits: 0, count: 0 };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_code_size + 1;
+    out.write(clear, size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        out.write(end, size);
+        return out.finish();
+    };
+    let mut code = first as u16;
+    for &index in rest {
+        if let Some(&longer) = table.get(&(code, index)) {
+            code = longer;
+            continue;
+        }
+        out.write(code, size);
+        if next == 1 << MAX_CODE_SIZE {
+            out.write(clear, size);
+            table.clear();
+            next = end + 1;
+            size = min_code_size + 1;
+        } else {
+            if next == 1 << size {
+                size += 1;
+            }
+            table.insert((code, index), next);
+            next += 1;
+        }
+        code = index as u16;
+    }
+    out.write(code, size);
+    out.write(end, size);
+    out.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // A plain GIF LZW decoder: it adds an entry for every code after the
+    // first, one code behind the encoder, and widens codes when the next
+    // entry no longer fits
+    fn lzw_decompress(data: &[u8], min_code_size: u32) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            table.clear();
+            table.extend((0..clear).map(|i| vec![i as u8]));
+            table.extend([Vec::new(), Vec::new()]);
+        };
+        reset(&mut table);
+        let mut size = min_code_size + 1;
+        let (mut bits, mut count, mut pos) = (0u32, 0u32, 0);
+        let mut previous: Option<usize> = None;
+        let mut out = Vec::new();
+        loop {
+            while count < size {
+                bits |= (*data.get(pos).expect("stream ends without an end code") as u32) << count;
+                pos += 1;
+                count += 8;
+            }
+            let code = (bits & ((1 << size) - 1)) as usize;
+            bits >>= size;
+            count -= size;
+
+            if code == clear {
+                reset(&mut table);
+                size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (previous, table.get(code)) {
+                (_, Some(entry)) => entry.clone(),
+                // The code being defined right now, its string plus its own
+                // first byte
+                (Some(previous), None) => {
+                    assert_eq!(code, table.len(), "code out of range");
+                    let mut entry = table[previous].clone();
+                    entry.push(entry[0]);
+                    entry
+                }
+                (None, None) => panic!("first code after a clear is not a literal"),
+            };
+            if let Some(previous) = previous {
+                if table.len() < 1 << MAX_CODE_SIZE {
+                    let mut added = table[previous].clone();
+                    added.push(entry[0]);
+                    table.push(added);
+                    if table.len() == 1 << size && size < MAX_CODE_SIZE {
+                        size += 1;
+                    }
+                }
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(code);
+        }
+    }
+
+    fn noise(len: usize, colors: u32) -> Vec<u8> {
+        let mut state = 0x9e37_79b9u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state % colors) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        let cases = [
+            (Vec::new(), 2),
+            (vec![0], 2),
+            // One and two color palettes still use 2 bit codes
+            (vec![0; 10_000], 2),
+            (noise(10_000, 2), 2),
+            (noise(1000, 16), 4),
+            // Enough noise to fill the 4096 entry table and start over
+            // several times
+            (noise(200_000, 256), 8),
+            (noise(100_000, 4), 2),
+            ([0u8, 1, 2, 3].repeat(5000), 2),
+        ];
+        for (indices, min_code_size) in &cases {
+            let compressed = lzw_compress(indices, *min_code_size);
+            assert_eq!(&lzw_decompress(&compressed, *min_code_size), indices, "{} indices", indices.len());
+        }
+    }
+
+    #[test]
+    fn gif_frames_decode_to_their_palette_colors() {
+        let one = Rgb8::new(200, 10, 10);
+        let two = Rgb8::new(10, 10, 200);
+        let flat = Image::filled(7, 5, one);
+        let mut striped = Image::filled(7, 5, one);
+        for row in striped.rows_mut().step_by(2) {
+            row.fill(two);
+        }
+        for (frames, expected_colors) in [(vec![flat.clone()], 1), (vec![flat, striped], 2)] {
+            let mut sequence = FrameSequence::new(7, 5);
+            for frame in &frames {
+                sequence.push(frame.clone(), Duration::from_millis(100));
+            }
+            let mut gif = Vec::new();
+            write_gif(&mut gif, &sequence, GifOptions::default()).unwrap();
+
+            // The global table is the smallest power of two, at least two
+            assert_eq!(gif[10] & 0x07, 0);
+            let table: Vec<Rgb8> = gif[13..19].chunks(3).map(|c| Rgb8::new(c[0], c[1], c[2])).collect();
+            let palette = &table[..expected_colors];
+            assert!(palette.contains(&one));
+            if expected_colors == 2 {
+                assert!(palette.contains(&two));
+            }
+
+            let mut pos = 19;
+            let mut decoded = Vec::new();
+            loop {
+                match gif[pos] {
+                    0x21 => {
+                        pos += 2;
+                        while gif[pos] != 0 {
+                            pos += gif[pos] as usize + 1;
+                        }
+                        pos += 1;
+                    }
+                    0x2c => {
+                        let min_code_size = gif[pos + 10] as u32;
+                        pos += 11;
+                        let mut data = Vec::new();
+                        while gif[pos] != 0 {
+                            data.extend_from_slice(&gif[pos + 1..pos + 1 + gif[pos] as usize]);
+                            pos += gif[pos] as usize + 1;
+                        }
+                        pos += 1;
+                        decoded.push(lzw_decompress(&data, min_code_size));
+                    }
+                    0x3b => break,
+                    block => panic!("unexpected block {:#x}", block),
+                }
+            }
+            assert_eq!(decoded.len(), frames.len());
+            for (indices, frame) in decoded.iter().zip(&frames) {
+                let colors: Vec<Rgb8> = indices.iter().map(|&i| table[i as usize]).collect();
+                assert_eq!(colors, frame.pixels());
+            }
+        }
+    }
+}
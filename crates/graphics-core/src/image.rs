@@ -0,0 +1,273 @@
+use std::{slice, sync::Mutex, thread};
+
+use crate::color::{convert_pixel, Pixel, Rgb8};
+
+// Row-major pixel buffer. Row 0 is the first row in memory, whether that ends
+// up at the top or bottom of a written file is decided by `files::RowOrder`.
+#[derive(Clone, Debug)]
+pub struct Image<P: Pixel = Rgb8> {
+    width: usize,
+    height: usize,
+    pixels: Vec<P>,
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn new(width: usize, height: usize) -> Image<P> {
+        Image::filled(width, height, P::default())
+    }
+
+    pub fn filled(width: usize, height: usize, color: P) -> Image<P> {
+        Image {
+            width,
+            height,
+            pixels: vec![color; width * height],
+        }
+    }
+
+    // Returns `None` if `pixels` doesn't hold exactly `width * height` pixels
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<P>) -> Option<Image<P>> {
+        if pixels.len() != width * height {
+            return None;
+        }
+        Some(Image { width, height, pixels })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<P> {
+        self.index(row, col).map(|i| self.pixels[i])
+    }
+
+    pub fn get_mut(&mut self, row: usize, col: usize) -> Option<&mut P> {
+        self.index(row, col).map(|i| &mut self.pixels[i])
+    }
+
+    // Panics if the pixel is outside the image, like slice indexing
+    pub fn set(&mut self, row: usize, col: usize, color: P) {
+        match self.get_mut(row, col) {
+            Some(pixel) => *pixel = color,
+            None => panic!("pixel ({}, {}) is outside the {}x{} image", row, col, self.width, self.height),
+        }
+    }
+
+    pub fn fill(&mut self, color: P) {
+        self.pixels.fill(color);
+    }
+
+    pub fn pixels(&self) -> &[P] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [P] {
+        &mut self.pixels
+    }
+
+    pub fn into_pixels(self) -> Vec<P> {
+        self.pixels
+    }
+
+    pub fn row(&self, row: usize) -> &[P] {
+        &self.pixels[row * self.width..(row + 1) * self.width]
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut [P] {
+        &mut self.pixels[row * self.width..(row + 1) * self.width]
+    }
+
+    pub fn rows(&self) -> slice::ChunksExact<'_, P> {
+        self.pixels.chunks_exact(self.width.max(1))
+    }
+
+    pub fn rows_mut(&mut self) -> slice::ChunksExactMut<'_, P> {
+        self.pixels.chunks_exact_mut(self.width.max(1))
+    }
+
+    // Splits the image into bands of `rows_per_chunk` rows, each paired with
+    // the index of its first row. The bands are disjoint so they can be handed
+    // to different threads.
+    pub fn row_chunks_mut(&mut self, rows_per_chunk: usize) -> impl Iterator<Item = (usize, &mut [P])> {
+        let rows_per_chunk = rows_per_chunk.max(1);
+        self.pixels
+            .chunks_mut((self.width * rows_per_chunk).max(1))
+            .enumerate()
+            .map(move |(i, chunk)| (i * rows_per_chunk, chunk))
+    }
+
+    // Calls `f(row, pixels)` for every row, spread over all available cores.
+    // Rows are handed out one at a time so slow rows don't stall a thread.
+    pub fn par_rows_mut<F>(&mut self, f: F)
+    where
+        F: Fn(usize, &mut [P]) + Sync,
+    {
+        if self.width == 0 {
+            return;
+        }
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let rows = Mutex::new(self.pixels.chunks_exact_mut(self.width).enumerate());
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let next = rows.lock().unwrap().next();
+                    let Some((row, pixels)) = next else {
+                        break;
+                    };
+                    f(row, pixels);
+                });
+            }
+        });
+    }
+
+    pub fn view(&self, row: usize, col: usize, width: usize, height: usize) -> ImageView<'_, P> {
+        self.check_rect(row, col, width, height);
+        ImageView { image: self, row, col, width, height }
+    }
+
+    pub fn view_mut(&mut self, row: usize, col: usize, width: usize, height: usize) -> ImageViewMut<'_, P> {
+        self.check_rect(row, col, width, height);
+        ImageViewMut { image: self, row, col, width, height }
+    }
+
+    pub fn convert<Q: Pixel>(&self) -> Image<Q> {
+        self.map(convert_pixel)
+    }
+
+    pub fn map<Q: Pixel>(&self, f: impl Fn(P) -> Q) -> Image<Q> {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|&p| f(p)).collect(),
+        }
+    }
+
+    fn index(&self, row: usize, col: usize) -> Option<usize> {
+        if row < self.height && col < self.width {
+            Some(row * self.width + col)
+        } else {
+            None
+        }
+    }
+
+    fn check_rect(&self, row: usize, col: usize, width: usize, height: usize) {
+        assert!(
+            row + height <= self.height && col + width <= self.width,
+            "{}x{} view at ({}, {}) is outside the {}x{} image",
+            width, height, row, col, self.width, self.height
+        );
+    }
+}
+
+// A rectangular window into an image, with its own (0, 0) at the window's
+// first row and column.
+#[derive(Clone, Copy)]
+pub struct ImageView<'a, P: Pixel> {
+    image: &'a Image<P>,
+    row: usize,
+    col: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<'a, P: Pixel> ImageView<'a, P> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<P> {
+        if row < self.height && col < self.width {
+            self.image.get(self.row + row, self.col + col)
+        } else {
+            None
+        }
+    }
+
+    pub fn row(&self, row: usize) -> &'a [P] {
+        assert!(row < self.height, "row {} is outside the view", row);
+        &self.image.row(self.row + row)[self.col..self.col + self.width]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [P]> + '_ {
+        (0..self.height).map(|row| self.row(row))
+    }
+
+    pub fn to_image(&self) -> Image<P> {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.rows().flatten().copied().collect(),
+        }
+    }
+}
+
+pub struct ImageViewMut<'a, P: Pixel> {
+    image: &'a mut Image<P>,
+    row: usize,
+    col: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<P: Pixel> ImageViewMut<'_, P> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<P> {
+        if row < self.height && col < self.width {
+            self.image.get(self.row + row, self.col + col)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, row: usize, col: usize) -> Option<&mut P> {
+        if row < self.height && col < self.width {
+            self.image.get_mut(self.row + row, self.col + col)
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, color: P) {
+        match self.get_mut(row, col) {
+            Some(pixel) => *pixel = color,
+            None => panic!("pixel ({}, {}) is outside the {}x{} view", row, col, self.width, self.height),
+        }
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut [P] {
+        assert!(row < self.height, "row {} is outside the view", row);
+        let (col, width) = (self.col, self.width);
+        &mut self.image.row_mut(self.row + row)[col..col + width]
+    }
+
+    pub fn fill(&mut self, color: P) {
+        for row in 0..self.height {
+            self.row_mut(row).fill(color);
+        }
+    }
+
+    pub fn copy_from(&mut self, source: &ImageView<'_, P>) {
+        assert!(
+            source.width() == self.width && source.height() == self.height,
+            "can't copy a {}x{} view into a {}x{} view",
+            source.width(), source.height(), self.width, self.height
+        );
+        for row in 0..self.height {
+            self.row_mut(row).copy_from_slice(source.row(row));
+        }
+    }
+}
@@ -0,0 +1,28 @@
+pub mod canvas;
+pub mod color;
+pub mod composite;
+pub mod coverage;
+mod deflate;
+pub mod denoise;
+pub mod exr;
+pub mod files;
+pub mod film;
+pub mod geometry;
+pub mod gif;
+pub mod image;
+pub mod mesh;
+pub mod ndc;
+pub mod paint;
+pub mod path;
+pub mod pipeline;
+pub mod png;
+pub mod quantize;
+pub mod raster;
+pub mod sequence;
+pub mod shader;
+pub mod svg;
+pub mod y4m;
+
+pub use color::{Bgr8, Gray8, GrayF32, Pixel, Rgb8, RgbF32, Rgba8};
+pub use geometry::{Point, Transform};
+pub use image::{Image, ImageView, ImageViewMut};
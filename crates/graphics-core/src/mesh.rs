@@ -0,0 +1,91 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+// An indexed triangle mesh. Triangles are wound counter-clockwise when seen
+// from outside in a left-handed, y-up space like the ray tracer's, which is
+// what the pipeline treats as front facing.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    // Texture coordinates, may be empty
+    pub uvs: Vec<Vector2<f32>>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+// One vertex's attributes, the usual input of a vertex shader
+#[derive(Clone, Copy, Debug)]
+pub struct MeshVertex {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub uv: Vector2<f32>,
+}
+
+impl Mesh {
+    // Latitude and longitude tessellation, `rings` bands from pole to pole
+    // and `segments` around the equator
+    pub fn uv_sphere(center: Vector3<f32>, radius: f32, rings: usize, segments: usize) -> Mesh {
+        let (rings, segments) = (rings.max(2), segments.max(3));
+        let mut mesh = Mesh::default();
+        for ring in 0..=rings {
+            let theta = PI * ring as f32 / rings as f32;
+            for segment in 0..=segments {
+                let phi = 2.0 * PI * segment as f32 / segments as f32;
+                let normal = Vector3::new(theta.sin() * phi.cos(), -theta.cos(), theta.sin() * phi.sin());
+                mesh.positions.push(center + normal * radius);
+                mesh.normals.push(normal);
+                // v runs from the top pole to the bottom one
+                mesh.uvs.push(Vector2::new(segment as f32 / segments as f32, 1.0 - ring as f32 / rings as f32));
+            }
+        }
+
+        // The seam and poles repeat vertices so every quad is regular; the
+        // degenerate pole triangles are dropped
+        let stride = segments + 1;
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let a = ring * stride + segment;
+                let (b, c, d) = (a + 1, a + stride, a + stride + 1);
+                if ring != 0 {
+                    mesh.triangles.push([a, b, d]);
+                }
+                if ring != rings - 1 {
+                    mesh.triangles.push([a, d, c]);
+                }
+            }
+        }
+        mesh
+    }
+
+    // The attributes interleaved, missing normals or UVs are zero
+    pub fn vertices(&self) -> Vec<MeshVertex> {
+        let zero2 = Vector2::new(0.0, 0.0);
+        let zero3 = Vector3::new(0.0, 0.0, 0.0);
+        (0..self.positions.len())
+            .map(|i| MeshVertex {
+                position: self.positions[i],
+                normal: self.normals.get(i).copied().unwrap_or(zero3),
+                uv: self.uvs.get(i).copied().unwrap_or(zero2),
+            })
+            .collect()
+    }
+
+    // Smooth normals from the triangles, each weighted by its area
+    pub fn compute_normals(&mut self) {
+        self.normals = vec![Vector3::new(0.0, 0.0, 0.0); self.positions.len()];
+        for &[a, b, c] in &self.triangles {
+            let (pa, pb, pc) = (self.positions[a], self.positions[b], self.positions[c]);
+            // Left-handed, so the winding makes the cross product point inward
+            let normal = (pc - pa).cross(pb - pa);
+            for i in [a, b, c] {
+                self.normals[i] += normal;
+            }
+        }
+        for normal in &mut self.normals {
+            if normal.magnitude2() > 0.0 {
+                *normal = normal.normalize();
+            }
+        }
+    }
+}
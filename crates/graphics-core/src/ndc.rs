@@ -0,0 +1,20 @@
+// Normalized device coordinates: the image spans -1..1 on both axes, with
+// row 0 at y = -1 and column 0 at x = -1.
+pub fn pixel_to_ndc(row: usize, col: usize, width: usize, height: usize) -> (f32, f32) {
+    point_to_ndc(row as f32, col as f32, width, height)
+}
+
+// Like `pixel_to_ndc` for fractional pixel coordinates, (0.5, 0.5) is the
+// center of the first pixel
+pub fn point_to_ndc(row: f32, col: f32, width: usize, height: usize) -> (f32, f32) {
+    let x = (col - width as f32 / 2.0) / (0.5 * width as f32);
+    let y = (row - height as f32 / 2.0) / (0.5 * height as f32);
+    (x, y)
+}
+
+// Inverse of `pixel_to_ndc`, returns fractional pixel coordinates as (row, col)
+pub fn ndc_to_pixel(x: f32, y: f32, width: usize, height: usize) -> (f32, f32) {
+    let col = x * 0.5 * width as f32 + width as f32 / 2.0;
+    let row = y * 0.5 * height as f32 + height as f32 / 2.0;
+    (row, col)
+}
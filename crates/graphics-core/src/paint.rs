@@ -0,0 +1,396 @@
+use std::{f32::consts::PI, fmt, str::FromStr};
+
+use crate::{
+    color::{linear_srgb_to_oklab, oklab_to_linear_srgb, Pixel, Rgba8},
+    composite::PremultipliedRgba,
+    geometry::{Point, Transform},
+};
+
+// What a gradient does past its first and last stop
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Spread {
+    // The end colors continue outward
+    #[default]
+    Pad,
+    // The stops start over
+    Repeat,
+    // The stops run back and forth
+    Reflect,
+}
+
+impl Spread {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Spread::Pad => t.clamp(0.0, 1.0),
+            Spread::Repeat => t - t.floor(),
+            Spread::Reflect => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 {
+                    2.0 - t
+                } else {
+                    t
+                }
+            }
+        }
+    }
+}
+
+// The color space stops are mixed in. Either way colors are premultiplied
+// first, so a stop fading to transparent doesn't drag its neighbour's color
+// toward black.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Interpolation {
+    // Linear light sRGB, physically a blend of the two lights
+    #[default]
+    LinearRgb,
+    // Perceptually even steps, without the muddy middle of some RGB blends
+    Oklab,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSpreadError(String);
+
+impl fmt::Display for ParseSpreadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown spread `{}`, expected pad, repeat or reflect", self.0)
+    }
+}
+
+impl std::error::Error for ParseSpreadError {}
+
+impl FromStr for Spread {
+    type Err = ParseSpreadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pad" => Ok(Spread::Pad),
+            "repeat" => Ok(Spread::Repeat),
+            "reflect" => Ok(Spread::Reflect),
+            _ => Err(ParseSpreadError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseInterpolationError(String);
+
+impl fmt::Display for ParseInterpolationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown interpolation `{}`, expected linear or oklab", self.0)
+    }
+}
+
+impl std::error::Error for ParseInterpolationError {}
+
+impl FromStr for Interpolation {
+    type Err = ParseInterpolationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Interpolation::LinearRgb),
+            "oklab" => Ok(Interpolation::Oklab),
+            _ => Err(ParseInterpolationError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ColorStop {
+    // Position along the gradient, 0.0 at its start and 1.0 at its end
+    pub offset: f32,
+    pub color: Rgba8,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GradientShape {
+    // Stops run from `start` to `end` and are constant across that line
+    Linear { start: Point, end: Point },
+    // Stops run outward from the center to the circle of `radius`
+    Radial { center: Point, radius: f32 },
+    // Stops run once around the center, beginning at `start_angle` radians
+    // and turning from +x toward +y
+    Conic { center: Point, start_angle: f32 },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Gradient {
+    shape: GradientShape,
+    // Sorted by offset; equal offsets keep the order they were added in,
+    // which makes a hard edge
+    stops: Vec<ColorStop>,
+    spread: Spread,
+    interpolation: Interpolation,
+    // Maps the gradient's geometry into the space it is drawn in
+    transform: Transform,
+    inverse: Transform,
+}
+
+impl Gradient {
+    pub fn new(shape: GradientShape) -> Gradient {
+        Gradient {
+            shape,
+            stops: Vec::new(),
+            spread: Spread::Pad,
+            interpolation: Interpolation::LinearRgb,
+            transform: Transform::IDENTITY,
+            inverse: Transform::IDENTITY,
+        }
+    }
+
+    pub fn linear(start: Point, end: Point) -> Gradient {
+        Gradient::new(GradientShape::Linear { start, end })
+    }
+
+    pub fn radial(center: Point, radius: f32) -> Gradient {
+        Gradient::new(GradientShape::Radial { center, radius })
+    }
+
+    pub fn conic(center: Point, start_angle: f32) -> Gradient {
+        Gradient::new(GradientShape::Conic { center, start_angle })
+    }
+
+    // Offsets outside 0..1 are clamped
+    pub fn with_stop(mut self, offset: f32, color: Rgba8) -> Self {
+        self.add_stop(offset, color);
+        self
+    }
+
+    pub fn with_spread(mut self, spread: Spread) -> Self {
+        self.spread = spread;
+        self
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    // A transform that can't be inverted leaves the gradient as it was
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        if let Some(inverse) = transform.invert() {
+            self.transform = transform;
+            self.inverse = inverse;
+        }
+        self
+    }
+
+    pub fn add_stop(&mut self, offset: f32, color: Rgba8) {
+        let offset = if offset.is_nan() { 0.0 } else { offset.clamp(0.0, 1.0) };
+        let index = self.stops.partition_point(|stop| stop.offset <= offset);
+        self.stops.insert(index, ColorStop { offset, color });
+    }
+
+    pub fn shape(&self) -> GradientShape {
+        self.shape
+    }
+
+    pub fn stops(&self) -> &[ColorStop] {
+        &self.stops
+    }
+
+    pub fn spread(&self) -> Spread {
+        self.spread
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    // Position along the gradient before the spread is applied
+    fn offset_at(&self, p: Point) -> f32 {
+        let p = self.inverse.apply(p);
+        match self.shape {
+            GradientShape::Linear { start, end } => {
+                let d = end - start;
+                let length2 = d.dot(d);
+                if length2 == 0.0 {
+                    return 0.0;
+                }
+                (p - start).dot(d) / length2
+            }
+            GradientShape::Radial { center, radius } => {
+                if radius <= 0.0 {
+                    return 1.0;
+                }
+                p.distance(center) / radius
+            }
+            GradientShape::Conic { center, start_angle } => {
+                let d = p - center;
+                ((d.y.atan2(d.x) - start_angle) / (2.0 * PI)).rem_euclid(1.0)
+            }
+        }
+    }
+
+    // Straight color in the interpolation space, premultiplied so mixing
+    // is a plain weighted sum
+    fn premultiplied(&self, color: Rgba8) -> [f32; 4] {
+        let [r, g, b, a] = color.to_linear_rgba();
+        let [x, y, z] = match self.interpolation {
+            Interpolation::LinearRgb => [r, g, b],
+            Interpolation::Oklab => linear_srgb_to_oklab([r, g, b]),
+        };
+        [x * a, y * a, z * a, a]
+    }
+
+    pub fn color_at(&self, p: Point) -> PremultipliedRgba {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return PremultipliedRgba::TRANSPARENT,
+        };
+        let t = self.spread.apply(self.offset_at(p));
+        let mixed = if t <= first.offset {
+            self.premultiplied(first.color)
+        } else if t >= last.offset {
+            self.premultiplied(last.color)
+        } else {
+            let next = self.stops.partition_point(|stop| stop.offset <= t);
+            let (a, b) = (self.stops[next - 1], self.stops[next]);
+            let k = (t - a.offset) / (b.offset - a.offset);
+            let (a, b) = (self.premultiplied(a.color), self.premultiplied(b.color));
+            std::array::from_fn(|i| a[i] + (b[i] - a[i]) * k)
+        };
+        let [x, y, z, a] = mixed;
+        match self.interpolation {
+            Interpolation::LinearRgb => PremultipliedRgba::new(x, y, z, a),
+            Interpolation::Oklab => {
+                if a <= 0.0 {
+                    return PremultipliedRgba::TRANSPARENT;
+                }
+                let [r, g, b] = oklab_to_linear_srgb([x / a, y / a, z / a]);
+                // OKLab mixes of in-gamut colors can land a hair outside it
+                let clamp = |v: f32| v.clamp(0.0, 1.0) * a;
+                PremultipliedRgba::new(clamp(r), clamp(g), clamp(b), a)
+            }
+        }
+    }
+}
+
+// What a shape is filled with
+#[derive(Clone, PartialEq, Debug)]
+pub enum Paint {
+    Solid(Rgba8),
+    Gradient(Gradient),
+}
+
+impl Paint {
+    // The color at a point in the space the shape is drawn in
+    pub fn color_at(&self, p: Point) -> PremultipliedRgba {
+        match self {
+            Paint::Solid(color) => PremultipliedRgba::from_pixel(*color),
+            Paint::Gradient(gradient) => gradient.color_at(p),
+        }
+    }
+}
+
+impl From<Rgba8> for Paint {
+    fn from(color: Rgba8) -> Self {
+        Paint::Solid(color)
+    }
+}
+
+impl From<Gradient> for Paint {
+    fn from(gradient: Gradient) -> Self {
+        Paint::Gradient(gradient)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba8 = Rgba8::new(255, 0, 0, 255);
+    const BLUE: Rgba8 = Rgba8::new(0, 0, 255, 255);
+    const BLACK: Rgba8 = Rgba8::new(0, 0, 0, 255);
+    const WHITE: Rgba8 = Rgba8::new(255, 255, 255, 255);
+
+    fn assert_close(actual: PremultipliedRgba, expected: [f32; 4]) {
+        let a = [actual.r, actual.g, actual.b, actual.a];
+        assert!((0..4).all(|i| (a[i] - expected[i]).abs() < 1e-4), "{:?} against {:?}", actual, expected);
+    }
+
+    // From x = 0 to x = 10
+    fn horizontal() -> Gradient {
+        Gradient::linear(Point::new(0.0, 0.0), Point::new(10.0, 0.0))
+    }
+
+    #[test]
+    fn spread_modes() {
+        let cases = [
+            (Spread::Pad, [(-0.5, 0.0), (0.3, 0.3), (1.5, 1.0)]),
+            (Spread::Repeat, [(1.25, 0.25), (-0.25, 0.75), (-1.0, 0.0)]),
+            (Spread::Reflect, [(1.25, 0.75), (2.25, 0.25), (3.0, 1.0)]),
+        ];
+        for (spread, values) in cases {
+            for (t, expected) in values {
+                assert!((spread.apply(t) - expected).abs() < 1e-6, "{:?} at {}", spread, t);
+            }
+        }
+        // Reflect mirrors about 0, so negative offsets run back the same way
+        for t in [-0.25, -0.9, -1.25, -1.75, -3.5] {
+            assert!((Spread::Reflect.apply(t) - Spread::Reflect.apply(-t)).abs() < 1e-6, "{}", t);
+        }
+        assert!((Spread::Reflect.apply(-1.25) - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn spread_applies_past_the_ends() {
+        let at = |gradient: &Gradient, x: f32| gradient.color_at(Point::new(x, 0.0));
+        let pad = horizontal().with_stop(0.0, RED).with_stop(1.0, BLUE);
+        assert_close(at(&pad, -5.0), [1.0, 0.0, 0.0, 1.0]);
+        assert_close(at(&pad, 15.0), [0.0, 0.0, 1.0, 1.0]);
+        assert_close(at(&pad, 2.5), [0.75, 0.0, 0.25, 1.0]);
+
+        let repeat = pad.clone().with_spread(Spread::Repeat);
+        assert_close(at(&repeat, 12.5), [0.75, 0.0, 0.25, 1.0]);
+        assert_close(at(&repeat, -7.5), [0.75, 0.0, 0.25, 1.0]);
+        let reflect = pad.with_spread(Spread::Reflect);
+        assert_close(at(&reflect, 12.5), [0.25, 0.0, 0.75, 1.0]);
+        assert_close(at(&reflect, -2.5), [0.75, 0.0, 0.25, 1.0]);
+    }
+
+    #[test]
+    fn equal_offsets_make_a_hard_stop() {
+        let gradient = horizontal().with_stop(0.0, RED).with_stop(0.5, RED).with_stop(1.0, BLUE).with_stop(0.5, BLUE);
+        let offsets: Vec<f32> = gradient.stops().iter().map(|stop| stop.offset).collect();
+        assert_eq!(offsets, [0.0, 0.5, 0.5, 1.0]);
+        // The stop added first at 0.5 comes first
+        assert_eq!(gradient.stops()[1].color, RED);
+        assert_eq!(gradient.stops()[2].color, BLUE);
+
+        assert_close(gradient.color_at(Point::new(4.99, 0.0)), [1.0, 0.0, 0.0, 1.0]);
+        assert_close(gradient.color_at(Point::new(5.0, 0.0)), [0.0, 0.0, 1.0, 1.0]);
+        assert_close(gradient.color_at(Point::new(5.01, 0.0)), [0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn interpolation_spaces() {
+        let middle = Point::new(5.0, 0.0);
+        let linear = horizontal().with_stop(0.0, BLACK).with_stop(1.0, WHITE);
+        assert_close(linear.color_at(middle), [0.5, 0.5, 0.5, 1.0]);
+        // Half way in OKLab is half the lightness, which is an eighth of
+        // the light
+        let oklab = linear.with_interpolation(Interpolation::Oklab);
+        assert_close(oklab.color_at(middle), [0.125, 0.125, 0.125, 1.0]);
+
+        // Premultiplied first, so fading out keeps the color instead of
+        // darkening it, in either space
+        for interpolation in [Interpolation::LinearRgb, Interpolation::Oklab] {
+            let fade = horizontal().with_stop(0.0, RED).with_stop(1.0, Rgba8::TRANSPARENT).with_interpolation(interpolation);
+            assert_close(fade.color_at(middle), [0.5, 0.0, 0.0, 0.5]);
+        }
+    }
+
+    #[test]
+    fn shapes() {
+        let radial = Gradient::radial(Point::new(0.0, 0.0), 4.0).with_stop(0.0, BLACK).with_stop(1.0, WHITE);
+        assert_close(radial.color_at(Point::new(0.0, 2.0)), [0.5, 0.5, 0.5, 1.0]);
+        let conic = Gradient::conic(Point::new(0.0, 0.0), 0.0).with_stop(0.0, BLACK).with_stop(1.0, WHITE);
+        // A quarter turn from +x toward +y
+        assert_close(conic.color_at(Point::new(0.0, 3.0)), [0.25, 0.25, 0.25, 1.0]);
+        assert_eq!(Gradient::radial(Point::new(0.0, 0.0), 1.0).color_at(Point::new(0.0, 0.0)), PremultipliedRgba::TRANSPARENT);
+    }
+}
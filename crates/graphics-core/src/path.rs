@@ -0,0 +1,503 @@
+use std::f32::consts::PI;
+
+use crate::{
+    canvas::ellipse_contour,
+    geometry::{Point, Transform},
+};
+
+// How far, in pixels, a flattened curve may stray from the real one
+pub const DEFAULT_TOLERANCE: f32 = 0.1;
+
+// Deepest subdivision when flattening, 2^16 segments per curve at most
+const MAX_SUBDIVISION_DEPTH: u32 = 16;
+
+// Magic number for approximating a quarter circle with one cubic
+const KAPPA: f32 = 0.552_284_8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PathCommand {
+    MoveTo(Point),
+    LineTo(Point),
+    // Control point, end point
+    QuadTo(Point, Point),
+    // Two control points, end point
+    CubicTo(Point, Point, Point),
+    Close,
+}
+
+// A sequence of subpaths built from lines and quadratic and cubic Bezier
+// curves
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Path {
+    commands: Vec<PathCommand>,
+}
+
+// A flattened subpath
+#[derive(Clone, PartialEq, Debug)]
+pub struct Polyline {
+    pub points: Vec<Point>,
+    pub closed: bool,
+}
+
+impl Path {
+    pub fn new() -> Path {
+        Path::default()
+    }
+
+    pub fn commands(&self) -> &[PathCommand] {
+        &self.commands
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn move_to(&mut self, p: Point) -> &mut Self {
+        self.commands.push(PathCommand::MoveTo(p));
+        self
+    }
+
+    pub fn line_to(&mut self, p: Point) -> &mut Self {
+        self.commands.push(PathCommand::LineTo(p));
+        self
+    }
+
+    pub fn quad_to(&mut self, control: Point, end: Point) -> &mut Self {
+        self.commands.push(PathCommand::QuadTo(control, end));
+        self
+    }
+
+    pub fn cubic_to(&mut self, control1: Point, control2: Point, end: Point) -> &mut Self {
+        self.commands.push(PathCommand::CubicTo(control1, control2, end));
+        self
+    }
+
+    // Elliptical arc from the current point to `end` with SVG's endpoint
+    // parameterization, added as cubic curves of at most a quarter turn each
+    pub fn arc_to(&mut self, rx: f32, ry: f32, x_rotation: f32, large_arc: bool, sweep: bool, end: Point) -> &mut Self {
+        let start = self.current_point();
+        if start == end {
+            return self;
+        }
+        let (mut rx, mut ry) = (rx.abs(), ry.abs());
+        if rx == 0.0 || ry == 0.0 {
+            return self.line_to(end);
+        }
+
+        // See the SVG spec's implementation notes, "conversion from endpoint
+        // to center parameterization"
+        let (sin_phi, cos_phi) = x_rotation.sin_cos();
+        let half = (start - end) * 0.5;
+        let x1 = cos_phi * half.x + sin_phi * half.y;
+        let y1 = -sin_phi * half.x + cos_phi * half.y;
+
+        // Radii too small to reach the end point are scaled up
+        let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+        if lambda > 1.0 {
+            rx *= lambda.sqrt();
+            ry *= lambda.sqrt();
+        }
+
+        let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+        let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+        let mut factor = (numerator / denominator).max(0.0).sqrt();
+        if large_arc == sweep {
+            factor = -factor;
+        }
+        let cx1 = factor * rx * y1 / ry;
+        let cy1 = -factor * ry * x1 / rx;
+        let mid = (start + end) * 0.5;
+        let center = Point::new(cos_phi * cx1 - sin_phi * cy1 + mid.x, sin_phi * cx1 + cos_phi * cy1 + mid.y);
+
+        let angle = |u: Point, v: Point| {
+            let a = (u.dot(v) / (u.length() * v.length())).clamp(-1.0, 1.0).acos();
+            if u.cross(v) < 0.0 { -a } else { a }
+        };
+        let u = Point::new((x1 - cx1) / rx, (y1 - cy1) / ry);
+        let v = Point::new((-x1 - cx1) / rx, (-y1 - cy1) / ry);
+        let theta1 = angle(Point::new(1.0, 0.0), u);
+        let mut delta = angle(u, v);
+        if !sweep && delta > 0.0 {
+            delta -= 2.0 * PI;
+        } else if sweep && delta < 0.0 {
+            delta += 2.0 * PI;
+        }
+
+        let segments = (delta.abs() / (PI / 2.0)).ceil().max(1.0) as usize;
+        let step = delta / segments as f32;
+        // Control point distance for a cubic approximating `step` radians
+        let k = 4.0 / 3.0 * (step / 4.0).tan();
+        let point_at = |theta: f32| {
+            let (sin, cos) = theta.sin_cos();
+            let (x, y) = (rx * cos, ry * sin);
+            Point::new(cos_phi * x - sin_phi * y + center.x, sin_phi * x + cos_phi * y + center.y)
+        };
+        let derivative_at = |theta: f32| {
+            let (sin, cos) = theta.sin_cos();
+            let (x, y) = (-rx * sin, ry * cos);
+            Point::new(cos_phi * x - sin_phi * y, sin_phi * x + cos_phi * y)
+        };
+        for i in 0..segments {
+            let t0 = theta1 + step * i as f32;
+            let t1 = t0 + step;
+            let p0 = point_at(t0);
+            let p1 = if i + 1 == segments { end } else { point_at(t1) };
+            self.cubic_to(p0 + derivative_at(t0) * k, p1 - derivative_at(t1) * k, p1);
+        }
+        self
+    }
+
+    // Where the next command starts: the end of the last one, or the start
+    // of the subpath after a close
+    pub fn current_point(&self) -> Point {
+        let mut closed = false;
+        for &command in self.commands.iter().rev() {
+            match command {
+                PathCommand::MoveTo(p) => return p,
+                PathCommand::LineTo(p) | PathCommand::QuadTo(_, p) | PathCommand::CubicTo(_, _, p) if !closed => {
+                    return p
+                }
+                PathCommand::Close => closed = true,
+                _ => {}
+            }
+        }
+        Point::default()
+    }
+
+    pub fn close(&mut self) -> &mut Self {
+        self.commands.push(PathCommand::Close);
+        self
+    }
+
+    pub fn rect(min: Point, max: Point) -> Path {
+        let mut path = Path::new();
+        path.move_to(min)
+            .line_to(Point::new(max.x, min.y))
+            .line_to(max)
+            .line_to(Point::new(min.x, max.y))
+            .close();
+        path
+    }
+
+    pub fn polygon(points: &[Point]) -> Path {
+        let mut path = Path::new();
+        for (i, &p) in points.iter().enumerate() {
+            if i == 0 {
+                path.move_to(p);
+            } else {
+                path.line_to(p);
+            }
+        }
+        if !points.is_empty() {
+            path.close();
+        }
+        path
+    }
+
+    // Four cubic arcs, within 0.03% of the true ellipse
+    pub fn ellipse(center: Point, rx: f32, ry: f32) -> Path {
+        let (kx, ky) = (rx * KAPPA, ry * KAPPA);
+        let c = center;
+        let mut path = Path::new();
+        path.move_to(Point::new(c.x + rx, c.y))
+            .cubic_to(Point::new(c.x + rx, c.y + ky), Point::new(c.x + kx, c.y + ry), Point::new(c.x, c.y + ry))
+            .cubic_to(Point::new(c.x - kx, c.y + ry), Point::new(c.x - rx, c.y + ky), Point::new(c.x - rx, c.y))
+            .cubic_to(Point::new(c.x - rx, c.y - ky), Point::new(c.x - kx, c.y - ry), Point::new(c.x, c.y - ry))
+            .cubic_to(Point::new(c.x + kx, c.y - ry), Point::new(c.x + rx, c.y - ky), Point::new(c.x + rx, c.y))
+            .close();
+        path
+    }
+
+    // Applies `f` to every point. Bezier curves are invariant under affine
+    // maps, so this is exact for translations, rotations, scales and skews.
+    pub fn map_points(&self, f: impl Fn(Point) -> Point) -> Path {
+        let commands = self
+            .commands
+            .iter()
+            .map(|&command| match command {
+                PathCommand::MoveTo(p) => PathCommand::MoveTo(f(p)),
+                PathCommand::LineTo(p) => PathCommand::LineTo(f(p)),
+                PathCommand::QuadTo(c, p) => PathCommand::QuadTo(f(c), f(p)),
+                PathCommand::CubicTo(c1, c2, p) => PathCommand::CubicTo(f(c1), f(c2), f(p)),
+                PathCommand::Close => PathCommand::Close,
+            })
+            .collect();
+        Path { commands }
+    }
+
+    pub fn transform(&self, transform: &Transform) -> Path {
+        self.map_points(|p| transform.apply(p))
+    }
+
+    // Turns every curve into line segments, subdividing each one until it
+    // is within `tolerance` of the real curve
+    pub fn flatten(&self, tolerance: f32) -> Vec<Polyline> {
+        let tolerance = tolerance.max(1e-4);
+        let mut polylines = Vec::new();
+        let mut current: Vec<Point> = Vec::new();
+        let mut start = Point::default();
+
+        let finish = |points: &mut Vec<Point>, closed: bool, polylines: &mut Vec<Polyline>| {
+            if points.len() > 1 || (closed && !points.is_empty()) {
+                polylines.push(Polyline { points: std::mem::take(points), closed });
+            }
+            points.clear();
+        };
+
+        for &command in &self.commands {
+            let last = current.last().copied().unwrap_or(start);
+            match command {
+                PathCommand::MoveTo(p) => {
+                    finish(&mut current, false, &mut polylines);
+                    start = p;
+                    current.push(p);
+                }
+                PathCommand::LineTo(p) => {
+                    if current.is_empty() {
+                        current.push(last);
+                    }
+                    current.push(p);
+                }
+                PathCommand::QuadTo(c, p) => {
+                    if current.is_empty() {
+                        current.push(last);
+                    }
+                    // Degree elevation, a quadratic is a special cubic
+                    let c1 = last + (c - last) * (2.0 / 3.0);
+                    let c2 = p + (c - p) * (2.0 / 3.0);
+                    flatten_cubic([last, c1, c2, p], tolerance, 0, &mut current);
+                }
+                PathCommand::CubicTo(c1, c2, p) => {
+                    if current.is_empty() {
+                        current.push(last);
+                    }
+                    flatten_cubic([last, c1, c2, p], tolerance, 0, &mut current);
+                }
+                PathCommand::Close => {
+                    finish(&mut current, true, &mut polylines);
+                    // A new subpath without a move starts where this one did
+                    current.clear();
+                }
+            }
+        }
+        finish(&mut current, false, &mut polylines);
+        polylines
+    }
+}
+
+// Pushes the points after p[0], splitting in half with de Casteljau's
+// algorithm while the control points are further than `tolerance` from the
+// chord
+fn flatten_cubic(p: [Point; 4], tolerance: f32, depth: u32, out: &mut Vec<Point>) {
+    let flat = distance_to_line(p[1], p[0], p[3]).max(distance_to_line(p[2], p[0], p[3]));
+    // The curve stays within 3/4 of its control polygon's distance
+    if flat * 0.75 <= tolerance || depth >= MAX_SUBDIVISION_DEPTH {
+        out.push(p[3]);
+        return;
+    }
+    let p01 = p[0].lerp(p[1], 0.5);
+    let p12 = p[1].lerp(p[2], 0.5);
+    let p23 = p[2].lerp(p[3], 0.5);
+    let p012 = p01.lerp(p12, 0.5);
+    let p123 = p12.lerp(p23, 0.5);
+    let mid = p012.lerp(p123, 0.5);
+    flatten_cubic([p[0], p01, p012, mid], tolerance, depth + 1, out);
+    flatten_cubic([mid, p123, p23, p[3]], tolerance, depth + 1, out);
+}
+
+fn distance_to_line(p: Point, a: Point, b: Point) -> f32 {
+    let d = b - a;
+    let length = d.length();
+    if length == 0.0 {
+        return p.distance(a);
+    }
+    (d.cross(p - a) / length).abs()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineJoin {
+    // Sharp corners, falling back to bevel past the stroke's miter limit
+    Miter,
+    Round,
+    Bevel,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineCap {
+    Butt,
+    Round,
+    // Like butt, extended by half the stroke width
+    Square,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StrokeStyle {
+    pub width: f32,
+    pub join: LineJoin,
+    pub cap: LineCap,
+    // Longest miter allowed, as a multiple of the stroke width
+    pub miter_limit: f32,
+}
+
+impl Default for StrokeStyle {
+    fn default() -> Self {
+        StrokeStyle {
+            width: 1.0,
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+            miter_limit: 4.0,
+        }
+    }
+}
+
+impl StrokeStyle {
+    pub fn new(width: f32) -> StrokeStyle {
+        StrokeStyle { width, ..Default::default() }
+    }
+
+    pub fn with_join(mut self, join: LineJoin) -> Self {
+        self.join = join;
+        self
+    }
+
+    pub fn with_cap(mut self, cap: LineCap) -> Self {
+        self.cap = cap;
+        self
+    }
+}
+
+// Outlines the stroke of flattened subpaths as a set of contours, all wound
+// the same way, so filling them with `FillRule::NonZero` paints the stroke
+pub fn stroke_polylines(polylines: &[Polyline], style: &StrokeStyle) -> Vec<Vec<Point>> {
+    let half = style.width * 0.5;
+    let mut contours = Vec::new();
+    if half <= 0.0 {
+        return contours;
+    }
+
+    for polyline in polylines {
+        let mut points = polyline.points.clone();
+        points.dedup();
+        if polyline.closed && points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        if points.len() == 1 {
+            // A zero length subpath only shows up as its caps
+            match style.cap {
+                LineCap::Butt => {}
+                LineCap::Round => contours.push(circle_contour(points[0], half)),
+                LineCap::Square => {
+                    let (p, h) = (points[0], Point::new(half, half));
+                    contours.push(vec![p - h, Point::new(p.x + half, p.y - half), p + h, Point::new(p.x - half, p.y + half)]);
+                }
+            }
+            continue;
+        }
+        if points.len() < 2 {
+            continue;
+        }
+
+        let n = points.len();
+        let segment_count = if polyline.closed { n } else { n - 1 };
+        for i in 0..segment_count {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            let d = direction(a, b);
+            let mut a = a;
+            let mut b = b;
+            if !polyline.closed && style.cap == LineCap::Square {
+                if i == 0 {
+                    a = a - d * half;
+                }
+                if i == segment_count - 1 {
+                    b = b + d * half;
+                }
+            }
+            let normal = perpendicular(d) * half;
+            contours.push(vec![a + normal, b + normal, b - normal, a - normal]);
+        }
+
+        // Joins between consecutive segments
+        let joints: Vec<usize> = if polyline.closed { (0..n).collect() } else { (1..n - 1).collect() };
+        for i in joints {
+            let prev = points[(i + n - 1) % n];
+            let p = points[i];
+            let next = points[(i + 1) % n];
+            if let Some(join) = join_contour(prev, p, next, half, style) {
+                contours.push(join);
+            }
+        }
+
+        if !polyline.closed && style.cap == LineCap::Round {
+            contours.push(circle_contour(points[0], half));
+            contours.push(circle_contour(points[n - 1], half));
+        }
+    }
+
+    // Same orientation everywhere so the pieces union under nonzero
+    for contour in &mut contours {
+        if signed_area(contour) < 0.0 {
+            contour.reverse();
+        }
+    }
+    contours
+}
+
+fn join_contour(prev: Point, p: Point, next: Point, half: f32, style: &StrokeStyle) -> Option<Vec<Point>> {
+    let d0 = direction(prev, p);
+    let d1 = direction(p, next);
+    let turn = d0.cross(d1);
+    if turn.abs() < 1e-6 && d0.dot(d1) > 0.0 {
+        // Straight through, the segment quads already meet
+        return None;
+    }
+    if style.join == LineJoin::Round {
+        return Some(circle_contour(p, half));
+    }
+
+    // The gap opens on the outside of the turn
+    let side = if turn > 0.0 { -1.0 } else { 1.0 };
+    let n0 = perpendicular(d0) * (half * side);
+    let n1 = perpendicular(d1) * (half * side);
+
+    if style.join == LineJoin::Miter {
+        let bisector = n0 + n1;
+        let bisector_length = bisector.length();
+        if bisector_length > 1e-6 {
+            // cos of half the angle between the segment normals
+            let cos_half = bisector_length / (2.0 * half);
+            let miter_length = half / cos_half;
+            if miter_length * 2.0 <= style.miter_limit * style.width {
+                let tip = p + bisector * (miter_length / bisector_length);
+                return Some(vec![p, p + n0, tip, p + n1]);
+            }
+        }
+    }
+    Some(vec![p, p + n0, p + n1])
+}
+
+fn direction(a: Point, b: Point) -> Point {
+    let d = b - a;
+    let length = d.length();
+    if length == 0.0 {
+        Point::new(1.0, 0.0)
+    } else {
+        d * (1.0 / length)
+    }
+}
+
+fn perpendicular(d: Point) -> Point {
+    Point::new(-d.y, d.x)
+}
+
+fn circle_contour(center: Point, radius: f32) -> Vec<Point> {
+    ellipse_contour(center, radius, radius)
+}
+
+fn signed_area(contour: &[Point]) -> f32 {
+    contour
+        .iter()
+        .zip(contour.iter().cycle().skip(1))
+        .map(|(a, b)| a.cross(*b))
+        .sum::<f32>()
+        * 0.5
+}
@@ -0,0 +1,383 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::{
+    color::{convert_pixel, Pixel},
+    geometry::Point,
+    image::Image,
+    mesh::Mesh,
+    ndc::ndc_to_pixel,
+    raster::{rasterize_interpolated, InterpolatedFragment, Interpolate, RasterVertex},
+    shader::{FragmentShader, VertexShader},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CullMode {
+    None,
+    // Skips triangles wound clockwise on screen
+    Back,
+    // Skips triangles wound counter-clockwise on screen
+    Front,
+}
+
+// Per-pixel depth, 0.0 at the near plane and 1.0 at the far plane
+#[derive(Clone, Debug)]
+pub struct DepthBuffer {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl DepthBuffer {
+    pub fn new(width: usize, height: usize) -> DepthBuffer {
+        DepthBuffer { width, height, values: vec![1.0; width * height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn clear(&mut self) {
+        self.values.fill(1.0);
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<f32> {
+        if row < self.height && col < self.width {
+            Some(self.values[row * self.width + col])
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, depth: f32) {
+        assert!(row < self.height && col < self.width, "depth ({}, {}) is outside the buffer", row, col);
+        self.values[row * self.width + col] = depth;
+    }
+}
+
+// A color image with a depth buffer of the same size
+pub struct Framebuffer<P: Pixel> {
+    pub color: Image<P>,
+    pub depth: DepthBuffer,
+}
+
+impl<P: Pixel> Framebuffer<P> {
+    pub fn new(width: usize, height: usize) -> Framebuffer<P> {
+        Framebuffer { color: Image::new(width, height), depth: DepthBuffer::new(width, height) }
+    }
+
+    pub fn clear(&mut self, color: P) {
+        self.color.fill(color);
+        self.depth.clear();
+    }
+}
+
+// A vertex after the vertex transform, in homogeneous clip space
+#[derive(Clone, Copy, Debug)]
+struct ClipVertex<V> {
+    position: Vector4<f32>,
+    varying: V,
+}
+
+// Signed distances to the six frustum planes, -w <= x, y, z <= w,
+// non-negative inside
+fn plane_distances(p: Vector4<f32>) -> [f32; 6] {
+    [p.w + p.x, p.w - p.x, p.w + p.y, p.w - p.y, p.w + p.z, p.w - p.z]
+}
+
+fn lerp_vertex<V: Interpolate>(a: &ClipVertex<V>, b: &ClipVertex<V>, t: f32) -> ClipVertex<V> {
+    ClipVertex {
+        position: a.position + (b.position - a.position) * t,
+        varying: V::interpolate([a.varying, b.varying, a.varying], [1.0 - t, t, 0.0]),
+    }
+}
+
+// Sutherland–Hodgman against each plane in turn. Clip space is linear in
+// the original triangle, so varyings are interpolated before the divide.
+fn clip_polygon<V: Interpolate>(triangle: [ClipVertex<V>; 3]) -> Vec<ClipVertex<V>> {
+    let mut polygon = triangle.to_vec();
+    let mut next = Vec::with_capacity(9);
+    for plane in 0..6 {
+        next.clear();
+        for i in 0..polygon.len() {
+            let a = &polygon[i];
+            let b = &polygon[(i + 1) % polygon.len()];
+            let da = plane_distances(a.position)[plane];
+            let db = plane_distances(b.position)[plane];
+            if da >= 0.0 {
+                next.push(*a);
+            }
+            if (da >= 0.0) != (db >= 0.0) {
+                next.push(lerp_vertex(a, b, da / (da - db)));
+            }
+        }
+        std::mem::swap(&mut polygon, &mut next);
+        if polygon.is_empty() {
+            break;
+        }
+    }
+    polygon
+}
+
+// Vertex transform, frustum clipping, perspective divide, viewport mapping,
+// back-face culling and the depth test, in that order. Projections follow
+// the OpenGL conventions: clip space z runs from -w to w, and the viewport
+// puts NDC y = -1 on row 0 like `ndc::ndc_to_pixel`.
+#[derive(Clone, Copy, Debug)]
+pub struct Pipeline {
+    pub model: Matrix4<f32>,
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+    pub cull_mode: CullMode,
+    pub depth_test: bool,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            model: Matrix4::identity(),
+            view: Matrix4::identity(),
+            projection: Matrix4::identity(),
+            cull_mode: CullMode::Back,
+            depth_test: true,
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    pub fn with_model(mut self, model: Matrix4<f32>) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn with_view(mut self, view: Matrix4<f32>) -> Self {
+        self.view = view;
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Matrix4<f32>) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn with_depth_test(mut self, depth_test: bool) -> Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    pub fn model_view_projection(&self) -> Matrix4<f32> {
+        self.projection * self.view * self.model
+    }
+
+    // Inverse transpose of the model matrix, which keeps normals
+    // perpendicular to surfaces under a non-uniform scale
+    pub fn normal_matrix(&self) -> Matrix4<f32> {
+        self.model.invert().map_or(self.model, |inverse| inverse.transpose())
+    }
+
+    // Draws indexed triangles with programmable shaders. The vertex shader
+    // does its own transforms, so the pipeline's matrices are not applied;
+    // clipping, culling and the depth test are.
+    pub fn draw_shaded<P: Pixel, VS: VertexShader + ?Sized, FS: FragmentShader<VS::Varying> + ?Sized>(
+        &self,
+        target: &mut Framebuffer<P>,
+        vertex_shader: &VS,
+        fragment_shader: &FS,
+        vertices: &[VS::Input],
+        triangles: &[[usize; 3]],
+    ) {
+        let clip: Vec<ClipVertex<VS::Varying>> = vertices
+            .iter()
+            .map(|input| {
+                let (position, varying) = vertex_shader.vertex(input);
+                ClipVertex { position, varying }
+            })
+            .collect();
+        let mut shade = |fragment: &InterpolatedFragment<VS::Varying>| fragment_shader.fragment(fragment).map(convert_pixel);
+        for &[a, b, c] in triangles {
+            self.draw_clipped(target, [clip[a], clip[b], clip[c]], &mut shade);
+        }
+    }
+
+    // Draws indexed triangles. `varyings` holds one value per position,
+    // interpolated perspective-correctly for `shade`, which returns the
+    // fragment's color or None to discard it.
+    pub fn draw<P: Pixel, V: Interpolate>(
+        &self,
+        target: &mut Framebuffer<P>,
+        positions: &[Vector3<f32>],
+        varyings: &[V],
+        triangles: &[[usize; 3]],
+        mut shade: impl FnMut(&InterpolatedFragment<V>) -> Option<P>,
+    ) {
+        let mvp = self.model_view_projection();
+        let clip: Vec<Vector4<f32>> = positions.iter().map(|p| mvp * p.extend(1.0)).collect();
+        for &[a, b, c] in triangles {
+            let triangle = [a, b, c].map(|i| ClipVertex { position: clip[i], varying: varyings[i] });
+            self.draw_clipped(target, triangle, &mut shade);
+        }
+    }
+
+    // Draws a mesh with world space positions and normals as the varyings.
+    // A mesh without a normal for every position is drawn flat, each
+    // triangle with its own normal.
+    pub fn draw_mesh<P: Pixel>(
+        &self,
+        target: &mut Framebuffer<P>,
+        mesh: &Mesh,
+        shade: impl FnMut(&InterpolatedFragment<(Vector3<f32>, Vector3<f32>)>) -> Option<P>,
+    ) {
+        let normal_matrix = self.normal_matrix();
+        let to_world = |p: Vector3<f32>, n: Vector3<f32>| {
+            let normal = (normal_matrix * n.extend(0.0)).truncate();
+            // A zero normal stays zero instead of turning into NaN
+            let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { normal };
+            ((self.model * p.extend(1.0)).truncate(), normal)
+        };
+        if mesh.normals.len() == mesh.positions.len() {
+            let world: Vec<(Vector3<f32>, Vector3<f32>)> =
+                mesh.positions.iter().zip(&mesh.normals).map(|(&p, &n)| to_world(p, n)).collect();
+            self.draw(target, &mesh.positions, &world, &mesh.triangles, shade);
+            return;
+        }
+
+        let mut positions = Vec::with_capacity(mesh.triangles.len() * 3);
+        let mut world = Vec::with_capacity(mesh.triangles.len() * 3);
+        for &[a, b, c] in &mesh.triangles {
+            let (pa, pb, pc) = (mesh.positions[a], mesh.positions[b], mesh.positions[c]);
+            // The same winding as Mesh::compute_normals
+            let normal = (pc - pa).cross(pb - pa);
+            for p in [pa, pb, pc] {
+                positions.push(p);
+                world.push(to_world(p, normal));
+            }
+        }
+        let triangles: Vec<[usize; 3]> = (0..mesh.triangles.len()).map(|t| [3 * t, 3 * t + 1, 3 * t + 2]).collect();
+        self.draw(target, &positions, &world, &triangles, shade);
+    }
+
+    fn draw_clipped<P: Pixel, V: Interpolate>(
+        &self,
+        target: &mut Framebuffer<P>,
+        triangle: [ClipVertex<V>; 3],
+        shade: &mut impl FnMut(&InterpolatedFragment<V>) -> Option<P>,
+    ) {
+        let distances = triangle.map(|v| plane_distances(v.position));
+        // Entirely outside one plane, nothing to draw
+        if (0..6).any(|plane| distances.iter().all(|d| d[plane] < 0.0)) {
+            return;
+        }
+        let polygon = if distances.iter().all(|d| d.iter().all(|&v| v >= 0.0)) {
+            triangle.to_vec()
+        } else {
+            clip_polygon(triangle)
+        };
+        if polygon.len() < 3 {
+            return;
+        }
+
+        let (width, height) = (target.color.width(), target.color.height());
+        let projected: Vec<RasterVertex<V>> = polygon
+            .iter()
+            .map(|v| {
+                let ndc = v.position.truncate() / v.position.w;
+                let (row, col) = ndc_to_pixel(ndc.x, ndc.y, width, height);
+                RasterVertex {
+                    position: Point::new(col, row),
+                    depth: ndc.z * 0.5 + 0.5,
+                    w: v.position.w,
+                    varying: v.varying,
+                }
+            })
+            .collect();
+
+        // Rows go up with NDC y, so a positive area is counter-clockwise as
+        // seen in the final image
+        let area: f32 = projected
+            .iter()
+            .zip(projected.iter().cycle().skip(1))
+            .map(|(a, b)| a.position.cross(b.position))
+            .sum();
+        let culled = match self.cull_mode {
+            CullMode::None => false,
+            CullMode::Back => area <= 0.0,
+            CullMode::Front => area >= 0.0,
+        };
+        if culled {
+            return;
+        }
+
+        // The clipped polygon is convex, a fan covers it
+        for i in 1..projected.len() - 1 {
+            let vertices = [projected[0], projected[i], projected[i + 1]];
+            rasterize_interpolated(vertices, width, height, |fragment| {
+                let (row, col) = (fragment.row, fragment.col);
+                if self.depth_test && fragment.depth >= target.depth.get(row, col).unwrap() {
+                    return;
+                }
+                // Discarded fragments leave the depth alone too
+                if let Some(color) = shade(&fragment) {
+                    if self.depth_test {
+                        target.depth.set(row, col, fragment.depth);
+                    }
+                    target.color.set(row, col, color);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Rgb8;
+
+    fn triangle() -> Mesh {
+        Mesh {
+            positions: vec![Vector3::new(-0.9, -0.9, 0.0), Vector3::new(0.9, -0.9, 0.0), Vector3::new(0.0, 0.9, 0.0)],
+            triangles: vec![[0, 1, 2]],
+            ..Mesh::default()
+        }
+    }
+
+    // The normals the fragments of `mesh` get
+    fn drawn_normals(mesh: &Mesh) -> Vec<Vector3<f32>> {
+        let mut target = Framebuffer::<Rgb8>::new(16, 16);
+        let mut normals = Vec::new();
+        Pipeline::new().with_cull_mode(CullMode::None).draw_mesh(&mut target, mesh, |fragment| {
+            normals.push(fragment.varying.1);
+            Some(Rgb8::new(255, 255, 255))
+        });
+        normals
+    }
+
+    #[test]
+    fn mesh_without_normals_is_drawn_flat() {
+        let normals = drawn_normals(&triangle());
+        assert!(!normals.is_empty());
+        for normal in normals {
+            assert!((normal - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-4, "{:?}", normal);
+        }
+    }
+
+    #[test]
+    fn zero_normals_stay_finite() {
+        let mut mesh = triangle();
+        mesh.normals = vec![Vector3::new(0.0, 0.0, 0.0); 3];
+        let normals = drawn_normals(&mesh);
+        assert!(!normals.is_empty());
+        assert!(normals.iter().all(|n| n.magnitude2() == 0.0));
+    }
+}
@@ -0,0 +1,287 @@
+use std::{fs, io::{self, Write}, path::Path};
+
+use crate::{
+    color::{convert_pixel, Pixel, Rgba8},
+    deflate::{zlib_compress, Crc32},
+    files::ImageError,
+    image::Image,
+    sequence::FrameSequence,
+};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_RGBA: u8 = 6;
+const DISPOSE_OP_NONE: u8 = 0;
+const BLEND_OP_SOURCE: u8 = 0;
+
+pub fn write_png_file<P: Pixel>(path: impl AsRef<Path>, image: &Image<P>) -> Result<(), ImageError> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = io::BufWriter::new(file);
+    write_png(&mut writer, image)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Images whose pixel format has an alpha channel are written as RGBA, the
+// rest as RGB. PNG rows always go top to bottom.
+pub fn write_png<P: Pixel>(mut writer: impl Write, image: &Image<P>) -> Result<(), ImageError> {
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 || width > i32::MAX as usize || height > i32::MAX as usize {
+        return Err(ImageError::InvalidDimensions { width: width as i64, height: height as i64 });
+    }
+    let alpha = P::CHANNELS == 4;
+    let crc = Crc32::new();
+
+    writer.write_all(&SIGNATURE)?;
+    write_chunk(&mut writer, &crc, b"IHDR", &header(width, height, alpha))?;
+    write_chunk(&mut writer, &crc, b"IDAT", &zlib_compress(&filtered_rows(image, alpha)))?;
+    write_chunk(&mut writer, &crc, b"IEND", &[])?;
+    Ok(())
+}
+
+pub fn write_apng_file<P: Pixel>(path: impl AsRef<Path>, sequence: &FrameSequence<P>) -> Result<(), ImageError> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = io::BufWriter::new(file);
+    write_apng(&mut writer, sequence)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Animated PNG, every frame covering the whole canvas. The first frame is
+// the plain image too, which is what readers without APNG support show.
+pub fn write_apng<P: Pixel>(mut writer: impl Write, sequence: &FrameSequence<P>) -> Result<(), ImageError> {
+    let (width, height) = (sequence.width(), sequence.height());
+    if width == 0 || height == 0 || width > i32::MAX as usize || height > i32::MAX as usize {
+        return Err(ImageError::InvalidDimensions { width: width as i64, height: height as i64 });
+    }
+    if sequence.frames().is_empty() {
+        return Err(ImageError::NoFrames);
+    }
+    let alpha = P::CHANNELS == 4;
+    let crc = Crc32::new();
+
+    writer.write_all(&SIGNATURE)?;
+    write_chunk(&mut writer, &crc, b"IHDR", &header(width, height, alpha))?;
+    let mut actl = Vec::with_capacity(8);
+    actl.extend((sequence.frames().len() as u32).to_be_bytes());
+    actl.extend(sequence.plays().to_be_bytes());
+    write_chunk(&mut writer, &crc, b"acTL", &actl)?;
+
+    // Frame controls and frame data share one sequence of numbers
+    let mut number = 0u32;
+    for (i, (image, delay)) in sequence.frames().iter().enumerate() {
+        let mut fctl = Vec::with_capacity(26);
+        fctl.extend(number.to_be_bytes());
+        fctl.extend((width as u32).to_be_bytes());
+        fctl.extend((height as u32).to_be_bytes());
+        fctl.extend(0u32.to_be_bytes());
+        fctl.extend(0u32.to_be_bytes());
+        // The delay as a fraction, in milliseconds
+        fctl.extend((delay.as_millis().min(u16::MAX as u128) as u16).to_be_bytes());
+        fctl.extend(1000u16.to_be_bytes());
+        fctl.push(DISPOSE_OP_NONE);
+        fctl.push(BLEND_OP_SOURCE);
+        write_chunk(&mut writer, &crc, b"fcTL", &fctl)?;
+        number += 1;
+
+        let data = zlib_compress(&filtered_rows(image, alpha));
+        if i == 0 {
+            write_chunk(&mut writer, &crc, b"IDAT", &data)?;
+        } else {
+            let mut fdat = Vec::with_capacity(4 + data.len());
+            fdat.extend(number.to_be_bytes());
+            fdat.extend(data);
+            write_chunk(&mut writer, &crc, b"fdAT", &fdat)?;
+            number += 1;
+        }
+    }
+    write_chunk(&mut writer, &crc, b"IEND", &[])?;
+    Ok(())
+}
+
+pub(crate) fn header(width: usize, height: usize, alpha: bool) -> Vec<u8> {
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend((width as u32).to_be_bytes());
+    ihdr.extend((height as u32).to_be_bytes());
+    ihdr.push(8); // bit depth
+    ihdr.push(if alpha { COLOR_TYPE_RGBA } else { COLOR_TYPE_RGB });
+    ihdr.push(0); // compression
+    ihdr.push(0); // filter method
+    ihdr.push(0); // no interlace
+    ihdr
+}
+
+pub(crate) fn write_chunk(writer: &mut impl Write, crc: &Crc32, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc.checksum(&[kind, data]).to_be_bytes())
+}
+
+// Each row gets whichever filter leaves the smallest residuals, the usual
+// heuristic for making the data compress well
+pub(crate) fn filtered_rows<P: Pixel>(image: &Image<P>, alpha: bool) -> Vec<u8> {
+    let bpp = if alpha { 4 } else { 3 };
+    let row_len = image.width() * bpp;
+    let mut out = Vec::with_capacity((row_len + 1) * image.height());
+    let mut previous = vec![0u8; row_len];
+    let mut current = Vec::with_capacity(row_len);
+    let mut candidate = vec![0u8; row_len];
+    let mut best = vec![0u8; row_len];
+
+    for row in image.rows() {
+        current.clear();
+        for &pixel in row {
+            let c: Rgba8 = convert_pixel(pixel);
+            current.extend_from_slice(&[c.r, c.g, c.b, c.a][..bpp]);
+        }
+
+        let mut best_filter = 0;
+        let mut best_score = u64::MAX;
+        for filter in 0..5u8 {
+            for i in 0..row_len {
+                let a = if i >= bpp { current[i - bpp] } else { 0 };
+                let b = previous[i];
+                let c = if i >= bpp { previous[i - bpp] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                candidate[i] = current[i].wrapping_sub(predicted);
+            }
+            let score: u64 = candidate.iter().map(|&v| (v as i8).unsigned_abs() as u64).sum();
+            if score < best_score {
+                best_score = score;
+                best_filter = filter;
+                best.copy_from_slice(&candidate);
+            }
+        }
+        out.push(best_filter);
+        out.extend_from_slice(&best);
+        previous.copy_from_slice(&current);
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Rgb8;
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    // Undoes `filtered_rows`, as a decoder would
+    fn unfilter(data: &[u8], row_len: usize, bpp: usize) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        for (y, row) in data.chunks(row_len + 1).enumerate() {
+            let start = out.len();
+            for (i, &v) in row[1..].iter().enumerate() {
+                let a = if i >= bpp { out[start + i - bpp] } else { 0 };
+                let b = if y > 0 { out[start + i - row_len] } else { 0 };
+                let c = if y > 0 && i >= bpp { out[start + i - row_len - bpp] } else { 0 };
+                let predicted = match row[0] {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    filter => panic!("unknown filter {}", filter),
+                };
+                out.push(v.wrapping_add(predicted));
+            }
+        }
+        out
+    }
+
+    fn filters(data: &[u8], row_len: usize) -> Vec<u8> {
+        data.chunks(row_len + 1).map(|row| row[0]).collect()
+    }
+
+    #[test]
+    fn filter_choice_follows_the_image() {
+        // Flat: every filter leaves zeros, the first one is kept
+        let flat = Image::filled(4, 3, Rgb8::new(0, 0, 0));
+        assert_eq!(filters(&filtered_rows(&flat, false), 12), [0, 0, 0]);
+
+        // A gradient along the rows is best predicted from the left
+        let mut across = Image::<Rgb8>::new(16, 2);
+        for row in across.rows_mut() {
+            for (x, p) in row.iter_mut().enumerate() {
+                *p = Rgb8::new(x as u8 * 16, 100, 200 - x as u8 * 8);
+            }
+        }
+        assert_eq!(filtered_rows(&across, false)[0], 1);
+
+        // Noisy rows that repeat are best predicted from above
+        let mut state = 7u32;
+        let row: Vec<Rgb8> = (0..16)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                Rgb8::new((state >> 8) as u8, (state >> 16) as u8, (state >> 24) as u8)
+            })
+            .collect();
+        let repeated = Image::from_pixels(16, 3, row.repeat(3)).unwrap();
+        assert_eq!(filters(&filtered_rows(&repeated, false), 48)[1..], [2, 2]);
+    }
+
+    #[test]
+    fn filtered_rows_decode_to_the_pixels() {
+        let mut image = Image::<Rgba8>::new(13, 9);
+        for (y, row) in image.rows_mut().enumerate() {
+            for (x, p) in row.iter_mut().enumerate() {
+                *p = Rgba8::new((x * 19) as u8, (y * 31) as u8, (x * y * 7) as u8, (255 - x * y) as u8);
+            }
+        }
+        let expected: Vec<u8> = image.pixels().iter().flat_map(|p| [p.r, p.g, p.b, p.a]).collect();
+        assert_eq!(unfilter(&filtered_rows(&image, true), 13 * 4, 4), expected);
+    }
+
+    #[test]
+    fn png_chunks_are_well_formed() {
+        let image = Image::filled(5, 4, Rgb8::new(10, 20, 30));
+        let mut png = Vec::new();
+        write_png(&mut png, &image).unwrap();
+        assert_eq!(png[..8], SIGNATURE);
+
+        let crc = Crc32::new();
+        let mut rest = &png[8..];
+        let mut kinds = Vec::new();
+        let mut idat = Vec::new();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            let stored = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc.checksum(&[kind, data]), stored);
+            if kind == b"IDAT" {
+                idat.extend_from_slice(data);
+            }
+            kinds.push(String::from_utf8(kind.to_vec()).unwrap());
+            rest = &rest[12 + len..];
+        }
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+        let pixels = unfilter(&decompress_to_vec_zlib(&idat).unwrap(), 15, 3);
+        assert_eq!(pixels, [10, 20, 30].repeat(20));
+    }
+}
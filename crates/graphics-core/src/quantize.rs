@@ -0,0 +1,251 @@
+use std::{fmt, str::FromStr};
+
+use crate::{color::Rgb8, image::Image};
+
+// How the error of mapping a pixel to its palette color is handled
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Dither {
+    // Every pixel gets its nearest color, smooth gradients show bands
+    #[default]
+    None,
+    // The error is spread onto the pixels right and below, which trades the
+    // bands for fine noise
+    FloydSteinberg,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDitherError(String);
+
+impl fmt::Display for ParseDitherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown dither `{}`, expected none or floyd-steinberg", self.0)
+    }
+}
+
+impl std::error::Error for ParseDitherError {}
+
+impl FromStr for Dither {
+    type Err = ParseDitherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Dither::None),
+            "floyd-steinberg" => Ok(Dither::FloydSteinberg),
+            _ => Err(ParseDitherError(s.to_string())),
+        }
+    }
+}
+
+pub const MAX_COLORS: usize = 256;
+// Colors are counted with 5 bits a channel, fine enough to place 256
+// palette colors, and the histogram stays small
+const HISTOGRAM_BITS: u32 = 5;
+// Nearest colors are remembered with 6 bits a channel
+const LOOKUP_BITS: u32 = 6;
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    // The color at HISTOGRAM_BITS
+    key: [u8; 3],
+    count: u64,
+    sum: [u64; 3],
+}
+
+fn channel_range(buckets: &[Bucket]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (min, max) = buckets.iter().fold((u8::MAX, 0), |(min, max), b| (min.min(b.key[c]), max.max(b.key[c])));
+            (c, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colors: Vec<Rgb8>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<Rgb8>) -> Palette {
+        assert!(!colors.is_empty() && colors.len() <= MAX_COLORS, "a palette has 1 to 256 colors");
+        Palette { colors }
+    }
+
+    // Median cut over the colors of all `images`: the box of colors spanning
+    // the most, weighted by how many pixels it holds, is cut in two at the
+    // median of its widest channel until there are `max_colors` boxes. Each
+    // box becomes the average of its pixels.
+    pub fn median_cut<'a>(images: impl IntoIterator<Item = &'a Image<Rgb8>>, max_colors: usize) -> Palette {
+        assert!((1..=MAX_COLORS).contains(&max_colors), "a palette has 1 to 256 colors");
+        let shift = 8 - HISTOGRAM_BITS;
+        let mut histogram = vec![(0u64, [0u64; 3]); 1 << (3 * HISTOGRAM_BITS)];
+        for image in images {
+            for p in image.pixels() {
+                let key = ((p.r >> shift) as usize) << (2 * HISTOGRAM_BITS) | ((p.g >> shift) as usize) << HISTOGRAM_BITS | (p.b >> shift) as usize;
+                let (count, sum) = &mut histogram[key];
+                *count += 1;
+                sum[0] += p.r as u64;
+                sum[1] += p.g as u64;
+                sum[2] += p.b as u64;
+            }
+        }
+        let mask = (1 << HISTOGRAM_BITS) - 1;
+        let buckets: Vec<Bucket> = histogram
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (count, _))| *count > 0)
+            .map(|(key, (count, sum))| Bucket {
+                key: [(key >> (2 * HISTOGRAM_BITS)) as u8, (key >> HISTOGRAM_BITS & mask) as u8, (key & mask) as u8],
+                count,
+                sum,
+            })
+            .collect();
+        if buckets.is_empty() {
+            return Palette::new(vec![Rgb8::default()]);
+        }
+
+        let mut boxes = vec![buckets];
+        while boxes.len() < max_colors {
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.len() > 1)
+                .max_by_key(|(_, b)| channel_range(b).1 as u64 * b.iter().map(|bucket| bucket.count).sum::<u64>());
+            let Some((index, _)) = widest else {
+                break;
+            };
+            let mut cut = boxes.swap_remove(index);
+            let (channel, _) = channel_range(&cut);
+            cut.sort_by_key(|bucket| bucket.key[channel]);
+            let half = cut.iter().map(|bucket| bucket.count).sum::<u64>() / 2;
+            let mut seen = 0;
+            let median = cut
+                .iter()
+                .position(|bucket| {
+                    seen += bucket.count;
+                    seen > half
+                })
+                .unwrap();
+            let rest = cut.split_off(median.clamp(1, cut.len() - 1));
+            boxes.push(cut);
+            boxes.push(rest);
+        }
+
+        let colors = boxes
+            .iter()
+            .map(|b| {
+                let count: u64 = b.iter().map(|bucket| bucket.count).sum();
+                let mean = |c: usize| ((b.iter().map(|bucket| bucket.sum[c]).sum::<u64>() + count / 2) / count) as u8;
+                Rgb8 { r: mean(0), g: mean(1), b: mean(2) }
+            })
+            .collect();
+        Palette::new(colors)
+    }
+
+    pub fn colors(&self) -> &[Rgb8] {
+        &self.colors
+    }
+
+    pub fn nearest(&self, r: u8, g: u8, b: u8) -> u8 {
+        let distance = |c: &Rgb8| {
+            let (dr, dg, db) = (c.r as i32 - r as i32, c.g as i32 - g as i32, c.b as i32 - b as i32);
+            dr * dr + dg * dg + db * db
+        };
+        (0..self.colors.len()).min_by_key(|&i| distance(&self.colors[i])).unwrap() as u8
+    }
+
+    // The palette index of every pixel, row by row
+    pub fn index(&self, image: &Image<Rgb8>, dither: Dither) -> Vec<u8> {
+        let shift = 8 - LOOKUP_BITS;
+        let mut lookup = vec![u16::MAX; 1 << (3 * LOOKUP_BITS)];
+        let mut nearest = |r: u8, g: u8, b: u8| {
+            let key = ((r >> shift) as usize) << (2 * LOOKUP_BITS) | ((g >> shift) as usize) << LOOKUP_BITS | (b >> shift) as usize;
+            if lookup[key] == u16::MAX {
+                lookup[key] = self.nearest(r, g, b) as u16;
+            }
+            lookup[key] as u8
+        };
+
+        let width = image.width();
+        let mut indices = Vec::with_capacity(width * image.height());
+        if dither == Dither::None {
+            indices.extend(image.pixels().iter().map(|p| nearest(p.r, p.g, p.b)));
+            return indices;
+        }
+
+        // Errors for this row and the next, with a pixel of padding either side
+        let mut error = vec![[0.0f32; 3]; width + 2];
+        let mut below = vec![[0.0f32; 3]; width + 2];
+        for row in image.rows() {
+            for (x, p) in row.iter().enumerate() {
+                let wanted = [p.r, p.g, p.b].map(|v| v as f32);
+                let wanted: [f32; 3] = std::array::from_fn(|c| (wanted[c] + error[x + 1][c]).clamp(0.0, 255.0));
+                let [r, g, b] = wanted.map(|v| v.round() as u8);
+                let index = nearest(r, g, b);
+                indices.push(index);
+
+                let chosen = self.colors[index as usize];
+                let chosen = [chosen.r, chosen.g, chosen.b];
+                for c in 0..3 {
+                    let e = wanted[c] - chosen[c] as f32;
+                    error[x + 2][c] += e * 7.0 / 16.0;
+                    below[x][c] += e * 3.0 / 16.0;
+                    below[x + 1][c] += e * 5.0 / 16.0;
+                    below[x + 2][c] += e * 1.0 / 16.0;
+                }
+            }
+            std::mem::swap(&mut error, &mut below);
+            below.fill([0.0; 3]);
+        }
+        indices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> Image<Rgb8> {
+        let mut image = Image::new(64, 64);
+        for (y, row) in image.rows_mut().enumerate() {
+            for (x, p) in row.iter_mut().enumerate() {
+                *p = Rgb8::new((x * 4) as u8, (y * 4) as u8, ((x * y) % 256) as u8);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn median_cut_stays_within_max_colors() {
+        let image = gradient();
+        for max_colors in [1, 2, 3, 7, 16, 100, 255, 256] {
+            let palette = Palette::median_cut([&image], max_colors);
+            assert!(palette.colors().len() <= max_colors, "{} colors for {}", palette.colors().len(), max_colors);
+        }
+    }
+
+    #[test]
+    fn median_cut_keeps_few_colors_exact() {
+        let colors = [Rgb8::new(0, 0, 0), Rgb8::new(255, 0, 0), Rgb8::new(0, 255, 0)];
+        let image = Image::from_pixels(3, 2, [colors, colors].concat()).unwrap();
+        let palette = Palette::median_cut([&image], 256);
+        assert_eq!(palette.colors().len(), 3);
+        assert!(colors.iter().all(|c| palette.colors().contains(c)));
+
+        let indices = palette.index(&image, Dither::None);
+        let mapped: Vec<Rgb8> = indices.iter().map(|&i| palette.colors()[i as usize]).collect();
+        assert_eq!(mapped, image.pixels());
+    }
+
+    #[test]
+    fn index_stays_in_the_palette() {
+        let image = gradient();
+        let palette = Palette::median_cut([&image], 5);
+        for dither in [Dither::None, Dither::FloydSteinberg] {
+            let indices = palette.index(&image, dither);
+            assert_eq!(indices.len(), 64 * 64);
+            assert!(indices.iter().all(|&i| (i as usize) < palette.colors().len()));
+        }
+    }
+}
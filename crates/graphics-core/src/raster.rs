@@ -0,0 +1,291 @@
+use cgmath::{Vector2, Vector3, Vector4};
+
+use crate::{
+    color::{Pixel, RgbF32},
+    geometry::Point,
+    image::Image,
+};
+
+// Vertex positions are snapped to 1/256th of a pixel so edge tests are exact
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+
+// Largest coordinate that can't overflow the edge function products
+const MAX_COORDINATE: f32 = (1 << 20) as f32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fragment {
+    pub row: usize,
+    pub col: usize,
+    // Weights of the three vertices at the pixel center, summing to 1
+    pub barycentric: [f32; 3],
+}
+
+#[derive(Clone, Copy)]
+struct FixedPoint {
+    x: i64,
+    y: i64,
+}
+
+impl FixedPoint {
+    fn from_point(p: Point) -> FixedPoint {
+        FixedPoint {
+            x: (p.x * SUBPIXEL_ONE as f32).round() as i64,
+            y: (p.y * SUBPIXEL_ONE as f32).round() as i64,
+        }
+    }
+}
+
+// Twice the signed area of (a, b, p), positive when p is to the right of
+// a -> b in y-down pixel space
+fn edge(a: FixedPoint, b: FixedPoint, p: FixedPoint) -> i64 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+// Top-left fill rule: a pixel center exactly on an edge belongs to the
+// triangle only if the edge is a top edge or a left edge, so triangles
+// sharing an edge never both draw the pixels on it.
+fn is_top_left(a: FixedPoint, b: FixedPoint) -> bool {
+    let is_top = a.y == b.y && b.x > a.x;
+    let is_left = b.y < a.y;
+    is_top || is_left
+}
+
+struct EdgeStepper {
+    // Edge function at the current pixel, with the fill rule bias applied
+    value: i64,
+    row_start: i64,
+    step_x: i64,
+    step_y: i64,
+}
+
+impl EdgeStepper {
+    fn new(a: FixedPoint, b: FixedPoint, origin: FixedPoint) -> EdgeStepper {
+        let bias = if is_top_left(a, b) { 0 } else { -1 };
+        let value = edge(a, b, origin) + bias;
+        EdgeStepper {
+            value,
+            row_start: value,
+            step_x: -(b.y - a.y) * SUBPIXEL_ONE,
+            step_y: (b.x - a.x) * SUBPIXEL_ONE,
+        }
+    }
+
+    fn next_col(&mut self) {
+        self.value += self.step_x;
+    }
+
+    fn next_row(&mut self) {
+        self.row_start += self.step_y;
+        self.value = self.row_start;
+    }
+}
+
+// Calls `f` for every pixel of a `width` x `height` target whose center is
+// covered by the triangle. Either winding is accepted, degenerate triangles
+// draw nothing.
+pub fn rasterize_triangle(vertices: [Point; 3], width: usize, height: usize, mut f: impl FnMut(Fragment)) {
+    if vertices
+        .iter()
+        .any(|v| !(v.x.abs() < MAX_COORDINATE && v.y.abs() < MAX_COORDINATE))
+    {
+        return;
+    }
+
+    let mut v = vertices.map(FixedPoint::from_point);
+    let mut area = edge(v[0], v[1], v[2]);
+    if area == 0 {
+        return;
+    }
+
+    // Flip to a consistent winding, remembering to flip the weights back
+    let flipped = area < 0;
+    if flipped {
+        v.swap(1, 2);
+        area = -area;
+    }
+
+    // Bounding box of pixel centers, clipped to the target
+    let min_x = v.iter().map(|p| p.x).min().unwrap();
+    let max_x = v.iter().map(|p| p.x).max().unwrap();
+    let min_y = v.iter().map(|p| p.y).min().unwrap();
+    let max_y = v.iter().map(|p| p.y).max().unwrap();
+    let half = SUBPIXEL_ONE / 2;
+    let first_col = ((min_x - half).max(0) + SUBPIXEL_ONE - 1) / SUBPIXEL_ONE;
+    let last_col = (max_x - half).div_euclid(SUBPIXEL_ONE).min(width as i64 - 1);
+    let first_row = ((min_y - half).max(0) + SUBPIXEL_ONE - 1) / SUBPIXEL_ONE;
+    let last_row = (max_y - half).div_euclid(SUBPIXEL_ONE).min(height as i64 - 1);
+    if first_col > last_col || first_row > last_row {
+        return;
+    }
+
+    let origin = FixedPoint {
+        x: first_col * SUBPIXEL_ONE + half,
+        y: first_row * SUBPIXEL_ONE + half,
+    };
+    let mut w0 = EdgeStepper::new(v[1], v[2], origin);
+    let mut w1 = EdgeStepper::new(v[2], v[0], origin);
+    let mut w2 = EdgeStepper::new(v[0], v[1], origin);
+    let bias = |a: FixedPoint, b: FixedPoint| if is_top_left(a, b) { 0 } else { 1 };
+    let unbias = [bias(v[1], v[2]), bias(v[2], v[0]), bias(v[0], v[1])];
+    let inv_area = 1.0 / area as f64;
+
+    for row in first_row..=last_row {
+        for col in first_col..=last_col {
+            if (w0.value | w1.value | w2.value) >= 0 {
+                let b0 = ((w0.value + unbias[0]) as f64 * inv_area) as f32;
+                let b1 = ((w1.value + unbias[1]) as f64 * inv_area) as f32;
+                let b2 = 1.0 - b0 - b1;
+                let barycentric = if flipped { [b0, b2, b1] } else { [b0, b1, b2] };
+                f(Fragment {
+                    row: row as usize,
+                    col: col as usize,
+                    barycentric,
+                });
+            }
+            w0.next_col();
+            w1.next_col();
+            w2.next_col();
+        }
+        w0.next_row();
+        w1.next_row();
+        w2.next_row();
+    }
+}
+
+// Values that can be blended across a triangle by barycentric weights
+pub trait Interpolate: Copy {
+    fn interpolate(values: [Self; 3], weights: [f32; 3]) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(values: [f32; 3], weights: [f32; 3]) -> f32 {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl Interpolate for Point {
+    fn interpolate(values: [Point; 3], weights: [f32; 3]) -> Point {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl Interpolate for RgbF32 {
+    fn interpolate(values: [RgbF32; 3], weights: [f32; 3]) -> RgbF32 {
+        RgbF32::new(
+            f32::interpolate(values.map(|c| c.r), weights),
+            f32::interpolate(values.map(|c| c.g), weights),
+            f32::interpolate(values.map(|c| c.b), weights),
+        )
+    }
+}
+
+impl<const N: usize> Interpolate for [f32; N] {
+    fn interpolate(values: [[f32; N]; 3], weights: [f32; 3]) -> [f32; N] {
+        std::array::from_fn(|i| f32::interpolate(values.map(|v| v[i]), weights))
+    }
+}
+
+impl Interpolate for Vector2<f32> {
+    fn interpolate(values: [Vector2<f32>; 3], weights: [f32; 3]) -> Vector2<f32> {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl Interpolate for Vector3<f32> {
+    fn interpolate(values: [Vector3<f32>; 3], weights: [f32; 3]) -> Vector3<f32> {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl Interpolate for Vector4<f32> {
+    fn interpolate(values: [Vector4<f32>; 3], weights: [f32; 3]) -> Vector4<f32> {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl<A: Interpolate, B: Interpolate> Interpolate for (A, B) {
+    fn interpolate(values: [(A, B); 3], weights: [f32; 3]) -> (A, B) {
+        (A::interpolate(values.map(|v| v.0), weights), B::interpolate(values.map(|v| v.1), weights))
+    }
+}
+
+impl Interpolate for () {
+    fn interpolate(_: [(); 3], _: [f32; 3]) {}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RasterVertex<V> {
+    // Pixel space position
+    pub position: Point,
+    // Depth after the perspective divide, which is affine in screen space
+    pub depth: f32,
+    // Clip space w, 1.0 when there is no perspective
+    pub w: f32,
+    pub varying: V,
+}
+
+impl<V> RasterVertex<V> {
+    pub fn new(position: Point, varying: V) -> RasterVertex<V> {
+        RasterVertex { position, depth: 0.0, w: 1.0, varying }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct InterpolatedFragment<V> {
+    pub row: usize,
+    pub col: usize,
+    pub depth: f32,
+    // Screen space weights, use these for anything affine in screen space
+    pub barycentric: [f32; 3],
+    // Perspective-correct weights, the ones `varying` was interpolated with
+    pub perspective: [f32; 3],
+    pub varying: V,
+}
+
+// Screen space barycentric weights interpolate linearly in the projected
+// image, but attributes like UVs and colors vary linearly on the triangle in
+// 3D. Weighting by 1/w and renormalizing undoes the perspective divide.
+pub fn perspective_correct(barycentric: [f32; 3], w: [f32; 3]) -> [f32; 3] {
+    let weighted = [barycentric[0] / w[0], barycentric[1] / w[1], barycentric[2] / w[2]];
+    let sum = weighted[0] + weighted[1] + weighted[2];
+    if sum == 0.0 {
+        return barycentric;
+    }
+    weighted.map(|v| v / sum)
+}
+
+// Like `rasterize_triangle`, also interpolating depth and the vertices'
+// varyings for every covered pixel
+pub fn rasterize_interpolated<V: Interpolate>(
+    vertices: [RasterVertex<V>; 3],
+    width: usize,
+    height: usize,
+    mut f: impl FnMut(InterpolatedFragment<V>),
+) {
+    let positions = vertices.map(|v| v.position);
+    let depths = vertices.map(|v| v.depth);
+    let ws = vertices.map(|v| v.w);
+    let varyings = vertices.map(|v| v.varying);
+    rasterize_triangle(positions, width, height, |fragment| {
+        let perspective = perspective_correct(fragment.barycentric, ws);
+        f(InterpolatedFragment {
+            row: fragment.row,
+            col: fragment.col,
+            depth: f32::interpolate(depths, fragment.barycentric),
+            barycentric: fragment.barycentric,
+            perspective,
+            varying: V::interpolate(varyings, perspective),
+        });
+    });
+}
+
+// Fills a triangle with linear colors blended smoothly between its vertices
+pub fn draw_gouraud_triangle<P: Pixel>(image: &mut Image<P>, positions: [Point; 3], colors: [RgbF32; 3]) {
+    let (width, height) = (image.width(), image.height());
+    let vertices = [0, 1, 2].map(|i| RasterVertex::new(positions[i], colors[i]));
+    rasterize_interpolated(vertices, width, height, |fragment| {
+        let color = fragment.varying;
+        image.set(fragment.row, fragment.col, P::from_linear_rgba([color.r, color.g, color.b, 1.0]));
+    });
+}
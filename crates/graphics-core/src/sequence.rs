@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::{color::Pixel, image::Image};
+
+// Frames of the same size, each shown for its own delay, for the animated
+// GIF and APNG writers
+#[derive(Clone, Debug)]
+pub struct FrameSequence<P: Pixel> {
+    width: usize,
+    height: usize,
+    plays: u32,
+    frames: Vec<(Image<P>, Duration)>,
+}
+
+impl<P: Pixel> FrameSequence<P> {
+    pub fn new(width: usize, height: usize) -> FrameSequence<P> {
+        FrameSequence { width, height, plays: 0, frames: Vec::new() }
+    }
+
+    // How many times the frames play before the last one stays up, 0 loops
+    // forever
+    pub fn with_plays(mut self, plays: u32) -> Self {
+        self.plays = plays;
+        self
+    }
+
+    pub fn with_frame(mut self, image: Image<P>, delay: Duration) -> Self {
+        self.push(image, delay);
+        self
+    }
+
+    pub fn push(&mut self, image: Image<P>, delay: Duration) {
+        assert!(image.width() == self.width && image.height() == self.height, "frame is not the size of the sequence");
+        self.frames.push((image, delay));
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn plays(&self) -> u32 {
+        self.plays
+    }
+
+    pub fn frames(&self) -> &[(Image<P>, Duration)] {
+        &self.frames
+    }
+}
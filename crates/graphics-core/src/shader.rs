@@ -0,0 +1,115 @@
+// Programmable stages for `Pipeline::draw_shaded`. A shader is any struct:
+// its fields are the uniforms, the same for every vertex or fragment of a
+// draw call, and the varyings are whatever type the vertex stage hands to
+// the fragment stage, interpolated perspective-correctly in between.
+
+use cgmath::{Vector2, Vector4};
+
+use crate::{
+    color::Pixel,
+    image::Image,
+    raster::{InterpolatedFragment, Interpolate},
+};
+
+pub trait VertexShader {
+    // Per-vertex attributes, such as `mesh::MeshVertex`
+    type Input;
+    type Varying: Interpolate;
+
+    // Returns the clip space position and the vertex's varyings
+    fn vertex(&self, input: &Self::Input) -> (Vector4<f32>, Self::Varying);
+}
+
+pub trait FragmentShader<V> {
+    // Converted to the framebuffer's pixel format on write, so a shader can
+    // work in linear `RgbF32` and still draw into an sRGB image
+    type Output: Pixel;
+
+    // The fragment's color, or None to discard it
+    fn fragment(&self, fragment: &InterpolatedFragment<V>) -> Option<Self::Output>;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+}
+
+// An image sampled by texture coordinates: u runs along the row and v down
+// the columns from row 0, and both wrap around outside 0..1
+#[derive(Clone, Debug)]
+pub struct Texture<P: Pixel> {
+    image: Image<P>,
+    filter: TextureFilter,
+}
+
+impl<P: Pixel> Texture<P> {
+    pub fn new(image: Image<P>) -> Texture<P> {
+        Texture { image, filter: TextureFilter::Bilinear }
+    }
+
+    pub fn with_filter(mut self, filter: TextureFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn image(&self) -> &Image<P> {
+        &self.image
+    }
+
+    // Linear RGBA, so filtering happens in linear light whatever the
+    // image's format. An empty image samples as transparent black.
+    pub fn sample(&self, uv: Vector2<f32>) -> [f32; 4] {
+        let (width, height) = (self.image.width(), self.image.height());
+        if width == 0 || height == 0 {
+            return [0.0; 4];
+        }
+        let texel = |col: i64, row: i64| {
+            let col = col.rem_euclid(width as i64) as usize;
+            let row = row.rem_euclid(height as i64) as usize;
+            self.image.get(row, col).unwrap().to_linear_rgba()
+        };
+        // Texel centers sit at half-integer coordinates
+        let x = uv.x * width as f32 - 0.5;
+        let y = uv.y * height as f32 - 0.5;
+        match self.filter {
+            TextureFilter::Nearest => texel(x.round() as i64, y.round() as i64),
+            TextureFilter::Bilinear => {
+                let (col, row) = (x.floor(), y.floor());
+                let (tx, ty) = (x - col, y - row);
+                let (col, row) = (col as i64, row as i64);
+                let [a, b, c, d] = [texel(col, row), texel(col + 1, row), texel(col, row + 1), texel(col + 1, row + 1)];
+                std::array::from_fn(|i| {
+                    let top = a[i] + (b[i] - a[i]) * tx;
+                    let bottom = c[i] + (d[i] - c[i]) * tx;
+                    top + (bottom - top) * ty
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Rgba8;
+
+    #[test]
+    fn empty_textures_sample_transparent_black() {
+        for filter in [TextureFilter::Nearest, TextureFilter::Bilinear] {
+            for image in [Image::<Rgba8>::new(0, 4), Image::new(4, 0), Image::new(0, 0)] {
+                assert_eq!(Texture::new(image).with_filter(filter).sample(Vector2::new(0.3, 0.7)), [0.0; 4]);
+            }
+        }
+    }
+
+    #[test]
+    fn coordinates_wrap_around() {
+        let mut image = Image::filled(2, 1, Rgba8::new(0, 0, 0, 255));
+        image.set(0, 1, Rgba8::new(255, 255, 255, 255));
+        let texture = Texture::new(image).with_filter(TextureFilter::Nearest);
+        assert_eq!(texture.sample(Vector2::new(0.75, 0.5)), [1.0; 4]);
+        assert_eq!(texture.sample(Vector2::new(-0.25, 3.5)), [1.0; 4]);
+        assert_eq!(texture.sample(Vector2::new(1.25, 0.5)), [0.0, 0.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,1410 @@
+// A practical subset of SVG 1.1: the basic shapes, path data, solid fill and
+// stroke paints and transforms. Everything else (text, gradients, clipping,
+// `use`, CSS stylesheets) is skipped rather than rejected, so files exported
+// by drawing tools still render their plain geometry.
+
+use std::fmt;
+
+use crate::{
+    canvas::{Canvas, FillRule},
+    color::{Pixel, Rgba8},
+    geometry::{Point, Transform},
+    image::Image,
+    path::{stroke_polylines, LineCap, LineJoin, Path, StrokeStyle, DEFAULT_TOLERANCE},
+};
+
+// Size used by browsers when the root has neither dimensions nor a viewBox
+const DEFAULT_WIDTH: f32 = 300.0;
+const DEFAULT_HEIGHT: f32 = 150.0;
+
+// Subtrees that never draw anything directly
+const SKIPPED_ELEMENTS: [&str; 14] = [
+    "defs",
+    "clipPath",
+    "mask",
+    "symbol",
+    "style",
+    "title",
+    "desc",
+    "metadata",
+    "pattern",
+    "marker",
+    "linearGradient",
+    "radialGradient",
+    "filter",
+    "script",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SvgError {
+    // The markup itself is broken, `offset` is in bytes
+    Xml { offset: usize, message: &'static str },
+    MismatchedTag { expected: String, found: String },
+    UnclosedTag(String),
+    NoRootElement,
+    NotSvg(String),
+    InvalidAttribute { name: String, value: String },
+}
+
+impl fmt::Display for SvgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SvgError::Xml { offset, message } => write!(f, "malformed xml at byte {}: {}", offset, message),
+            SvgError::MismatchedTag { expected, found } => {
+                write!(f, "closing tag </{}> does not match <{}>", found, expected)
+            }
+            SvgError::UnclosedTag(name) => write!(f, "element <{}> is never closed", name),
+            SvgError::NoRootElement => write!(f, "document has no root element"),
+            SvgError::NotSvg(name) => write!(f, "root element is <{}>, expected <svg>", name),
+            SvgError::InvalidAttribute { name, value } => write!(f, "invalid value `{}` for attribute {}", value, name),
+        }
+    }
+}
+
+impl std::error::Error for SvgError {}
+
+// A shape in document (viewBox) units, ready to be drawn
+#[derive(Clone, Debug)]
+pub struct SvgShape {
+    pub path: Path,
+    // Maps the path's own user units to document units
+    pub transform: Transform,
+    pub fill: Option<(Rgba8, FillRule)>,
+    pub stroke: Option<(Rgba8, StrokeStyle)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SvgDocument {
+    width: f32,
+    height: f32,
+    // min x, min y, width, height
+    view_box: [f32; 4],
+    preserve_aspect_ratio: bool,
+    shapes: Vec<SvgShape>,
+}
+
+impl SvgDocument {
+    // Intrinsic size in CSS pixels
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    pub fn shapes(&self) -> &[SvgShape] {
+        &self.shapes
+    }
+
+    // Maps document units onto a width x height pixel viewport, centering
+    // the view box and scaling it to fit unless preserveAspectRatio="none"
+    pub fn viewport_transform(&self, width: f32, height: f32) -> Transform {
+        let [min_x, min_y, view_width, view_height] = self.view_box;
+        let (mut sx, mut sy) = (width / view_width, height / view_height);
+        let (mut tx, mut ty) = (0.0, 0.0);
+        if self.preserve_aspect_ratio {
+            let s = sx.min(sy);
+            tx = (width - view_width * s) * 0.5;
+            ty = (height - view_height * s) * 0.5;
+            sx = s;
+            sy = s;
+        }
+        Transform::translate(tx, ty)
+            .multiply(Transform::scale(sx, sy))
+            .multiply(Transform::translate(-min_x, -min_y))
+    }
+
+    // Draws every shape over the image, stretched to cover all of it
+    pub fn render<P: Pixel>(&self, image: &mut Image<P>) {
+        let viewport = self.viewport_transform(image.width() as f32, image.height() as f32);
+        let mut canvas = Canvas::new(image);
+        for shape in &self.shapes {
+            let transform = viewport.multiply(shape.transform);
+            let scale = transform.mean_scale();
+            if scale <= 0.0 || !scale.is_finite() {
+                continue;
+            }
+            if let Some((color, rule)) = shape.fill {
+                canvas.fill_path(&shape.path.transform(&transform), rule, color);
+            }
+            if let Some((color, style)) = shape.stroke {
+                // The outline is built in user space, so a non-uniform scale
+                // or skew distorts the stroke the way SVG expects. Scaling
+                // user space to roughly pixel size first keeps the flattening
+                // tolerance and round joins at pixel precision.
+                let to_pixels = transform.multiply(Transform::scale(1.0 / scale, 1.0 / scale));
+                let polylines = shape.path.transform(&Transform::scale(scale, scale)).flatten(DEFAULT_TOLERANCE);
+                let style = StrokeStyle { width: style.width * scale, ..style };
+                let contours: Vec<Vec<Point>> = stroke_polylines(&polylines, &style)
+                    .into_iter()
+                    .map(|contour| contour.into_iter().map(|p| to_pixels.apply(p)).collect())
+                    .collect();
+                canvas.fill_contours(&contours, FillRule::NonZero, color);
+            }
+        }
+    }
+}
+
+pub fn parse_svg(source: &str) -> Result<SvgDocument, SvgError> {
+    let mut reader = XmlReader { source, pos: 0 };
+    let root = match reader.next_token()? {
+        Some(Token::Start(tag)) => tag,
+        Some(Token::End(_)) => return Err(SvgError::Xml { offset: reader.pos, message: "unexpected closing tag" }),
+        None => return Err(SvgError::NoRootElement),
+    };
+    if root.name != "svg" {
+        return Err(SvgError::NotSvg(root.name));
+    }
+
+    let mut document = root_document(&root)?;
+    let mut parser = Parser { document: &mut document, stack: Vec::new() };
+    parser.start(root)?;
+    while !parser.stack.is_empty() {
+        match reader.next_token()? {
+            Some(Token::Start(tag)) => parser.start(tag)?,
+            Some(Token::End(name)) => {
+                let (open, _) = parser.stack.pop().unwrap();
+                if open != name {
+                    return Err(SvgError::MismatchedTag { expected: open, found: name });
+                }
+            }
+            None => return Err(SvgError::UnclosedTag(parser.stack.pop().unwrap().0)),
+        }
+    }
+    Ok(document)
+}
+
+fn root_document(root: &Tag) -> Result<SvgDocument, SvgError> {
+    let view_box = match root.attribute("viewBox") {
+        Some(value) => match parse_numbers(value).as_deref() {
+            Some(&[x, y, w, h]) if w > 0.0 && h > 0.0 => Some([x, y, w, h]),
+            _ => return Err(invalid("viewBox", value)),
+        },
+        None => None,
+    };
+    // Percentages and missing sizes fall back to the view box
+    let absolute = |name: &str| match root.attribute(name) {
+        Some(value) if value.trim_end().ends_with('%') => Ok(None),
+        Some(value) => match parse_length(value) {
+            Some(length) if length > 0.0 => Ok(Some(length)),
+            _ => Err(invalid(name, value)),
+        },
+        None => Ok(None),
+    };
+    let (width, height) = match (absolute("width")?, absolute("height")?, view_box) {
+        (Some(w), Some(h), _) => (w, h),
+        (Some(w), None, Some([_, _, vw, vh])) => (w, w * vh / vw),
+        (None, Some(h), Some([_, _, vw, vh])) => (h * vw / vh, h),
+        (w, h, Some([_, _, vw, vh])) => (w.unwrap_or(vw), h.unwrap_or(vh)),
+        (w, h, None) => (w.unwrap_or(DEFAULT_WIDTH), h.unwrap_or(DEFAULT_HEIGHT)),
+    };
+    Ok(SvgDocument {
+        width,
+        height,
+        view_box: view_box.unwrap_or([0.0, 0.0, width, height]),
+        preserve_aspect_ratio: root.attribute("preserveAspectRatio").map(str::trim) != Some("none"),
+        shapes: Vec::new(),
+    })
+}
+
+fn invalid(name: &str, value: &str) -> SvgError {
+    SvgError::InvalidAttribute { name: name.to_string(), value: value.to_string() }
+}
+
+// Inherited state of an open element, `None` inside a subtree that is not
+// rendered
+type Context = Option<(Style, Transform)>;
+
+struct Parser<'a> {
+    document: &'a mut SvgDocument,
+    stack: Vec<(String, Context)>,
+}
+
+impl Parser<'_> {
+    fn start(&mut self, tag: Tag) -> Result<(), SvgError> {
+        let parent = match self.stack.last() {
+            Some((_, context)) => context.clone(),
+            None => Some((Style::default(), Transform::IDENTITY)),
+        };
+        let context = match parent {
+            Some((style, transform)) if !SKIPPED_ELEMENTS.contains(&tag.name.as_str()) => self.element(&tag, style, transform)?,
+            _ => None,
+        };
+        if !tag.self_closing {
+            self.stack.push((tag.name, context));
+        }
+        Ok(())
+    }
+
+    // Returns the context for the element's children
+    fn element(&mut self, tag: &Tag, style: Style, transform: Transform) -> Result<Context, SvgError> {
+        let style = style.inherit(tag, self.percent_reference(Axis::Other));
+        if !style.display {
+            return Ok(None);
+        }
+        let mut transform = match tag.attribute("transform") {
+            Some(value) => transform.multiply(parse_transform(value).ok_or_else(|| invalid("transform", value))?),
+            None => transform,
+        };
+        match tag.name.as_str() {
+            "svg" if !self.stack.is_empty() => {
+                // Nested viewports only move their content, they do not clip
+                // or rescale it here
+                let x = self.length(tag, "x", Axis::X)?;
+                let y = self.length(tag, "y", Axis::Y)?;
+                transform = transform.multiply(Transform::translate(x, y));
+                Ok(Some((style, transform)))
+            }
+            "svg" | "g" | "a" | "switch" => Ok(Some((style, transform))),
+            "rect" | "circle" | "ellipse" | "line" | "polyline" | "polygon" | "path" => {
+                if let Some(path) = self.shape_path(tag)? {
+                    // Lines enclose no area, so they are never filled
+                    let fill = match tag.name.as_str() {
+                        "line" => None,
+                        _ => style.fill_color(),
+                    };
+                    let shape = SvgShape {
+                        path,
+                        transform,
+                        fill: fill.map(|color| (color, style.fill_rule)),
+                        stroke: style.stroke_color().map(|color| (color, style.stroke_style())),
+                    };
+                    if style.visible && (shape.fill.is_some() || shape.stroke.is_some()) {
+                        self.document.shapes.push(shape);
+                    }
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn shape_path(&self, tag: &Tag) -> Result<Option<Path>, SvgError> {
+        let path = match tag.name.as_str() {
+            "rect" => {
+                let x = self.length(tag, "x", Axis::X)?;
+                let y = self.length(tag, "y", Axis::Y)?;
+                let width = self.length(tag, "width", Axis::X)?;
+                let height = self.length(tag, "height", Axis::Y)?;
+                if width <= 0.0 || height <= 0.0 {
+                    return Ok(None);
+                }
+                // A missing corner radius copies the other one
+                let (rx, ry) = match (tag.attribute("rx"), tag.attribute("ry")) {
+                    (None, None) => (0.0, 0.0),
+                    (Some(_), None) => (self.length(tag, "rx", Axis::X)?, self.length(tag, "rx", Axis::X)?),
+                    (None, Some(_)) => (self.length(tag, "ry", Axis::Y)?, self.length(tag, "ry", Axis::Y)?),
+                    (Some(_), Some(_)) => (self.length(tag, "rx", Axis::X)?, self.length(tag, "ry", Axis::Y)?),
+                };
+                rounded_rect(x, y, width, height, rx.clamp(0.0, width / 2.0), ry.clamp(0.0, height / 2.0))
+            }
+            "circle" => {
+                let center = Point::new(self.length(tag, "cx", Axis::X)?, self.length(tag, "cy", Axis::Y)?);
+                let r = self.length(tag, "r", Axis::Other)?;
+                if r <= 0.0 {
+                    return Ok(None);
+                }
+                Path::ellipse(center, r, r)
+            }
+            "ellipse" => {
+                let center = Point::new(self.length(tag, "cx", Axis::X)?, self.length(tag, "cy", Axis::Y)?);
+                let rx = self.length(tag, "rx", Axis::X)?;
+                let ry = self.length(tag, "ry", Axis::Y)?;
+                if rx <= 0.0 || ry <= 0.0 {
+                    return Ok(None);
+                }
+                Path::ellipse(center, rx, ry)
+            }
+            "line" => {
+                let mut path = Path::new();
+                path.move_to(Point::new(self.length(tag, "x1", Axis::X)?, self.length(tag, "y1", Axis::Y)?))
+                    .line_to(Point::new(self.length(tag, "x2", Axis::X)?, self.length(tag, "y2", Axis::Y)?));
+                path
+            }
+            "polyline" | "polygon" => {
+                // Like path data, a broken list draws the points before the
+                // error
+                let mut scanner = Scanner::new(tag.attribute("points").unwrap_or(""));
+                let mut points = Vec::new();
+                while let (Some(x), Some(y)) = (scanner.number(), scanner.number()) {
+                    points.push(Point::new(x, y));
+                }
+                if points.is_empty() {
+                    return Ok(None);
+                }
+                let mut path = Path::new();
+                path.move_to(points[0]);
+                for &p in &points[1..] {
+                    path.line_to(p);
+                }
+                if tag.name == "polygon" {
+                    path.close();
+                }
+                path
+            }
+            _ => parse_path_data(tag.attribute("d").unwrap_or("")),
+        };
+        Ok(if path.is_empty() { None } else { Some(path) })
+    }
+
+    // Lengths in user units, percentages are of the root view box
+    fn length(&self, tag: &Tag, name: &str, axis: Axis) -> Result<f32, SvgError> {
+        let value = match tag.attribute(name) {
+            Some(value) => value.trim(),
+            None => return Ok(0.0),
+        };
+        parse_length_or_percent(value, self.percent_reference(axis)).ok_or_else(|| invalid(name, value))
+    }
+
+    // What 100% is along `axis`
+    fn percent_reference(&self, axis: Axis) -> f32 {
+        let [_, _, width, height] = self.document.view_box;
+        match axis {
+            Axis::X => width,
+            Axis::Y => height,
+            Axis::Other => ((width * width + height * height) / 2.0).sqrt(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Axis {
+    X,
+    Y,
+    Other,
+}
+
+fn rounded_rect(x: f32, y: f32, width: f32, height: f32, rx: f32, ry: f32) -> Path {
+    if rx == 0.0 || ry == 0.0 {
+        return Path::rect(Point::new(x, y), Point::new(x + width, y + height));
+    }
+    let (right, bottom) = (x + width, y + height);
+    let mut path = Path::new();
+    path.move_to(Point::new(x + rx, y))
+        .line_to(Point::new(right - rx, y))
+        .arc_to(rx, ry, 0.0, false, true, Point::new(right, y + ry))
+        .line_to(Point::new(right, bottom - ry))
+        .arc_to(rx, ry, 0.0, false, true, Point::new(right - rx, bottom))
+        .line_to(Point::new(x + rx, bottom))
+        .arc_to(rx, ry, 0.0, false, true, Point::new(x, bottom - ry))
+        .line_to(Point::new(x, y + ry))
+        .arc_to(rx, ry, 0.0, false, true, Point::new(x + rx, y))
+        .close();
+    path
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Paint {
+    None,
+    Color(Rgba8),
+    CurrentColor,
+}
+
+// The presentation properties this importer understands
+#[derive(Clone, Debug)]
+struct Style {
+    fill: Paint,
+    fill_rule: FillRule,
+    fill_opacity: f32,
+    stroke: Paint,
+    stroke_opacity: f32,
+    stroke_width: f32,
+    line_join: LineJoin,
+    line_cap: LineCap,
+    miter_limit: f32,
+    color: Rgba8,
+    // `opacity` applies to groups as a whole; folding it into the shapes
+    // below is exact unless they overlap
+    opacity: f32,
+    display: bool,
+    visible: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            fill: Paint::Color(Rgba8::new(0, 0, 0, 255)),
+            fill_rule: FillRule::NonZero,
+            fill_opacity: 1.0,
+            stroke: Paint::None,
+            stroke_opacity: 1.0,
+            stroke_width: 1.0,
+            line_join: LineJoin::Miter,
+            line_cap: LineCap::Butt,
+            miter_limit: 4.0,
+            color: Rgba8::new(0, 0, 0, 255),
+            opacity: 1.0,
+            display: true,
+            visible: true,
+        }
+    }
+}
+
+impl Style {
+    // The element's style given its parent's: presentation attributes first,
+    // then the `style` attribute, which takes precedence. As SVG asks, a
+    // value that can't be parsed is ignored and the inherited one kept.
+    // `percent` is what a 100% stroke width is.
+    fn inherit(mut self, tag: &Tag, percent: f32) -> Style {
+        // Not inherited, only the element itself can turn it off
+        self.display = true;
+        // The element's own opacity is set once, whichever way it is given,
+        // and then combined with its parent's
+        let group_opacity = self.opacity;
+        self.opacity = 1.0;
+        for (name, value) in &tag.attributes {
+            self.set(name, value, percent);
+        }
+        if let Some(declarations) = tag.attribute("style") {
+            for declaration in declarations.split(';') {
+                if let Some((name, value)) = declaration.split_once(':') {
+                    let value = value.trim();
+                    self.set(name.trim(), value.strip_suffix("!important").unwrap_or(value).trim(), percent);
+                }
+            }
+        }
+        self.opacity *= group_opacity;
+        self
+    }
+
+    fn set(&mut self, name: &str, value: &str, percent: f32) {
+        let value = value.trim();
+        if value == "inherit" {
+            return;
+        }
+        fn update<T>(property: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *property = value;
+            }
+        }
+        match name {
+            "fill" => update(&mut self.fill, parse_paint(value)),
+            "stroke" => update(&mut self.stroke, parse_paint(value)),
+            "color" => update(&mut self.color, parse_color(value)),
+            "fill-opacity" => update(&mut self.fill_opacity, parse_opacity(value)),
+            "stroke-opacity" => update(&mut self.stroke_opacity, parse_opacity(value)),
+            "opacity" => update(&mut self.opacity, parse_opacity(value)),
+            "stroke-width" => {
+                update(&mut self.stroke_width, parse_length_or_percent(value, percent).filter(|&w| w >= 0.0));
+            }
+            "stroke-miterlimit" => update(&mut self.miter_limit, value.parse::<f32>().ok().filter(|&m| m >= 1.0)),
+            "fill-rule" => {
+                let rule = match value {
+                    "nonzero" => Some(FillRule::NonZero),
+                    "evenodd" => Some(FillRule::EvenOdd),
+                    _ => None,
+                };
+                update(&mut self.fill_rule, rule);
+            }
+            "stroke-linejoin" => {
+                let join = match value {
+                    "miter" | "miter-clip" | "arcs" => Some(LineJoin::Miter),
+                    "round" => Some(LineJoin::Round),
+                    "bevel" => Some(LineJoin::Bevel),
+                    _ => None,
+                };
+                update(&mut self.line_join, join);
+            }
+            "stroke-linecap" => {
+                let cap = match value {
+                    "butt" => Some(LineCap::Butt),
+                    "round" => Some(LineCap::Round),
+                    "square" => Some(LineCap::Square),
+                    _ => None,
+                };
+                update(&mut self.line_cap, cap);
+            }
+            "display" => self.display = value != "none",
+            "visibility" => self.visible = value == "visible",
+            _ => {}
+        }
+    }
+
+    fn resolve(&self, paint: Paint, opacity: f32) -> Option<Rgba8> {
+        let color = match paint {
+            Paint::None => return None,
+            Paint::Color(color) => color,
+            Paint::CurrentColor => self.color,
+        };
+        let alpha = (color.a as f32 * opacity * self.opacity).round() as u8;
+        if alpha == 0 {
+            return None;
+        }
+        Some(Rgba8::new(color.r, color.g, color.b, alpha))
+    }
+
+    fn fill_color(&self) -> Option<Rgba8> {
+        self.resolve(self.fill, self.fill_opacity)
+    }
+
+    fn stroke_color(&self) -> Option<Rgba8> {
+        if self.stroke_width == 0.0 {
+            return None;
+        }
+        self.resolve(self.stroke, self.stroke_opacity)
+    }
+
+    fn stroke_style(&self) -> StrokeStyle {
+        StrokeStyle {
+            width: self.stroke_width,
+            join: self.line_join,
+            cap: self.line_cap,
+            miter_limit: self.miter_limit,
+        }
+    }
+}
+
+fn parse_paint(value: &str) -> Option<Paint> {
+    match value {
+        "none" => Some(Paint::None),
+        "currentColor" => Some(Paint::CurrentColor),
+        // Gradients and patterns are not supported, use the fallback color
+        // if there is one
+        _ if value.starts_with("url(") => {
+            let fallback = value[value.find(')')? + 1..].trim();
+            if fallback.is_empty() {
+                Some(Paint::None)
+            } else {
+                parse_paint(fallback)
+            }
+        }
+        _ => parse_color(value).map(Paint::Color),
+    }
+}
+
+fn parse_opacity(value: &str) -> Option<f32> {
+    let opacity = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f32>().ok()? / 100.0,
+        None => value.parse::<f32>().ok()?,
+    };
+    Some(opacity.clamp(0.0, 1.0))
+}
+
+// Colors as CSS writes them: #rgb, #rgba, #rrggbb, #rrggbbaa, rgb(), rgba(),
+// hsl(), hsla(), the named colors and `transparent`
+pub fn parse_color(value: &str) -> Option<Rgba8> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('#') {
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        return match hex.len() {
+            3 => Some(Rgba8::new(digit(0) * 17, digit(1) * 17, digit(2) * 17, 255)),
+            4 => Some(Rgba8::new(digit(0) * 17, digit(1) * 17, digit(2) * 17, digit(3) * 17)),
+            6 => Some(Rgba8::new(byte(0), byte(2), byte(4), 255)),
+            8 => Some(Rgba8::new(byte(0), byte(2), byte(4), byte(6))),
+            _ => None,
+        };
+    }
+
+    let lower = value.to_ascii_lowercase();
+    if let Some(args) = lower.strip_prefix("rgba(").or_else(|| lower.strip_prefix("rgb(")) {
+        let args: Vec<&str> = args
+            .strip_suffix(')')?
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .collect();
+        let channel = |arg: &str| -> Option<u8> {
+            let v = match arg.strip_suffix('%') {
+                Some(percent) => percent.parse::<f32>().ok()? * 2.55,
+                None => arg.parse::<f32>().ok()?,
+            };
+            Some(v.clamp(0.0, 255.0).round() as u8)
+        };
+        let alpha = match args.get(3) {
+            Some(arg) => (parse_opacity(arg)? * 255.0).round() as u8,
+            None => 255,
+        };
+        return match args.len() {
+            3 | 4 => Some(Rgba8::new(channel(args[0])?, channel(args[1])?, channel(args[2])?, alpha)),
+            _ => None,
+        };
+    }
+
+    if let Some(args) = lower.strip_prefix("hsla(").or_else(|| lower.strip_prefix("hsl(")) {
+        let args: Vec<&str> = args
+            .strip_suffix(')')?
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .collect();
+        if !(3..=4).contains(&args.len()) {
+            return None;
+        }
+        let hue = args[0].strip_suffix("deg").unwrap_or(args[0]).parse::<f32>().ok()?.rem_euclid(360.0) / 60.0;
+        let fraction = |arg: &str| Some((arg.strip_suffix('%')?.parse::<f32>().ok()? / 100.0).clamp(0.0, 1.0));
+        let (saturation, lightness) = (fraction(args[1])?, fraction(args[2])?);
+        let alpha = match args.get(3) {
+            Some(arg) => (parse_opacity(arg)? * 255.0).round() as u8,
+            None => 255,
+        };
+        // The chroma split over the two channels either side of the hue
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = lightness - chroma / 2.0;
+        let channel = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        return Some(Rgba8::new(channel(r), channel(g), channel(b), alpha));
+    }
+
+    if lower == "transparent" {
+        return Some(Rgba8::TRANSPARENT);
+    }
+    let index = NAMED_COLORS.binary_search_by_key(&lower.as_str(), |&(name, _)| name).ok()?;
+    let [r, g, b] = NAMED_COLORS[index].1;
+    Some(Rgba8::new(r, g, b, 255))
+}
+
+// The CSS color keywords, sorted by name
+const NAMED_COLORS: [(&str, [u8; 3]); 148] = [
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 148]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 148]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+// A number with an optional absolute unit, converted to CSS pixels
+fn parse_length(value: &str) -> Option<f32> {
+    const UNITS: [(&str, f32); 7] = [
+        ("px", 1.0),
+        ("pt", 96.0 / 72.0),
+        ("pc", 16.0),
+        ("in", 96.0),
+        ("cm", 96.0 / 2.54),
+        ("mm", 96.0 / 25.4),
+        ("em", 16.0),
+    ];
+    let value = value.trim();
+    let (number, scale) = UNITS
+        .iter()
+        .find_map(|&(unit, scale)| value.strip_suffix(unit).map(|number| (number, scale)))
+        .unwrap_or((value, 1.0));
+    number.trim().parse::<f32>().ok().filter(|v| v.is_finite()).map(|v| v * scale)
+}
+
+// A length, or a percentage of `reference`
+fn parse_length_or_percent(value: &str, reference: f32) -> Option<f32> {
+    match value.trim().strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f32>().ok().filter(|v| v.is_finite()).map(|p| p / 100.0 * reference),
+        None => parse_length(value),
+    }
+}
+
+fn parse_numbers(value: &str) -> Option<Vec<f32>> {
+    let mut scanner = Scanner::new(value);
+    let mut numbers = Vec::new();
+    while !scanner.at_end() {
+        numbers.push(scanner.number()?);
+    }
+    Some(numbers)
+}
+
+// A list such as "translate(10 20) rotate(45, 5, 5)", applied right to left
+// like nested groups
+fn parse_transform(value: &str) -> Option<Transform> {
+    let separators = |c: char| c.is_whitespace() || c == ',';
+    let mut result = Transform::IDENTITY;
+    let mut rest = value.trim_start_matches(separators);
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('(')?;
+        let (args, after) = after.split_once(')')?;
+        let transform = match (name.trim(), parse_numbers(args)?.as_slice()) {
+            ("matrix", &[a, b, c, d, e, f]) => Transform { a, b, c, d, e, f },
+            ("translate", &[tx]) => Transform::translate(tx, 0.0),
+            ("translate", &[tx, ty]) => Transform::translate(tx, ty),
+            ("scale", &[s]) => Transform::scale(s, s),
+            ("scale", &[sx, sy]) => Transform::scale(sx, sy),
+            ("rotate", &[angle]) => Transform::rotate(angle.to_radians()),
+            ("rotate", &[angle, cx, cy]) => Transform::translate(cx, cy)
+                .multiply(Transform::rotate(angle.to_radians()))
+                .multiply(Transform::translate(-cx, -cy)),
+            ("skewX", &[angle]) => Transform::skew_x(angle.to_radians()),
+            ("skewY", &[angle]) => Transform::skew_y(angle.to_radians()),
+            _ => return None,
+        };
+        result = result.multiply(transform);
+        rest = after.trim_start_matches(separators);
+    }
+    Some(result)
+}
+
+// Path data as in the `d` attribute. Errors are not fatal: as the spec asks,
+// everything up to the first bad segment is kept.
+pub fn parse_path_data(data: &str) -> Path {
+    let mut scanner = Scanner::new(data);
+    let mut state = PathState::default();
+    let mut command: Option<u8> = None;
+    while !scanner.at_end() {
+        let next = match scanner.command() {
+            Some(c) => c,
+            // Coordinates after a moveto are implicit linetos
+            None => match command {
+                Some(b'M') => b'L',
+                Some(b'm') => b'l',
+                Some(c) if c != b'Z' && c != b'z' => c,
+                _ => break,
+            },
+        };
+        if state.path.is_empty() && next != b'M' && next != b'm' {
+            break;
+        }
+        if state.segment(&mut scanner, next).is_none() {
+            break;
+        }
+        command = Some(next);
+    }
+    state.path
+}
+
+#[derive(Default)]
+struct PathState {
+    path: Path,
+    current: Point,
+    start: Point,
+    // Previous control points, for the smooth curve commands to reflect
+    cubic_control: Option<Point>,
+    quad_control: Option<Point>,
+    closed: bool,
+}
+
+impl PathState {
+    fn segment(&mut self, s: &mut Scanner, command: u8) -> Option<()> {
+        let origin = if command.is_ascii_lowercase() { self.current } else { Point::default() };
+        let point = |s: &mut Scanner| Some(origin + Point::new(s.number()?, s.number()?));
+        let upper = command.to_ascii_uppercase();
+
+        // Drawing after a closepath starts from the closed subpath's start
+        if self.closed && upper != b'M' && upper != b'Z' {
+            self.path.move_to(self.start);
+        }
+        self.closed = false;
+
+        let (mut cubic_control, mut quad_control) = (None, None);
+        match upper {
+            b'M' => {
+                let p = point(s)?;
+                self.path.move_to(p);
+                self.start = p;
+                self.current = p;
+            }
+            b'L' => {
+                self.current = point(s)?;
+                self.path.line_to(self.current);
+            }
+            b'H' => {
+                self.current = Point::new(origin.x + s.number()?, self.current.y);
+                self.path.line_to(self.current);
+            }
+            b'V' => {
+                self.current = Point::new(self.current.x, origin.y + s.number()?);
+                self.path.line_to(self.current);
+            }
+            b'C' | b'S' => {
+                let c1 = match upper {
+                    b'C' => point(s)?,
+                    _ => self.cubic_control.map_or(self.current, |c| self.current * 2.0 - c),
+                };
+                let c2 = point(s)?;
+                self.current = point(s)?;
+                self.path.cubic_to(c1, c2, self.current);
+                cubic_control = Some(c2);
+            }
+            b'Q' | b'T' => {
+                let c = match upper {
+                    b'Q' => point(s)?,
+                    _ => self.quad_control.map_or(self.current, |c| self.current * 2.0 - c),
+                };
+                self.current = point(s)?;
+                self.path.quad_to(c, self.current);
+                quad_control = Some(c);
+            }
+            b'A' => {
+                let (rx, ry, rotation) = (s.number()?, s.number()?, s.number()?);
+                let (large_arc, sweep) = (s.flag()?, s.flag()?);
+                let end = point(s)?;
+                self.path.arc_to(rx, ry, rotation.to_radians(), large_arc, sweep, end);
+                self.current = end;
+            }
+            b'Z' => {
+                self.path.close();
+                self.current = self.start;
+                self.closed = true;
+            }
+            _ => return None,
+        }
+        self.cubic_control = cubic_control;
+        self.quad_control = quad_control;
+        Some(())
+    }
+}
+
+// Reads the compact number syntax shared by path data, point lists and
+// transforms, where "1.5.5-2e1" is three numbers
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(text: &'a str) -> Scanner<'a> {
+        Scanner { bytes: text.as_bytes(), pos: 0 }
+    }
+
+    fn skip_separators(&mut self) {
+        while self.pos < self.bytes.len() && (self.bytes[self.pos].is_ascii_whitespace() || self.bytes[self.pos] == b',') {
+            self.pos += 1;
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_separators();
+        self.pos >= self.bytes.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn command(&mut self) -> Option<u8> {
+        self.skip_separators();
+        let c = self.peek().filter(|c| c.is_ascii_alphabetic() && *c != b'e' && *c != b'E')?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self) -> Option<f32> {
+        self.skip_separators();
+        let start = self.pos;
+        if let Some(b'+' | b'-') = self.peek() {
+            self.pos += 1;
+        }
+        let mut digits = self.digits();
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            digits += self.digits();
+        }
+        if digits == 0 {
+            self.pos = start;
+            return None;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            let mantissa_end = self.pos;
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                self.pos = mantissa_end;
+            }
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).ok()?;
+        text.parse().ok().filter(|v: &f32| v.is_finite())
+    }
+
+    // Arc flags are single characters and need no separator after them
+    fn flag(&mut self) -> Option<bool> {
+        self.skip_separators();
+        let flag = match self.peek()? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(flag)
+    }
+}
+
+struct Tag {
+    name: String,
+    attributes: Vec<(String, String)>,
+    self_closing: bool,
+}
+
+impl Tag {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+enum Token {
+    Start(Tag),
+    End(String),
+}
+
+// Just enough XML to walk the element tree: text, comments, processing
+// instructions, CDATA and the doctype are skipped, namespace prefixes on
+// element names are dropped.
+struct XmlReader<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl XmlReader<'_> {
+    fn error(&self, message: &'static str) -> SvgError {
+        SvgError::Xml { offset: self.pos, message }
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.pos..]
+    }
+
+    fn skip_past(&mut self, end: &str, message: &'static str) -> Result<(), SvgError> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => Err(self.error(message)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn name(&mut self) -> Result<String, SvgError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let name = rest[..len].to_string();
+        self.pos += len;
+        Ok(name)
+    }
+
+    fn expect(&mut self, c: char, message: &'static str) -> Result<(), SvgError> {
+        if !self.rest().starts_with(c) {
+            return Err(self.error(message));
+        }
+        self.pos += c.len_utf8();
+        Ok(())
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, SvgError> {
+        loop {
+            match self.rest().find('<') {
+                Some(i) => self.pos += i,
+                None => return Ok(None),
+            }
+            let rest = self.rest();
+            if rest.starts_with("<!--") {
+                self.skip_past("-->", "unterminated comment")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.skip_past("]]>", "unterminated cdata section")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>", "unterminated processing instruction")?;
+            } else if rest.starts_with("<!") {
+                // A doctype can carry an internal subset in brackets
+                let bracket = rest.find('[');
+                let close = rest.find('>');
+                if let (Some(bracket), Some(close)) = (bracket, close) {
+                    if bracket < close {
+                        self.pos += bracket;
+                        self.skip_past("]", "unterminated doctype")?;
+                    }
+                }
+                self.skip_past(">", "unterminated doctype")?;
+            } else if rest.starts_with("</") {
+                self.pos += 2;
+                let name = local_name(self.name()?);
+                self.skip_whitespace();
+                self.expect('>', "expected `>` after closing tag name")?;
+                return Ok(Some(Token::End(name)));
+            } else {
+                self.pos += 1;
+                return self.start_tag().map(|tag| Some(Token::Start(tag)));
+            }
+        }
+    }
+
+    fn start_tag(&mut self) -> Result<Tag, SvgError> {
+        let name = local_name(self.name()?);
+        let mut attributes = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(Tag { name, attributes, self_closing: true });
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                return Ok(Tag { name, attributes, self_closing: false });
+            }
+            if self.rest().is_empty() {
+                return Err(self.error("unterminated start tag"));
+            }
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            self.expect('=', "expected `=` after attribute name")?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(q @ ('"' | '\'')) => q,
+                _ => return Err(self.error("attribute value must be quoted")),
+            };
+            self.pos += 1;
+            let len = match self.rest().find(quote) {
+                Some(len) => len,
+                None => return Err(self.error("unterminated attribute value")),
+            };
+            let value = decode_entities(&self.rest()[..len]);
+            self.pos += len + 1;
+            attributes.push((attribute, value));
+        }
+    }
+}
+
+// "svg:rect" is a rect
+fn local_name(name: String) -> String {
+    match name.split_once(':') {
+        Some((_, local)) => local.to_string(),
+        None => name,
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "lt" => '<',
+                "gt" => '>',
+                "amp" => '&',
+                "quot" => '"',
+                "apos" => '\'',
+                entity => {
+                    let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            // Not an entity we know, keep it as written
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::PathCommand;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4
+    }
+
+    fn only_shape(source: &str) -> SvgShape {
+        let document = parse_svg(source).unwrap();
+        assert_eq!(document.shapes().len(), 1);
+        document.shapes()[0].clone()
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#f80"), Some(Rgba8::new(255, 136, 0, 255)));
+        assert_eq!(parse_color("#f808"), Some(Rgba8::new(255, 136, 0, 136)));
+        assert_eq!(parse_color("#12ab9F"), Some(Rgba8::new(0x12, 0xab, 0x9f, 255)));
+        assert_eq!(parse_color("#12ab9f80"), Some(Rgba8::new(0x12, 0xab, 0x9f, 0x80)));
+        assert_eq!(parse_color("rgb(10, 20, 30)"), Some(Rgba8::new(10, 20, 30, 255)));
+        assert_eq!(parse_color("rgb(100% 0% 50% / 0.5)"), Some(Rgba8::new(255, 0, 128, 128)));
+        assert_eq!(parse_color("rgba(300,-5,0,2)"), Some(Rgba8::new(255, 0, 0, 255)));
+        assert_eq!(parse_color("hsl(120, 100%, 25%)"), Some(Rgba8::new(0, 128, 0, 255)));
+        assert_eq!(parse_color("hsl(-120deg 100% 50%)"), Some(Rgba8::new(0, 0, 255, 255)));
+        assert_eq!(parse_color("hsla(0, 0%, 100%, 25%)"), Some(Rgba8::new(255, 255, 255, 64)));
+        assert_eq!(parse_color("rebeccapurple"), Some(Rgba8::new(102, 51, 153, 255)));
+        assert_eq!(parse_color("LightGoldenrodYellow"), Some(Rgba8::new(250, 250, 210, 255)));
+        assert_eq!(parse_color("transparent"), Some(Rgba8::TRANSPARENT));
+        for bad in ["", "#12", "#ggg", "rgb(1, 2)", "hsl(0, 50, 50)", "notacolor", "rgb(1 2 3"] {
+            assert_eq!(parse_color(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn named_colors_are_sorted() {
+        assert!(NAMED_COLORS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn parses_path_data() {
+        let path = parse_path_data("M10 20L30 40h5v-5z");
+        assert_eq!(
+            path.commands(),
+            [
+                PathCommand::MoveTo(Point::new(10.0, 20.0)),
+                PathCommand::LineTo(Point::new(30.0, 40.0)),
+                PathCommand::LineTo(Point::new(35.0, 40.0)),
+                PathCommand::LineTo(Point::new(35.0, 35.0)),
+                PathCommand::Close,
+            ]
+        );
+
+        // Implicit linetos after a relative moveto, and packed numbers
+        let path = parse_path_data("m1.5.5 2-1e0,0 2");
+        assert_eq!(
+            path.commands(),
+            [
+                PathCommand::MoveTo(Point::new(1.5, 0.5)),
+                PathCommand::LineTo(Point::new(3.5, -0.5)),
+                PathCommand::LineTo(Point::new(3.5, 1.5)),
+            ]
+        );
+
+        // Smooth curves reflect the previous control point
+        let path = parse_path_data("M0 0C0 10 10 10 10 0S20 -10 20 0");
+        assert_eq!(
+            path.commands()[2],
+            PathCommand::CubicTo(Point::new(10.0, -10.0), Point::new(20.0, -10.0), Point::new(20.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn path_data_errors_keep_what_came_before() {
+        let path = parse_path_data("M0 0 L10 0 L20 Q");
+        assert_eq!(path.commands(), [PathCommand::MoveTo(Point::new(0.0, 0.0)), PathCommand::LineTo(Point::new(10.0, 0.0))]);
+        assert!(parse_path_data("L10 10").is_empty());
+        assert!(parse_path_data("").is_empty());
+    }
+
+    #[test]
+    fn parses_transforms() {
+        let t = parse_transform("translate(10 20) scale(2)").unwrap();
+        assert!(close(t.apply(Point::new(1.0, 1.0)), Point::new(12.0, 22.0)));
+
+        let t = parse_transform("rotate(90, 5, 5)").unwrap();
+        assert!(close(t.apply(Point::new(10.0, 5.0)), Point::new(5.0, 10.0)));
+
+        let t = parse_transform(" matrix(1,0,0,1,3,4),skewX(45) ").unwrap();
+        assert!(close(t.apply(Point::new(0.0, 1.0)), Point::new(4.0, 5.0)));
+
+        assert!(parse_transform("").is_some());
+        assert!(parse_transform("scale(1, 2, 3)").is_none());
+        assert!(parse_transform("wobble(1)").is_none());
+        assert!(parse_transform("translate(1").is_none());
+    }
+
+    #[test]
+    fn invalid_presentation_values_keep_the_inherited_ones() {
+        let shape = only_shape(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 100">
+                <g fill="#336699" stroke="red" stroke-width="3" stroke-linejoin="round">
+                    <rect width="10" height="10" fill="#nope" stroke-width="wide" stroke-linejoin="sharp"
+                        style="stroke-linecap: pointy; fill-rule: sometimes; stroke: bogus(1)"/>
+                </g>
+            </svg>"##,
+        );
+        assert_eq!(shape.fill, Some((Rgba8::new(0x33, 0x66, 0x99, 255), FillRule::NonZero)));
+        let (stroke, style) = shape.stroke.unwrap();
+        assert_eq!(stroke, Rgba8::new(255, 0, 0, 255));
+        assert_eq!(style.width, 3.0);
+        assert_eq!(style.join, LineJoin::Round);
+        assert_eq!(style.cap, LineCap::Butt);
+    }
+
+    #[test]
+    fn stroke_widths_take_percentages() {
+        let shape = only_shape(
+            r#"<svg viewBox="0 0 300 400"><line x1="0" y1="0" x2="10" y2="10" stroke="black" stroke-width="2%"/></svg>"#,
+        );
+        // 2% of the view box diagonal over root 2
+        let (_, style) = shape.stroke.unwrap();
+        assert!((style.width - 0.02 * (300.0f32 * 300.0 + 400.0 * 400.0).sqrt() / 2.0f32.sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn opacity_applies_once_per_element() {
+        let shape = only_shape(r#"<svg><rect width="1" height="1" opacity="0.5" style="opacity: 0.5"/></svg>"#);
+        assert_eq!(shape.fill.unwrap().0.a, 128);
+
+        let shape = only_shape(r#"<svg><g opacity="0.5"><rect width="1" height="1" opacity="0.5"/></g></svg>"#);
+        assert_eq!(shape.fill.unwrap().0.a, 64);
+    }
+
+    #[test]
+    fn broken_markup_is_still_an_error() {
+        assert!(matches!(parse_svg("<svg><rect></svg>"), Err(SvgError::MismatchedTag { .. })));
+        assert!(matches!(parse_svg("<svg><g>"), Err(SvgError::UnclosedTag(_))));
+        assert!(matches!(parse_svg("<html/>"), Err(SvgError::NotSvg(_))));
+        assert!(matches!(parse_svg("<svg viewBox='0 0 -1 1'/>"), Err(SvgError::InvalidAttribute { .. })));
+    }
+}
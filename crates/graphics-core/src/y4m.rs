@@ -0,0 +1,233 @@
+use std::{fmt, io::Write, str::FromStr};
+
+use crate::{
+    color::{convert_pixel, Pixel, Rgb8},
+    files::ImageError,
+    image::Image,
+};
+
+// How much of the color resolution is kept
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Chroma {
+    // Chroma at half the width and height, centered between the luma samples
+    // it covers. What most encoders take.
+    #[default]
+    Subsampled420,
+    Full444,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseChromaError(String);
+
+impl fmt::Display for ParseChromaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown chroma `{}`, expected 420 or 444", self.0)
+    }
+}
+
+impl std::error::Error for ParseChromaError {}
+
+impl FromStr for Chroma {
+    type Err = ParseChromaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "420" => Ok(Chroma::Subsampled420),
+            "444" => Ok(Chroma::Full444),
+            _ => Err(ParseChromaError(s.to_string())),
+        }
+    }
+}
+
+// BT.709 luma weights
+const KR: f32 = 0.2126;
+const KB: f32 = 0.0722;
+
+// BT.709 in limited range, Y in 16..235 and Cb, Cr in 16..240, from pixel
+// values taken as already gamma encoded
+fn to_ycbcr([r, g, b]: [f32; 3]) -> [u8; 3] {
+    let y = KR * r + (1.0 - KR - KB) * g + KB * b;
+    let cb = (b - y) / (2.0 * (1.0 - KB));
+    let cr = (r - y) / (2.0 * (1.0 - KR));
+    [16.0 + 219.0 * y, 128.0 + 224.0 * cb, 128.0 + 224.0 * cr].map(|v| v.round().clamp(0.0, 255.0) as u8)
+}
+
+// Streams frames as YUV4MPEG2, uncompressed video that encoders read from
+// a pipe. The header goes out with the first frame.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    frame_rate: (u32, u32),
+    chroma: Chroma,
+    started: bool,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(writer: W, width: usize, height: usize) -> Y4mWriter<W> {
+        Y4mWriter { writer, width, height, frame_rate: (25, 1), chroma: Chroma::default(), started: false }
+    }
+
+    // Frames per second as a fraction, like 30000/1001
+    pub fn with_frame_rate(mut self, numerator: u32, denominator: u32) -> Self {
+        self.frame_rate = (numerator, denominator);
+        self
+    }
+
+    pub fn with_chroma(mut self, chroma: Chroma) -> Self {
+        self.chroma = chroma;
+        self
+    }
+
+    pub fn write_frame<P: Pixel>(&mut self, image: &Image<P>) -> Result<(), ImageError> {
+        let (width, height) = (self.width, self.height);
+        if image.width() != width || image.height() != height {
+            return Err(ImageError::InvalidDimensions { width: image.width() as i64, height: image.height() as i64 });
+        }
+        if !self.started {
+            let (numerator, denominator) = self.frame_rate;
+            if width == 0 || height == 0 {
+                return Err(ImageError::InvalidDimensions { width: width as i64, height: height as i64 });
+            }
+            if numerator == 0 || denominator == 0 {
+                return Err(ImageError::InvalidFrameRate { numerator, denominator });
+            }
+            let chroma = match self.chroma {
+                Chroma::Subsampled420 => "420jpeg",
+                Chroma::Full444 => "444",
+            };
+            writeln!(
+                self.writer,
+                "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C{} XCOLORRANGE=LIMITED",
+                width, height, numerator, denominator, chroma
+            )?;
+            self.started = true;
+        }
+
+        let rgb = |row: usize, col: usize| {
+            let p: Rgb8 = convert_pixel(image.row(row)[col]);
+            [p.r, p.g, p.b].map(|v| v as f32 / 255.0)
+        };
+        let mut y = Vec::with_capacity(width * height);
+        for row in 0..height {
+            y.extend((0..width).map(|col| to_ycbcr(rgb(row, col))[0]));
+        }
+
+        // The average of each 2 by 2 block, less at odd edges
+        let (chroma_width, chroma_height, step) = match self.chroma {
+            Chroma::Subsampled420 => (width.div_ceil(2), height.div_ceil(2), 2),
+            Chroma::Full444 => (width, height, 1),
+        };
+        let mut cb = Vec::with_capacity(chroma_width * chroma_height);
+        let mut cr = Vec::with_capacity(chroma_width * chroma_height);
+        for row in 0..chroma_height {
+            for col in 0..chroma_width {
+                let mut sum = [0.0; 3];
+                let mut count = 0.0;
+                for r in row * step..(row * step + step).min(height) {
+                    for c in col * step..(col * step + step).min(width) {
+                        let p = rgb(r, c);
+                        (0..3).for_each(|i| sum[i] += p[i]);
+                        count += 1.0;
+                    }
+                }
+                let [_, u, v] = to_ycbcr(sum.map(|s| s / count));
+                cb.push(u);
+                cr.push(v);
+            }
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&y)?;
+        self.writer.write_all(&cb)?;
+        self.writer.write_all(&cr)?;
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> Result<W, ImageError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(image: &Image<Rgb8>, chroma: Chroma) -> Vec<u8> {
+        let mut writer = Y4mWriter::new(Vec::new(), image.width(), image.height()).with_chroma(chroma);
+        writer.write_frame(image).unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    // The planes of the first frame, after the header and FRAME line
+    fn planes(bytes: &[u8]) -> &[u8] {
+        let header = bytes.iter().position(|&b| b == b'\n').unwrap() + 1;
+        assert_eq!(&bytes[header..header + 6], b"FRAME\n");
+        &bytes[header + 6..]
+    }
+
+    #[test]
+    fn header_names_size_rate_and_chroma() {
+        let image = Image::<Rgb8>::new(3, 2);
+        let mut writer = Y4mWriter::new(Vec::new(), 3, 2).with_frame_rate(30000, 1001);
+        writer.write_frame(&image).unwrap();
+        writer.write_frame(&image).unwrap();
+        let bytes = writer.into_inner().unwrap();
+        let header = b"YUV4MPEG2 W3 H2 F30000:1001 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+        assert!(bytes.starts_with(header));
+        // 6 luma and 2 times 2 chroma samples per frame, the header only once
+        assert_eq!(bytes.len(), header.len() + 2 * (6 + 6 + 2 * 2));
+
+        let bytes = encode(&image, Chroma::Full444);
+        assert!(bytes.starts_with(b"YUV4MPEG2 W3 H2 F25:1 Ip A1:1 C444 XCOLORRANGE=LIMITED\n"));
+        assert_eq!(planes(&bytes).len(), 3 * 6);
+    }
+
+    #[test]
+    fn limited_range_levels() {
+        let cases = [
+            (Rgb8::WHITE, [235, 128, 128]),
+            (Rgb8::BLACK, [16, 128, 128]),
+            // Pure red has the largest Cr, pure blue the largest Cb
+            (Rgb8::RED, [63, 102, 240]),
+            (Rgb8::new(0, 0, 255), [32, 240, 118]),
+        ];
+        for (color, expected) in cases {
+            let image = Image::from_pixels(1, 1, vec![color]).unwrap();
+            assert_eq!(planes(&encode(&image, Chroma::Full444)), expected, "{:?}", color);
+        }
+    }
+
+    #[test]
+    fn chroma_averages_blocks_and_odd_edges() {
+        // 3 by 3: a full block, a 1 wide column, a 1 high row and a corner
+        let (w, k) = (Rgb8::WHITE, Rgb8::BLACK);
+        let image = Image::from_pixels(3, 3, vec![w, k, w, k, w, k, w, w, k]).unwrap();
+        let bytes = encode(&image, Chroma::Subsampled420);
+        let data = planes(&bytes);
+        assert_eq!(data.len(), 9 + 2 * 4);
+        assert_eq!(&data[..9], [235, 16, 235, 16, 235, 16, 235, 235, 16]);
+        // Gray averages have no color, whatever the block covers
+        assert_eq!(&data[9..], [128; 8]);
+
+        // A red and a blue half average to their mix, not to either side
+        let (r, b) = (Rgb8::RED, Rgb8::new(0, 0, 255));
+        let image = Image::from_pixels(3, 1, vec![r, b, r]).unwrap();
+        let bytes = encode(&image, Chroma::Subsampled420);
+        let mix = to_ycbcr([0.5, 0.0, 0.5]);
+        assert_eq!(&planes(&bytes)[3..], [mix[1], 102, mix[2], 240]);
+    }
+
+    #[test]
+    fn zero_frame_rate_is_an_error() {
+        let image = Image::<Rgb8>::new(2, 2);
+        let mut writer = Y4mWriter::new(Vec::new(), 2, 2).with_frame_rate(0, 1);
+        assert!(matches!(
+            writer.write_frame(&image),
+            Err(ImageError::InvalidFrameRate { numerator: 0, denominator: 1 })
+        ));
+        let mut writer = Y4mWriter::new(Vec::new(), 2, 2).with_frame_rate(25, 0);
+        assert!(matches!(writer.write_frame(&image), Err(ImageError::InvalidFrameRate { .. })));
+    }
+}
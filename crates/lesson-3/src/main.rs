@@ -9,7 +9,7 @@ fn main() {
             break;
         }
         bytes[i as usize] = i;
-        i = i + 1;
+        i += 1;
     }
 
     println!("{:?}", bytes);
@@ -1,6 +1,6 @@
-use std::{fs, io::Write};
+use std::{fs, io::{self, Write}, process::ExitCode};
 
-fn main() {
+fn main() -> ExitCode {
     // [0,0,0,0,0,0,0,0,0,0,0,0,0,0 ...]
     let mut bytes: [u8; 128] = [0; 128];
 
@@ -10,16 +10,25 @@ fn main() {
             break;
         }
         bytes[i as usize] = i;
-        i = i + 1;
+        i += 1;
     }
 
+    if let Err(err) = write_bytes("bytes.txt", &bytes) {
+        eprintln!("failed to write bytes.txt: {}", err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn write_bytes(path: &str, bytes: &[u8]) -> io::Result<()> {
     let mut file = fs::OpenOptions::new()
         .create(true)
         .write(true)
-        .open("bytes.txt")
-        .unwrap();
+        .truncate(true)
+        .open(path)?;
 
     for byte in bytes {
-        file.write(&[byte]).unwrap();
+        file.write_all(&[*byte])?;
     }
+    Ok(())
 }
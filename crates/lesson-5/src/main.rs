@@ -1,61 +1,94 @@
-use std::fs;
-use byteorder::WriteBytesExt;
+use std::{f32::consts::PI, process::ExitCode};
+use graphics_core::{
+    canvas::Canvas,
+    composite::BlendMode,
+    files::{write_image_file, BmpOptions, RowOrder},
+    paint::{Gradient, Interpolation, Spread},
+    Image, Point, Rgb8, Rgba8, Transform,
+};
 
-const HEIGHT: usize = 256;
-const WIDTH: usize = 256;
-const BYTES_PER_PIXEL: usize = 3;
-const ARRAY_LENGTH: usize = HEIGHT * WIDTH * BYTES_PER_PIXEL;
+const HEIGHT: usize = 512;
+const WIDTH: usize = 512;
 
-fn main() {
-    // [0,0,0,0,0,0,0,0,0,0,0 ...]
-    // [0 0 0   0 0 0   0 0 0 ...]
-    //  ^         ^
-    //  0         4
-    let mut image_bytes: [u8; ARRAY_LENGTH] = [0; ARRAY_LENGTH];
+// Red rising with the row and green with the column, from two gradients
+// added together
+fn draw_ramps(canvas: &mut Canvas<Rgb8>) {
+    let (width, height) = (WIDTH as f32, HEIGHT as f32);
+    let (min, max) = (Point::new(0.0, 0.0), Point::new(width, height));
+    let red = Gradient::linear(min, Point::new(0.0, height))
+        .with_stop(0.0, Rgba8::new(0, 0, 0, 255))
+        .with_stop(1.0, Rgba8::new(255, 0, 0, 255));
+    let green = Gradient::linear(min, Point::new(width, 0.0))
+        .with_stop(0.0, Rgba8::new(0, 0, 0, 255))
+        .with_stop(1.0, Rgba8::new(0, 255, 0, 255));
+    canvas.fill_rect(min, max, red);
+    canvas.set_blend_mode(BlendMode::Additive);
+    canvas.fill_rect(min, max, green);
+    canvas.set_blend_mode(BlendMode::Normal);
+}
 
-    for row in 0..HEIGHT {
-        for column in 0..WIDTH {
-            // [0 0 0   0 0 0   0 0 0 ...]
-            //          ^ ^ ^
-            //         pb g r
-            let pixel_pointer: usize = WIDTH * BYTES_PER_PIXEL * row + BYTES_PER_PIXEL * column;
-            let blue_pointer = pixel_pointer;
-            let green_pointer = pixel_pointer + 1;
-            let red_pointer = pixel_pointer + 2;
-        
-            image_bytes[red_pointer] = row as u8;
-            image_bytes[green_pointer] = column as u8;
-            image_bytes[blue_pointer] = 0;
-        }
+fn parse_shape(s: &str) -> Option<Gradient> {
+    let (width, height) = (WIDTH as f32, HEIGHT as f32);
+    let center = Point::new(width / 2.0, height / 2.0);
+    // Small enough that the spread mode shows around them
+    match s {
+        "linear" => Some(Gradient::linear(Point::new(width * 0.4, height * 0.4), Point::new(width * 0.6, height * 0.6))),
+        "radial" => Some(Gradient::radial(center, width * 0.15)),
+        "conic" => Some(Gradient::conic(center, 0.0)),
+        _ => None,
     }
+}
 
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open("image.bmp")
-        .unwrap();
+// usage: lesson-5 [linear|radial|conic [pad|repeat|reflect] [linear|oklab]]
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut image = Image::new(WIDTH, HEIGHT);
+    let mut canvas = Canvas::new(&mut image);
 
-    // File header
-    file.write_u8('B' as u8).unwrap();
-    file.write_u8('M' as u8).unwrap();
-    file.write_u32::<byteorder::LE>(14 + 40 + ARRAY_LENGTH as u32).unwrap(); // file size
-    file.write_u32::<byteorder::LE>(0).unwrap(); // reserved ??
-    file.write_u32::<byteorder::LE>(14 + 40 as u32).unwrap(); // content offset
+    if let Some(shape) = args.first() {
+        let Some(gradient) = parse_shape(shape) else {
+            eprintln!("unknown gradient `{}`, expected linear, radial or conic", shape);
+            return ExitCode::FAILURE;
+        };
+        let spread = match args.get(1).map_or(Ok(Spread::default()), |arg| arg.parse()) {
+            Ok(spread) => spread,
+            Err(err) => {
+                eprintln!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+        let interpolation = match args.get(2).map_or(Ok(Interpolation::default()), |arg| arg.parse()) {
+            Ok(interpolation) => interpolation,
+            Err(err) => {
+                eprintln!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+        let gradient = gradient
+            .with_spread(spread)
+            .with_interpolation(interpolation)
+            .with_stop(0.0, Rgba8::new(255, 32, 32, 255))
+            .with_stop(0.5, Rgba8::new(255, 230, 0, 255))
+            .with_stop(1.0, Rgba8::new(0, 64, 255, 255));
 
-    // Info header
-    file.write_u32::<byteorder::LE>(40 as u32).unwrap(); // info header size
-    file.write_u32::<byteorder::LE>(WIDTH as u32).unwrap(); // width
-    file.write_u32::<byteorder::LE>(HEIGHT as u32).unwrap(); // height
-    file.write_u16::<byteorder::LE>(1).unwrap(); // number of color planes???
-    file.write_u16::<byteorder::LE>(BYTES_PER_PIXEL as u16 * 8).unwrap(); // bits per pixel
-    file.write_u32::<byteorder::LE>(0).unwrap(); // compression
-    file.write_u32::<byteorder::LE>(ARRAY_LENGTH as u32).unwrap(); // image size
-    file.write_u32::<byteorder::LE>(0).unwrap(); // horizontal res
-    file.write_u32::<byteorder::LE>(0).unwrap(); // vertical res
-    file.write_u32::<byteorder::LE>(0).unwrap(); // colors in color table
-    file.write_u32::<byteorder::LE>(0).unwrap(); // important color count
+        // The same gradient on the background and, turned half way around
+        // the center, on a circle in front of it
+        let (width, height) = (WIDTH as f32, HEIGHT as f32);
+        let center = Point::new(width / 2.0, height / 2.0);
+        let turn = Transform::translate(center.x, center.y)
+            .multiply(Transform::rotate(PI))
+            .multiply(Transform::translate(-center.x, -center.y));
+        canvas.fill_rect(Point::new(0.0, 0.0), Point::new(width, height), gradient.clone());
+        canvas.fill_circle(center, width * 0.3, gradient.with_transform(turn));
+    } else {
+        draw_ramps(&mut canvas);
+    }
 
-    for byte in image_bytes {
-        file.write_u8(byte).unwrap();
+    // Row 0 is written first, at the bottom of the file
+    let options = BmpOptions { row_order: RowOrder::BottomUp, ..Default::default() };
+    if let Err(err) = write_image_file("image.bmp", &image, options) {
+        eprintln!("failed to write image.bmp: {}", err);
+        return ExitCode::FAILURE;
     }
-}
\ No newline at end of file
+    ExitCode::SUCCESS
+}
@@ -1,77 +1,262 @@
-use std::{fs, time::Instant};
-use byteorder::WriteBytesExt;
+use std::{process::ExitCode, str::FromStr, time::Instant};
+use graphics_core::{
+    composite::{BlendMode, CompositeOp, PremultipliedRgba},
+    coverage::{coverage_from_distance, supersample, SamplePattern},
+    files::{write_image_file, BmpOptions, RowOrder},
+    ndc::{ndc_to_pixel, point_to_ndc},
+    png::write_png_file,
+    raster::rasterize_triangle,
+    svg::parse_svg,
+    GrayF32, Image, Point, Rgb8, Rgba8,
+};
 
 const HEIGHT: usize = 512;
 const WIDTH: usize = 512;
-const BYTES_PER_PIXEL: usize = 3;
-const ARRAY_LENGTH: usize = HEIGHT * WIDTH * BYTES_PER_PIXEL;
+// The largest SVG width or height rendered, bigger documents are refused
+// rather than allocated
+const MAX_SVG_SIZE: f32 = 8192.0;
 
-fn main() {
-    // [0,0,0,0,0,0,0,0,0,0,0 ...]
-    // [0 0 0   0 0 0   0 0 0 ...]
-    //  ^         ^
-    //  0         4
-    let mut image_bytes: [u8; ARRAY_LENGTH] = [0; ARRAY_LENGTH];
+enum AntiAliasing {
+    // One sample at the pixel corner, hard edges
+    None,
+    // Coverage estimated from the distance to the shape's edge
+    Analytic,
+    // Fraction of the pattern's sample offsets inside the shape
+    Supersample(Vec<(f32, f32)>),
+}
+
+impl FromStr for AntiAliasing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(AntiAliasing::None),
+            "analytic" => Ok(AntiAliasing::Analytic),
+            pattern => pattern
+                .parse::<SamplePattern>()
+                .map(|pattern| AntiAliasing::Supersample(pattern.offsets()))
+                .map_err(|_| format!("unknown anti-aliasing `{}`, expected none, analytic, center, grid:N or rotated:N", s)),
+        }
+    }
+}
+
+// Picks either a Porter–Duff operator or a blend mode, the other stays at
+// its default
+fn parse_compositing(s: &str) -> Result<(CompositeOp, BlendMode), String> {
+    if let Ok(op) = s.parse() {
+        return Ok((op, BlendMode::Normal));
+    }
+    if let Ok(mode) = s.parse() {
+        return Ok((CompositeOp::Over, mode));
+    }
+    Err(format!(
+        "unknown compositing `{}`, expected over, in, out, atop, xor, multiply, screen, overlay or additive",
+        s
+    ))
+}
+
+// usage: lesson-6 [none | analytic | center | grid:N | rotated:N] [operator | blend mode]
+//        lesson-6 drawing.svg [output.bmp | output.png]
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let first = args.next();
+    if let Some(input) = first.as_deref().filter(|arg| arg.ends_with(".svg")) {
+        return render_svg(input, &args.next().unwrap_or_else(|| "image.bmp".to_string()));
+    }
+
+    let anti_aliasing = match first {
+        None => AntiAliasing::Analytic,
+        Some(arg) => match arg.parse() {
+            Ok(anti_aliasing) => anti_aliasing,
+            Err(err) => {
+                eprintln!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    let (op, mode) = match args.next() {
+        None => (CompositeOp::Over, BlendMode::Normal),
+        Some(arg) => match parse_compositing(&arg) {
+            Ok(compositing) => compositing,
+            Err(err) => {
+                eprintln!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    let circle = Circle {
+        center: Vector { x: 0.0, y: 0.0, z: 0.0 },
+        radius: 0.3,
+    };
+
+    // let triangle = Triangle {
+    //     p0: Vector { x: -0.5, y: 0.5, z: 0.0 },
+    //     p1: Vector { x: 0.5, y: 0.5, z: 0.0 },
+    //     p2: Vector { x: -0.5, y: -0.5, z: 0.0 },
+    //     colors: [Rgb8::RED; 3],
+    // };
+
+    let triangle = Triangle {
+        p0: Vector { x: -0.4, y: 0.4, z: 0.0 },
+        p1: Vector { x: 0.8, y: 0.5, z: 0.0 },
+        p2: Vector { x: -0.75, y: -0.75, z: 0.0 },
+        colors: [Rgb8::RED, Rgb8::new(255, 200, 0), Rgb8::new(200, 0, 255)],
+    };
+
+    // The circle is translucent so it shows how it combines with the
+    // triangle underneath
+    let circle_color = PremultipliedRgba::from_pixel(Rgba8::new(0, 0, 255, 170));
+    let mut image: Image<PremultipliedRgba> = Image::new(WIDTH, HEIGHT);
 
     let now = Instant::now();
+    let triangle_coverage = triangle_coverage(&triangle, &anti_aliasing);
     for row in 0..HEIGHT {
         for column in 0..WIDTH {
-            // [0 0 0   0 0 0   0 0 0 ...]
-            //          ^ ^ ^
-            //         pr g b
-            let pixel_pointer: usize = WIDTH * BYTES_PER_PIXEL * row + BYTES_PER_PIXEL * column;
-            let blue_pointer = pixel_pointer;
-            let green_pointer = pixel_pointer + 1;
-            let red_pointer = pixel_pointer + 2;
-
-            if is_pixel_in_circle(row, column, WIDTH, HEIGHT) {
-                image_bytes[red_pointer] = 0;
-                image_bytes[green_pointer] = 0;
-                image_bytes[blue_pointer] = 255;
-            } else if is_pixel_in_triangle(row, column, WIDTH, HEIGHT) {
-                image_bytes[red_pointer] = 255;
-                image_bytes[green_pointer] = 0;
-                image_bytes[blue_pointer] = 0;
-            } else {
-                image_bytes[red_pointer] = 0;
-                image_bytes[green_pointer] = 0;
-                image_bytes[blue_pointer] = 0;
+            // Later shapes are composited onto earlier ones, in linear light
+            let triangle_coverage = triangle_coverage.get(row, column).unwrap().0;
+            let circle_coverage = pixel_coverage(&circle, &anti_aliasing, row, column);
+            let mut color = PremultipliedRgba::TRANSPARENT;
+            if triangle_coverage > 0.0 {
+                let triangle_color = PremultipliedRgba::from_pixel(gouraud_color(&triangle, row, column));
+                color = triangle_color.scale(triangle_coverage.min(1.0)).over(color);
             }
+            if circle_coverage > 0.0 {
+                // Coverage masks the operator's result, so `in` and `out` do
+                // not reach past the circle's edge
+                color = color.lerp(circle_color.composite(color, op, mode), circle_coverage.min(1.0));
+            }
+            image.set(row, column, color);
         }
     }
     println!("{}", now.elapsed().as_micros());
 
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open("image.bmp")
-        .unwrap();
-
-    // File header
-    file.write_u8('B' as u8).unwrap();
-    file.write_u8('M' as u8).unwrap();
-    file.write_u32::<byteorder::LE>(14 + 40 + ARRAY_LENGTH as u32).unwrap(); // file size
-    file.write_u32::<byteorder::LE>(0).unwrap(); // reserved ??
-    file.write_u32::<byteorder::LE>(14 + 40 as u32).unwrap(); // content offset
-
-    // Info header
-    file.write_u32::<byteorder::LE>(40 as u32).unwrap(); // info header size
-    file.write_u32::<byteorder::LE>(WIDTH as u32).unwrap(); // width
-    file.write_u32::<byteorder::LE>(HEIGHT as u32).unwrap(); // height
-    file.write_u16::<byteorder::LE>(1).unwrap(); // number of color planes???
-    file.write_u16::<byteorder::LE>(BYTES_PER_PIXEL as u16 * 8).unwrap(); // bits per pixel
-    file.write_u32::<byteorder::LE>(0).unwrap(); // compression
-    file.write_u32::<byteorder::LE>(ARRAY_LENGTH as u32).unwrap(); // image size
-    file.write_u32::<byteorder::LE>(0).unwrap(); // horizontal res
-    file.write_u32::<byteorder::LE>(0).unwrap(); // vertical res
-    file.write_u32::<byteorder::LE>(0).unwrap(); // colors in color table
-    file.write_u32::<byteorder::LE>(0).unwrap(); // important color count
-
-    for byte in image_bytes {
-        file.write_u8(byte).unwrap();
+    // Flattened onto black, BMP has no use for the alpha
+    let background = PremultipliedRgba::from_pixel(Rgb8::BLACK);
+    let image: Image<Rgb8> = image.map(|color| color.over(background).to_pixel());
+
+    // Row 0 is y = -1, so it goes at the bottom of the file
+    let options = BmpOptions { row_order: RowOrder::BottomUp, ..Default::default() };
+    if let Err(err) = write_image_file("image.bmp", &image, options) {
+        eprintln!("failed to write image.bmp: {}", err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+// Rasterizes an SVG file at its own size onto a white background, through
+// the same canvas as the 2D shapes
+fn render_svg(input: &str, output: &str) -> ExitCode {
+    let source = match std::fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", input, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let document = match parse_svg(&source) {
+        Ok(document) => document,
+        Err(err) => {
+            eprintln!("failed to parse {}: {}", input, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (width, height) = (document.width(), document.height());
+    if !(width <= MAX_SVG_SIZE && height <= MAX_SVG_SIZE) {
+        eprintln!(
+            "{} is {}x{}, larger than the largest size rendered, {}x{}",
+            input, width, height, MAX_SVG_SIZE, MAX_SVG_SIZE
+        );
+        return ExitCode::FAILURE;
+    }
+    let width = (width.ceil() as usize).max(1);
+    let height = (height.ceil() as usize).max(1);
+    let mut image = Image::filled(width, height, Rgb8::WHITE);
+    document.render(&mut image);
+
+    // SVG rows go top to bottom, the default for both formats
+    let result = if output.ends_with(".png") {
+        write_png_file(output, &image)
+    } else {
+        write_image_file(output, &image, BmpOptions::default())
+    };
+    if let Err(err) = result {
+        eprintln!("failed to write {}: {}", output, err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+trait Shape {
+    fn contains(&self, point: Vector) -> bool;
+    // Distance to the edge in NDC units, negative inside the shape
+    fn signed_distance(&self, point: Vector) -> f32;
+}
+
+fn pixel_coverage(shape: &impl Shape, anti_aliasing: &AntiAliasing, row: usize, column: usize) -> f32 {
+    let to_point = |row: f32, column: f32| {
+        let (x, y) = point_to_ndc(row, column, WIDTH, HEIGHT);
+        Vector { x, y, z: 0.0 }
+    };
+    match anti_aliasing {
+        AntiAliasing::None => {
+            if shape.contains(to_point(row as f32, column as f32)) { 1.0 } else { 0.0 }
+        }
+        AntiAliasing::Analytic => {
+            let center = to_point(row as f32 + 0.5, column as f32 + 0.5);
+            let pixels_per_unit = 0.5 * WIDTH.min(HEIGHT) as f32;
+            coverage_from_distance(shape.signed_distance(center) * pixels_per_unit)
+        }
+        AntiAliasing::Supersample(offsets) => {
+            supersample(row, column, offsets, |column, row| shape.contains(to_point(row, column)))
+        }
     }
 }
 
+// Rasterizes the triangle once per sample position instead of testing every
+// pixel of the image against it
+fn triangle_coverage(triangle: &Triangle, anti_aliasing: &AntiAliasing) -> Image<GrayF32> {
+    let mut coverage: Image<GrayF32> = Image::new(WIDTH, HEIGHT);
+    let to_pixel = |p: Vector| {
+        let (row, column) = ndc_to_pixel(p.x, p.y, WIDTH, HEIGHT);
+        Point::new(column, row)
+    };
+    let vertices = [to_pixel(triangle.p0), to_pixel(triangle.p1), to_pixel(triangle.p2)];
+
+    // The rasterizer samples pixel centers, shift the triangle so it samples
+    // at `offset` instead
+    let mut rasterize_at = |offset: (f32, f32), weight: f32| {
+        let shift = Point::new(0.5 - offset.0, 0.5 - offset.1);
+        rasterize_triangle(vertices.map(|v| v + shift), WIDTH, HEIGHT, |fragment| {
+            coverage.get_mut(fragment.row, fragment.col).unwrap().0 += weight;
+        });
+    };
+
+    match anti_aliasing {
+        AntiAliasing::None => rasterize_at((0.0, 0.0), 1.0),
+        AntiAliasing::Supersample(offsets) => {
+            for &offset in offsets {
+                rasterize_at(offset, 1.0 / offsets.len() as f32);
+            }
+        }
+        AntiAliasing::Analytic => {
+            // Only pixels near the triangle can be partially covered
+            let min_col = vertices.iter().map(|v| v.x).fold(f32::MAX, f32::min).floor() - 1.0;
+            let max_col = vertices.iter().map(|v| v.x).fold(f32::MIN, f32::max).ceil() + 1.0;
+            let min_row = vertices.iter().map(|v| v.y).fold(f32::MAX, f32::min).floor() - 1.0;
+            let max_row = vertices.iter().map(|v| v.y).fold(f32::MIN, f32::max).ceil() + 1.0;
+            for row in min_row.max(0.0) as usize..(max_row.max(0.0) as usize).min(HEIGHT) {
+                for column in min_col.max(0.0) as usize..(max_col.max(0.0) as usize).min(WIDTH) {
+                    coverage.set(row, column, GrayF32(pixel_coverage(triangle, anti_aliasing, row, column)));
+                }
+            }
+        }
+    }
+    coverage
+}
+
+#[derive(Clone, Copy)]
 struct Vector {
     x: f32,
     y: f32,
@@ -91,18 +276,17 @@ struct Circle {
     radius: f32,
 }
 
-fn is_pixel_in_circle(row: usize, column: usize, row_size: usize, col_size: usize) -> bool {
-    let x = (column as f32 - col_size as f32 / 2.0) / (0.5 * col_size as f32);
-    let y = (row as f32 - row_size as f32 / 2.0) / (0.5 * row_size as f32);
-    let z = 0.0;
-    let point = Vector {x, y, z};
+impl Shape for Circle {
+    fn contains(&self, point: Vector) -> bool {
+        is_point_in_circle(self, point)
+    }
 
-    let center = Vector {x: 0.0, y: 0.0, z: 0.0};
-    let circle = Circle { center, radius: 0.3 };
-    is_point_in_circle(circle, point)
+    fn signed_distance(&self, point: Vector) -> f32 {
+        distance(self.center, point) - self.radius
+    }
 }
 
-fn is_point_in_circle(circle: Circle, point: Vector) -> bool {
+fn is_point_in_circle(circle: &Circle, point: Vector) -> bool {
     let dist = distance(circle.center, point);
     dist <= circle.radius
 }
@@ -120,30 +304,54 @@ struct Triangle {
     p0: Vector,
     p1: Vector,
     p2: Vector,
+    // Per-vertex colors, blended across the triangle
+    colors: [Rgb8; 3],
 }
 
-fn is_pixel_in_triangle(row: usize, column: usize, row_size: usize, col_size: usize) -> bool {
-    let x = (column as f32 - col_size as f32 / 2.0) / (0.5 * col_size as f32);
-    let y = (row as f32 - row_size as f32 / 2.0) / (0.5 * row_size as f32);
-    let z = 0.0;
-    let point = Vector {x, y, z};
+impl Shape for Triangle {
+    fn contains(&self, point: Vector) -> bool {
+        is_point_in_triangle(self, point)
+    }
 
-    // let triangle = Triangle {
-    //     p0: Vector { x: -0.5, y: 0.5, z: 0.0 },
-    //     p1: Vector { x: 0.5, y: 0.5, z: 0.0 },
-    //     p2: Vector { x: -0.5, y: -0.5, z: 0.0 },
-    // };
+    fn signed_distance(&self, point: Vector) -> f32 {
+        let edges = [(self.p0, self.p1), (self.p1, self.p2), (self.p2, self.p0)];
+        let edge_distance = edges
+            .iter()
+            .map(|&(a, b)| distance_to_segment(a, b, point))
+            .fold(f32::MAX, f32::min);
+        if self.contains(point) { -edge_distance } else { edge_distance }
+    }
+}
 
-    let triangle = Triangle {
-        p0: Vector { x: -0.4, y: 0.4, z: 0.0 },
-        p1: Vector { x: 0.8, y: 0.5, z: 0.0 },
-        p2: Vector { x: -0.75, y: -0.75, z: 0.0 },
-    };
+// Gouraud shading: the vertex colors weighted by the barycentric
+// coordinates of the pixel center
+fn gouraud_color(triangle: &Triangle, row: usize, column: usize) -> Rgb8 {
+    let (x, y) = point_to_ndc(row as f32 + 0.5, column as f32 + 0.5, WIDTH, HEIGHT);
+    let weights = barycentric_weights(triangle, Vector { x, y, z: 0.0 });
 
-    is_point_in_triangle(triangle, point)
+    // Edge pixels can have their center just outside the triangle, clamp
+    // so the color stays between the vertex colors
+    let weights = weights.map(|w| w.max(0.0));
+    let total: f32 = weights.iter().sum();
+    let mut color = [0.0; 3];
+    for (weight, vertex_color) in weights.iter().zip(triangle.colors) {
+        for (channel, value) in color.iter_mut().zip(vertex_color.to_f32()) {
+            *channel += weight / total * value;
+        }
+    }
+    Rgb8::from_f32(color[0], color[1], color[2])
+}
+
+fn distance_to_segment(a: Vector, b: Vector, p: Vector) -> f32 {
+    let (abx, aby) = (b.x - a.x, b.y - a.y);
+    let (apx, apy) = (p.x - a.x, p.y - a.y);
+    let length2 = abx * abx + aby * aby;
+    let t = if length2 > 0.0 { ((apx * abx + apy * aby) / length2).clamp(0.0, 1.0) } else { 0.0 };
+    let closest = Vector { x: a.x + abx * t, y: a.y + aby * t, z: 0.0 };
+    distance(closest, p)
 }
 
-fn is_point_in_triangle(t: Triangle, p: Vector) -> bool {
+fn is_point_in_triangle(t: &Triangle, p: Vector) -> bool {
     // See: https://stackoverflow.com/a/2049712/1445441
     // use barycentric coordinates, who knew
 
@@ -155,5 +363,22 @@ fn is_point_in_triangle(t: Triangle, p: Vector) -> bool {
     }
 
     let d = (t.p2.x - t.p1.x) * (p.y - t.p1.y) - (t.p2.y - t.p1.y) * (p.x - t.p1.x);
-    return d == 0.0 || (d < 0.0) == (s + v <= 0.0);
-}
\ No newline at end of file
+    d == 0.0 || (d < 0.0) == (s + v <= 0.0)
+}
+
+// Normalized barycentric coordinates of `p`: one weight per vertex, summing to
+// 1, all of them between 0 and 1 when `p` is inside the triangle.
+fn barycentric_weights(t: &Triangle, p: Vector) -> [f32; 3] {
+    // The same signed areas as `is_point_in_triangle`. Each one is twice the
+    // area of the sub-triangle formed by `p` and one edge, which is
+    // proportional to the weight of the vertex opposite that edge.
+    let s = (t.p0.x - t.p2.x) * (p.y - t.p2.y) - (t.p0.y - t.p2.y) * (p.x - t.p2.x);
+    let v = (t.p1.x - t.p0.x) * (p.y - t.p0.y) - (t.p1.y - t.p0.y) * (p.x - t.p0.x);
+    let d = (t.p2.x - t.p1.x) * (p.y - t.p1.y) - (t.p2.y - t.p1.y) * (p.x - t.p1.x);
+
+    let area = s + v + d;
+    if area == 0.0 {
+        return [1.0 / 3.0; 3];
+    }
+    [d / area, s / area, v / area]
+}
@@ -0,0 +1,429 @@
+use std::{
+    fmt,
+    ops::{Add, Mul, Sub},
+    str::FromStr,
+};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{Camera, Scene};
+
+// How a key goes over to the next one
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Curve {
+    // Holds the value until the next key
+    Step,
+    #[default]
+    Linear,
+    // Eases out of this key and into the next
+    Smooth,
+    // Passes through the keys with the slope of their neighbours, so a
+    // path through a few keys comes out without corners
+    CatmullRom,
+}
+
+// Anything that can be blended between keys
+pub trait Animatable: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> {}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>> Animatable for T {}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Key<T> {
+    pub time: f32,
+    pub value: T,
+    pub curve: Curve,
+}
+
+// A value over time, held before the first key and after the last. Times
+// are in frames.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keys: Vec<Key<T>>,
+}
+
+impl<T: Animatable> Track<T> {
+    // Keys as (time, value) pairs, all joined by `curve`
+    pub fn new(curve: Curve, keys: &[(f32, T)]) -> Track<T> {
+        assert!(!keys.is_empty(), "a track needs at least one key");
+        keys.iter().fold(Track { keys: Vec::new() }, |track, &(time, value)| track.with_key(time, value, curve))
+    }
+
+    pub fn constant(value: T) -> Track<T> {
+        Track::new(Curve::Step, &[(0.0, value)])
+    }
+
+    // Keys can be added in any order, a key at the time of another one goes
+    // after it
+    pub fn with_key(mut self, time: f32, value: T, curve: Curve) -> Self {
+        let index = self.keys.partition_point(|key| key.time <= time);
+        self.keys.insert(index, Key { time, value, curve });
+        self
+    }
+
+    pub fn at(&self, time: f32) -> T {
+        let keys = &self.keys;
+        let next = keys.partition_point(|key| key.time <= time);
+        if next == 0 {
+            return keys[0].value;
+        }
+        if next == keys.len() {
+            return keys[next - 1].value;
+        }
+        let (a, b) = (keys[next - 1], keys[next]);
+        let duration = b.time - a.time;
+        let t = (time - a.time) / duration;
+        match a.curve {
+            Curve::Step => a.value,
+            Curve::Linear => a.value + (b.value - a.value) * t,
+            Curve::Smooth => a.value + (b.value - a.value) * (t * t * (3.0 - 2.0 * t)),
+            Curve::CatmullRom => {
+                // The slope at a key is the one from its previous key to its
+                // next, the end keys stand in for their missing neighbours
+                let before = keys[next.saturating_sub(2)];
+                let after = keys[(next + 1).min(keys.len() - 1)];
+                let slope = |p: Key<T>, q: Key<T>| (q.value - p.value) * (duration / (q.time - p.time).max(f32::EPSILON));
+                let (t2, t3) = (t * t, t * t * t);
+                a.value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + slope(before, b) * (t3 - 2.0 * t2 + t)
+                    + b.value * (3.0 * t2 - 2.0 * t3)
+                    + slope(a, after) * (t3 - t2)
+            }
+        }
+    }
+}
+
+// Where an object is over time, as an offset from where it is placed and a
+// scale about its center
+#[derive(Clone, Debug)]
+pub struct Motion {
+    pub translation: Track<Vector3<f32>>,
+    pub scale: Track<f32>,
+}
+
+impl Motion {
+    pub fn new(translation: Track<Vector3<f32>>, scale: Track<f32>) -> Motion {
+        Motion { translation, scale }
+    }
+
+    pub fn at(&self, time: f32) -> (Vector3<f32>, f32) {
+        (self.translation.at(time), self.scale.at(time))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CameraMotion {
+    position: Track<Vector3<f32>>,
+    forward: Track<Vector3<f32>>,
+}
+
+#[derive(Clone, Debug)]
+struct LightMotion {
+    direction: Track<Vector3<f32>>,
+    intensity: Track<f32>,
+}
+
+// Tracks for the camera, and for lights and objects by their index in the
+// scene. The camera and lights without a track stay as the scene has them.
+// Objects without one are held still, as the motion a scene gives them for
+// a still render is keyed on its own shutter, not on frames.
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    camera: Option<CameraMotion>,
+    lights: Vec<(usize, LightMotion)>,
+    objects: Vec<(usize, Motion)>,
+}
+
+impl Animation {
+    pub fn new() -> Animation {
+        Animation::default()
+    }
+
+    // `forward` doesn't need to stay normalized in between keys
+    pub fn with_camera(mut self, position: Track<Vector3<f32>>, forward: Track<Vector3<f32>>) -> Self {
+        self.camera = Some(CameraMotion { position, forward });
+        self
+    }
+
+    pub fn with_light(mut self, index: usize, direction: Track<Vector3<f32>>, intensity: Track<f32>) -> Self {
+        self.lights.push((index, LightMotion { direction, intensity }));
+        self
+    }
+
+    pub fn with_object(mut self, index: usize, motion: Motion) -> Self {
+        self.objects.push((index, motion));
+        self
+    }
+
+    // Poses the camera and the lights at `time`. Objects get their motion
+    // instead, so they still move while the shutter is open.
+    pub fn apply(&self, scene: &mut Scene, camera: &mut Camera, time: f32) {
+        if let Some(motion) = &self.camera {
+            camera.position = motion.position.at(time);
+            camera.forward = motion.forward.at(time).normalize();
+        }
+        for (index, motion) in &self.lights {
+            let light = &mut scene.lights[*index];
+            light.direction = motion.direction.at(time).normalize();
+            light.intensity = motion.intensity.at(time);
+        }
+        for sphere in &mut scene.spheres {
+            sphere.motion = None;
+        }
+        for (index, motion) in &self.objects {
+            scene.spheres[*index].motion = Some(motion.clone());
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Sequence {
+    // The camera circles the scene once, looking at its middle
+    #[default]
+    Turntable,
+    // The camera flies over the spheres and looks back at them from behind,
+    // while the light sweeps over and the purple sphere slides across and back
+    Flythrough,
+}
+
+// Indices in the scene in main
+const KEY_LIGHT: usize = 0;
+const FILL_LIGHT: usize = 1;
+const PURPLE_SPHERE: usize = 8;
+
+impl Sequence {
+    pub fn animation(self, frames: FrameRange) -> Animation {
+        let FrameRange { first, last } = frames;
+        let (first, last) = (first as f32, last as f32);
+        let at = |u: f32| first + (last - first) * u;
+        match self {
+            Sequence::Turntable => {
+                const CENTER: Vector3<f32> = Vector3::new(0.0, 0.0, 7.0);
+                const RADIUS: f32 = 17.0;
+                const KEYS: usize = 16;
+                // One frame past the last, so that the last frame leads back
+                // into the first when the sequence loops
+                let period = last + 1.0 - first;
+                let mut positions = Vec::new();
+                let mut forwards = Vec::new();
+                for k in 0..=KEYS {
+                    let angle = std::f32::consts::TAU * k as f32 / KEYS as f32;
+                    let offset = Vector3::new(-angle.sin(), 0.0, -angle.cos()) * RADIUS;
+                    let time = first + period * k as f32 / KEYS as f32;
+                    positions.push((time, CENTER + offset));
+                    forwards.push((time, -offset.normalize()));
+                }
+                Animation::new().with_camera(
+                    Track::new(Curve::CatmullRom, &positions),
+                    Track::new(Curve::CatmullRom, &forwards),
+                )
+            }
+            Sequence::Flythrough => Animation::new()
+                .with_camera(
+                    Track::new(
+                        Curve::CatmullRom,
+                        &[
+                            (at(0.0), Vector3::new(0.0, 0.0, -10.0)),
+                            (at(0.25), Vector3::new(2.0, 3.0, -2.0)),
+                            (at(0.5), Vector3::new(1.0, 7.0, 8.0)),
+                            (at(0.75), Vector3::new(-3.0, 3.0, 20.0)),
+                            (at(1.0), Vector3::new(0.0, 0.0, 26.0)),
+                        ],
+                    ),
+                    Track::new(
+                        Curve::Smooth,
+                        &[
+                            (at(0.0), Vector3::new(0.0, 0.0, 1.0)),
+                            (at(0.25), Vector3::new(-0.1, -0.3, 1.0)),
+                            (at(0.5), Vector3::new(-0.1, -1.0, 0.3)),
+                            (at(0.75), Vector3::new(0.2, -0.3, -1.0)),
+                            (at(1.0), Vector3::new(0.0, 0.0, -1.0)),
+                        ],
+                    ),
+                )
+                .with_light(
+                    KEY_LIGHT,
+                    Track::new(Curve::Smooth, &[(at(0.0), Vector3::new(-1.0, 0.0, 0.0)), (at(1.0), Vector3::new(-1.0, -1.0, 1.0))]),
+                    Track::constant(0.5),
+                )
+                .with_light(
+                    FILL_LIGHT,
+                    Track::constant(Vector3::new(0.0, -1.0, 0.0)),
+                    Track::new(Curve::Smooth, &[(at(0.0), 0.25), (at(0.5), 1.0), (at(1.0), 0.25)]),
+                )
+                .with_object(
+                    PURPLE_SPHERE,
+                    Motion::new(
+                        Track::new(
+                            Curve::CatmullRom,
+                            &[(at(0.0), Vector3::new(0.0, 0.0, 0.0)), (at(0.5), Vector3::new(1.5, 0.0, 0.0)), (at(1.0), Vector3::new(0.0, 0.0, 0.0))],
+                        ),
+                        Track::constant(1.0),
+                    ),
+                ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseSequenceError(String);
+
+impl fmt::Display for ParseSequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown sequence `{}`, expected turntable or flythrough", self.0)
+    }
+}
+
+impl std::error::Error for ParseSequenceError {}
+
+impl FromStr for Sequence {
+    type Err = ParseSequenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "turntable" => Ok(Sequence::Turntable),
+            "flythrough" => Ok(Sequence::Flythrough),
+            _ => Err(ParseSequenceError(s.to_string())),
+        }
+    }
+}
+
+// Frame numbers, both ends included
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FrameRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl Default for FrameRange {
+    fn default() -> Self {
+        FrameRange { first: 1, last: 48 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseFrameRangeError(String);
+
+impl fmt::Display for ParseFrameRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid frame range `{}`, expected <first>-<last> or a single frame", self.0)
+    }
+}
+
+impl std::error::Error for ParseFrameRangeError {}
+
+impl FromStr for FrameRange {
+    type Err = ParseFrameRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseFrameRangeError(s.to_string());
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let first = first.parse().map_err(|_| err())?;
+        let last = last.parse().map_err(|_| err())?;
+        if last < first {
+            return Err(err());
+        }
+        Ok(FrameRange { first, last })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{spectral::Glass, DirectionalLight, Material, Shutter, Sphere};
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} against {}", actual, expected);
+    }
+
+    #[test]
+    fn curves_between_keys() {
+        let keys = [(0.0, 0.0), (4.0, 8.0)];
+        let step = Track::new(Curve::Step, &keys);
+        let linear = Track::new(Curve::Linear, &keys);
+        let smooth = Track::new(Curve::Smooth, &keys);
+        let cases = [(0.0, 0.0, 0.0, 0.0), (1.0, 0.0, 2.0, 1.25), (2.0, 0.0, 4.0, 4.0), (3.99, 0.0, 7.98, 7.9999)];
+        for (time, stepped, lerped, eased) in cases {
+            assert_close(step.at(time), stepped);
+            assert_close(linear.at(time), lerped);
+            assert!((smooth.at(time) - eased).abs() < 1e-3, "{} at {}", smooth.at(time), time);
+        }
+        assert_close(step.at(4.0), 8.0);
+
+        // Through samples of x², Catmull-Rom is exact between the middle
+        // keys and passes through every key
+        let squares = Track::new(Curve::CatmullRom, &[(0.0, 0.0), (1.0, 1.0), (2.0, 4.0), (3.0, 9.0)]);
+        assert_close(squares.at(1.5), 2.25);
+        for x in [0.0, 1.0, 2.0, 3.0] {
+            assert_close(squares.at(x), x * x);
+        }
+        // Uneven spacing scales the slopes to the segment
+        let uneven = Track::new(Curve::CatmullRom, &[(0.0, 0.0), (2.0, 2.0), (3.0, 3.0), (5.0, 5.0)]);
+        assert_close(uneven.at(2.5), 2.5);
+    }
+
+    #[test]
+    fn held_outside_the_keys() {
+        let track = Track::new(Curve::CatmullRom, &[(2.0, 1.0), (4.0, 3.0), (6.0, -1.0)]);
+        assert_eq!(track.at(-10.0), 1.0);
+        assert_eq!(track.at(2.0), 1.0);
+        assert_eq!(track.at(6.0), -1.0);
+        assert_eq!(track.at(100.0), -1.0);
+        assert_eq!(Track::constant(7.0).at(-3.0), 7.0);
+        assert_eq!(Track::constant(7.0).at(3.0), 7.0);
+    }
+
+    #[test]
+    fn keys_in_any_order_and_at_equal_times() {
+        let track = Track::new(Curve::Linear, &[(4.0, 4.0), (0.0, 0.0), (2.0, 10.0)]);
+        assert_close(track.at(1.0), 5.0);
+        assert_close(track.at(3.0), 7.0);
+
+        // A second key at the same time jumps there, the first one is
+        // reached from before and the second one goes on
+        let jump = Track::new(Curve::Linear, &[(0.0, 0.0), (2.0, 2.0), (2.0, 10.0), (4.0, 12.0)]);
+        assert_close(jump.at(1.99), 1.99);
+        assert_close(jump.at(2.0), 10.0);
+        assert_close(jump.at(3.0), 11.0);
+        // Each key's own curve leads to the next
+        let mixed =
+            Track::new(Curve::Linear, &[(0.0, 0.0)]).with_key(4.0, 4.0, Curve::Step).with_key(2.0, 2.0, Curve::Step);
+        assert_close(mixed.at(1.0), 1.0);
+        assert_close(mixed.at(3.0), 2.0);
+    }
+
+    #[test]
+    fn apply_poses_the_scene_and_replaces_object_motion() {
+        let origin = Vector3::new(0.0, 0.0, 0.0);
+        let material = Material { emittance: origin, reflectance: 1.0, specular: 0.0, glass: None::<Glass> };
+        let sphere = |motion| Sphere { center: Vector3::new(0.0, 0.0, 5.0), radius: 1.0, material, motion, interior: None };
+        let light = || DirectionalLight { direction: Vector3::new(0.0, -1.0, 0.0), intensity: 1.0, color: Vector3::new(1.0, 1.0, 1.0) };
+        let sliding = Motion::new(
+            Track::new(Curve::Linear, &[(0.0, origin), (1.0, Vector3::new(1.0, 0.0, 0.0))]),
+            Track::constant(1.0),
+        );
+        let mut scene = Scene { spheres: vec![sphere(Some(sliding)), sphere(None)], lights: vec![light(), light()], volumes: Vec::new() };
+        let mut camera =
+            Camera { position: origin, forward: Vector3::new(0.0, 0.0, 1.0), shutter: Shutter { open: 0.0, close: 0.0 } };
+
+        let rising = Motion::new(Track::constant(origin), Track::new(Curve::Linear, &[(0.0, 1.0), (10.0, 2.0)]));
+        let animation = Animation::new()
+            .with_camera(
+                Track::new(Curve::Linear, &[(0.0, origin), (10.0, Vector3::new(10.0, 0.0, 0.0))]),
+                Track::constant(Vector3::new(0.0, 0.0, 2.0)),
+            )
+            .with_light(1, Track::constant(Vector3::new(3.0, 0.0, 0.0)), Track::new(Curve::Linear, &[(0.0, 0.0), (10.0, 1.0)]))
+            .with_object(1, rising);
+        animation.apply(&mut scene, &mut camera, 5.0);
+
+        assert_eq!(camera.position, Vector3::new(5.0, 0.0, 0.0));
+        assert_eq!(camera.forward, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(scene.lights[0].direction, Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(scene.lights[1].direction, Vector3::new(1.0, 0.0, 0.0));
+        assert_close(scene.lights[1].intensity, 0.5);
+        // The first sphere's own motion was keyed for a still render, it has
+        // no track here and stands still; the second one follows its track
+        assert!(scene.spheres[0].motion.is_none());
+        assert_eq!(scene.spheres[0].at(0.5), (Vector3::new(0.0, 0.0, 5.0), 1.0));
+        assert_eq!(scene.spheres[1].at(5.0).1, 1.5);
+    }
+}
@@ -0,0 +1,450 @@
+use std::{fmt, str::FromStr};
+
+use cgmath::{Vector3, Zero};
+use graphics_core::{
+    color::{GrayF32, RgbF32},
+    denoise::FeatureBuffers,
+    exr::{write_exr_file, ExrImage},
+    files::{write_image_file, BmpOptions, ImageError},
+    Image, Rgb8,
+};
+
+use crate::{Intersection, Radiance, Ray, Scene};
+
+// Arbitrary output variables, the passes written next to the beauty image.
+// Everything but the light passes is taken from what the camera rays hit
+// first.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aov {
+    // Distance along the camera ray
+    Depth,
+    // World space
+    Position,
+    Normal,
+    Albedo,
+    // Objects and distinct materials numbered from 1, 0 where nothing was
+    // hit
+    MaterialId,
+    ObjectId,
+    // The beauty image split by how the light got there
+    Direct,
+    Indirect,
+    Emission,
+    // How many samples the pixel got
+    Samples,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 10] = [
+        Aov::Depth,
+        Aov::Position,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Emission,
+        Aov::Samples,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material-id",
+            Aov::ObjectId => "object-id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+            Aov::Samples => "samples",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAovError(String);
+
+impl fmt::Display for ParseAovError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown AOV `{}`, expected depth, position, normal, albedo, material-id, object-id, direct, indirect, \
+             emission, samples, all or none",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseAovError {}
+
+impl FromStr for Aov {
+    type Err = ParseAovError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == s).ok_or_else(|| ParseAovError(s.to_string()))
+    }
+}
+
+// The AOVs to write, parsed from a comma separated list, `all` or `none`.
+// By default the features the denoiser uses.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AovSelection(Vec<Aov>);
+
+impl Default for AovSelection {
+    fn default() -> Self {
+        AovSelection(vec![Aov::Albedo, Aov::Normal, Aov::Depth])
+    }
+}
+
+impl FromStr for AovSelection {
+    type Err = ParseAovError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(AovSelection(Aov::ALL.to_vec())),
+            "none" => Ok(AovSelection(Vec::new())),
+            _ => {
+                let mut aovs = Vec::new();
+                for aov in s.split(',') {
+                    let aov = aov.parse()?;
+                    if !aovs.contains(&aov) {
+                        aovs.push(aov);
+                    }
+                }
+                Ok(AovSelection(aovs))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OutputFormat {
+    // One 8 bit image per pass, mapped to something viewable
+    #[default]
+    Bmp,
+    // Everything as float layers of image.exr
+    Exr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOutputFormatError(String);
+
+impl fmt::Display for ParseOutputFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown output format `{}`, expected bmp or exr", self.0)
+    }
+}
+
+impl std::error::Error for ParseOutputFormatError {}
+
+impl FromStr for OutputFormat {
+    type Err = ParseOutputFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bmp" => Ok(OutputFormat::Bmp),
+            "exr" => Ok(OutputFormat::Exr),
+            _ => Err(ParseOutputFormatError(s.to_string())),
+        }
+    }
+}
+
+// Material ids for every sphere, spheres sharing a material share an id
+pub fn material_ids(scene: &Scene) -> Vec<u32> {
+    let mut materials = Vec::new();
+    let mut ids = Vec::new();
+    for sphere in &scene.spheres {
+        let id = match materials.iter().position(|&material| material == sphere.material) {
+            Some(index) => index,
+            None => {
+                materials.push(sphere.material);
+                materials.len() - 1
+            }
+        };
+        ids.push(id as u32 + 1);
+    }
+    ids
+}
+
+// Sums over a pixel's samples
+#[derive(Clone, Copy)]
+pub struct PixelSum {
+    albedo: Vector3<f32>,
+    normal: Vector3<f32>,
+    position: Vector3<f32>,
+    depth: f32,
+    emission: Vector3<f32>,
+    direct: Vector3<f32>,
+    indirect: Vector3<f32>,
+    samples: u32,
+    // How much of the samples hit something, the weight of the sums above
+    // it
+    coverage: f32,
+    // Ids can't be averaged, a pixel takes those of the sample nearest its
+    // center
+    nearest: f32,
+    material: u32,
+    object: u32,
+}
+
+impl Default for PixelSum {
+    fn default() -> Self {
+        PixelSum {
+            albedo: Vector3::zero(),
+            normal: Vector3::zero(),
+            position: Vector3::zero(),
+            depth: 0.0,
+            emission: Vector3::zero(),
+            direct: Vector3::zero(),
+            indirect: Vector3::zero(),
+            samples: 0,
+            coverage: 0.0,
+            nearest: f32::MAX,
+            material: 0,
+            object: 0,
+        }
+    }
+}
+
+impl PixelSum {
+    // `offset` is where in the pixel the camera ray went through, `surface`
+    // what it hits, as it was shaded
+    pub fn add(
+        &mut self,
+        scene: &Scene,
+        material_ids: &[u32],
+        ray: &Ray,
+        surface: Option<(usize, Intersection)>,
+        offset: (f32, f32),
+        radiance: &Radiance,
+    ) {
+        self.samples += 1;
+        self.emission += radiance.emission;
+        self.direct += radiance.direct;
+        self.indirect += radiance.indirect;
+
+        // Volumes in front of the surface count by how much of it they
+        // hide, otherwise the denoiser would keep the edges of surfaces that
+        // smoke covers. They have no normal, and no ids.
+        let distance = surface.as_ref().map_or(f32::INFINITY, |(_, intersection)| intersection.distance);
+        let mut layers: Vec<_> = scene
+            .volumes
+            .iter()
+            .filter_map(|volume| volume.opacity(ray, distance).map(|(start, opacity)| (start, opacity, volume)))
+            .collect();
+        layers.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut visible = 1.0;
+        for (start, opacity, volume) in layers {
+            let weight = visible * opacity;
+            self.albedo += volume.medium.albedo() * weight;
+            self.position += (ray.origin + ray.direction * start) * weight;
+            self.depth += start * weight;
+            self.coverage += weight;
+            visible -= weight;
+        }
+
+        let Some((index, intersection)) = surface else {
+            return;
+        };
+        self.albedo += scene.spheres[index].material.albedo() * visible;
+        self.normal += intersection.normal * visible;
+        self.position += (ray.origin + ray.direction * intersection.distance) * visible;
+        self.depth += intersection.distance * visible;
+        self.coverage += visible;
+        let distance = (offset.0 - 0.5).powi(2) + (offset.1 - 0.5).powi(2);
+        if distance < self.nearest {
+            self.nearest = distance;
+            self.material = material_ids[index];
+            self.object = index as u32 + 1;
+        }
+    }
+}
+
+pub struct Aovs {
+    albedo: Image<RgbF32>,
+    // World space, zero where nothing was hit
+    normal: Image<RgbF32>,
+    position: Image<RgbF32>,
+    // Infinite where nothing was hit
+    depth: Image<GrayF32>,
+    emission: Image<RgbF32>,
+    direct: Image<RgbF32>,
+    indirect: Image<RgbF32>,
+    samples: Image<GrayF32>,
+    material: Image<GrayF32>,
+    object: Image<GrayF32>,
+}
+
+fn rgb(v: Vector3<f32>) -> RgbF32 {
+    RgbF32::new(v.x, v.y, v.z)
+}
+
+// Raw values clamped to 0..1, without sRGB encoding. The beauty image and
+// animation frames are written this way.
+pub fn to_rgb8(p: RgbF32) -> Rgb8 {
+    Rgb8::from_f32(p.r, p.g, p.b)
+}
+
+// Maps the finite values to 0..1 using their range, and the rest to 0
+fn normalized(image: &Image<GrayF32>) -> Image<GrayF32> {
+    let finite = image.pixels().iter().map(|p| p.0).filter(|v| v.is_finite());
+    let (min, max) = finite.fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(v), max.max(v)));
+    let range = (max - min).max(1e-6);
+    image.map(|GrayF32(v)| GrayF32(if v.is_finite() { (v - min) / range } else { 0.0 }))
+}
+
+// A distinct color per id, black for 0
+fn id_color(GrayF32(id): GrayF32) -> Rgb8 {
+    if id == 0.0 {
+        return Rgb8::new(0, 0, 0);
+    }
+    let hash = (id as u32).wrapping_mul(0x9e3779b9);
+    let hue = (hash >> 8) as f32 / (1 << 24) as f32 * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    Rgb8::from_f32(r, g, b)
+}
+
+impl Aovs {
+    pub fn new(width: usize, height: usize) -> Aovs {
+        Aovs {
+            albedo: Image::new(width, height),
+            normal: Image::new(width, height),
+            position: Image::new(width, height),
+            depth: Image::new(width, height),
+            emission: Image::new(width, height),
+            direct: Image::new(width, height),
+            indirect: Image::new(width, height),
+            samples: Image::new(width, height),
+            material: Image::new(width, height),
+            object: Image::new(width, height),
+        }
+    }
+
+    pub fn set_row(&mut self, row: usize, sums: &[PixelSum]) {
+        for (col, sum) in sums.iter().enumerate() {
+            // Misses count as black and as no normal, but positions and
+            // depths are only averaged over the hits so an edge pixel
+            // doesn't end up halfway to infinity
+            let samples = sum.samples.max(1) as f32;
+            let hits = if sum.coverage > 0.0 { sum.coverage } else { 1.0 };
+            let depth = if sum.coverage > 0.0 { sum.depth / hits } else { f32::INFINITY };
+            self.albedo.set(row, col, rgb(sum.albedo / samples));
+            self.normal.set(row, col, rgb(sum.normal / samples));
+            self.position.set(row, col, rgb(sum.position / hits));
+            self.depth.set(row, col, GrayF32(depth));
+            self.emission.set(row, col, rgb(sum.emission / samples));
+            self.direct.set(row, col, rgb(sum.direct / samples));
+            self.indirect.set(row, col, rgb(sum.indirect / samples));
+            self.samples.set(row, col, GrayF32(sum.samples as f32));
+            self.material.set(row, col, GrayF32(sum.material as f32));
+            self.object.set(row, col, GrayF32(sum.object as f32));
+        }
+    }
+
+    pub fn feature_buffers(&self) -> FeatureBuffers<'_> {
+        FeatureBuffers { albedo: &self.albedo, normal: &self.normal, depth: &self.depth }
+    }
+
+    // For the BMPs, normals are mapped from -1..1 to 0..1, positions and
+    // depths by their range with depth going from white near to dark far,
+    // sample counts relative to the most any pixel got, and ids get a color
+    // each. Misses are black.
+    fn to_bmp(&self, aov: Aov) -> Image<Rgb8> {
+        let gray = |v: f32| Rgb8::from_f32(v, v, v);
+        match aov {
+            Aov::Depth => {
+                let scaled = normalized(&self.depth);
+                let mut image = Image::new(self.depth.width(), self.depth.height());
+                for (pixel, (v, d)) in image.pixels_mut().iter_mut().zip(scaled.pixels().iter().zip(self.depth.pixels())) {
+                    if d.0.is_finite() {
+                        *pixel = gray(1.0 - 0.8 * v.0);
+                    }
+                }
+                image
+            }
+            Aov::Position => {
+                let hits = self.position.pixels().iter().zip(self.depth.pixels()).filter(|(_, d)| d.0.is_finite());
+                let (min, max) = hits.fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), (p, _)| {
+                    let p = [p.r, p.g, p.b];
+                    (std::array::from_fn(|i| min[i].min(p[i])), std::array::from_fn(|i| max[i].max(p[i])))
+                });
+                let scale = |v: f32, i: usize| (v - min[i]) / (max[i] - min[i]).max(1e-6);
+                let mut image = Image::new(self.position.width(), self.position.height());
+                for (pixel, (p, d)) in image.pixels_mut().iter_mut().zip(self.position.pixels().iter().zip(self.depth.pixels())) {
+                    if d.0.is_finite() {
+                        *pixel = Rgb8::from_f32(scale(p.r, 0), scale(p.g, 1), scale(p.b, 2));
+                    }
+                }
+                image
+            }
+            Aov::Normal => self.normal.map(|p| Rgb8::from_f32(p.r * 0.5 + 0.5, p.g * 0.5 + 0.5, p.b * 0.5 + 0.5)),
+            Aov::Albedo => self.albedo.map(to_rgb8),
+            Aov::MaterialId => self.material.map(id_color),
+            Aov::ObjectId => self.object.map(id_color),
+            Aov::Direct => self.direct.map(to_rgb8),
+            Aov::Indirect => self.indirect.map(to_rgb8),
+            Aov::Emission => self.emission.map(to_rgb8),
+            Aov::Samples => {
+                let most = self.samples.pixels().iter().fold(1.0f32, |most, p| most.max(p.0));
+                self.samples.map(|GrayF32(n)| gray(n / most))
+            }
+        }
+    }
+
+    fn add_layer(&self, exr: ExrImage, aov: Aov) -> ExrImage {
+        match aov {
+            Aov::Depth => exr.with_channel("depth.Z", &self.depth),
+            Aov::Position => exr.with_layer("position", &self.position),
+            Aov::Normal => exr.with_layer("normal", &self.normal),
+            Aov::Albedo => exr.with_layer("albedo", &self.albedo),
+            Aov::MaterialId => exr.with_channel("material.id", &self.material),
+            Aov::ObjectId => exr.with_channel("object.id", &self.object),
+            Aov::Direct => exr.with_layer("direct", &self.direct),
+            Aov::Indirect => exr.with_layer("indirect", &self.indirect),
+            Aov::Emission => exr.with_layer("emission", &self.emission),
+            Aov::Samples => exr.with_channel("samples.count", &self.samples),
+        }
+    }
+
+    // As image.bmp, denoised.bmp and `<aov>.bmp`, or all in image.exr with
+    // the beauty image as the main layer
+    pub fn write(
+        &self,
+        selection: &AovSelection,
+        format: OutputFormat,
+        image: &Image<RgbF32>,
+        denoised: &Image<RgbF32>,
+        options: BmpOptions,
+    ) -> Result<(), (String, ImageError)> {
+        match format {
+            OutputFormat::Bmp => {
+                let passes = [("image".to_string(), image.map(to_rgb8)), ("denoised".to_string(), denoised.map(to_rgb8))];
+                let aovs = selection.0.iter().map(|&aov| (aov.name().to_string(), self.to_bmp(aov)));
+                for (name, image) in passes.into_iter().chain(aovs) {
+                    let path = format!("{}.bmp", name);
+                    write_image_file(&path, &image, options).map_err(|err| (path, err))?;
+                }
+            }
+            OutputFormat::Exr => {
+                let exr = ExrImage::new(image.width(), image.height())
+                    .with_row_order(options.row_order)
+                    .with_layer("", image)
+                    .with_layer("denoised", denoised);
+                let exr = selection.0.iter().fold(exr, |exr, &aov| self.add_layer(exr, aov));
+                write_exr_file("image.exr", &exr).map_err(|err| ("image.exr".to_string(), err))?;
+            }
+        }
+        Ok(())
+    }
+}
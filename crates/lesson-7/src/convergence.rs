@@ -0,0 +1,109 @@
+use std::time::Instant;
+
+use cgmath::Vector3;
+use graphics_core::{color::RgbF32, Image};
+
+use crate::{
+    get_color, pixel_to_position,
+    sampling::{Sampler, SamplerKind},
+    spectral::Rgb,
+    Camera, Scene,
+};
+
+// Small enough that the reference renders in seconds
+const SIZE: usize = 64;
+const REFERENCE_SAMPLES: u32 = 16384;
+const MAX_SAMPLES: u32 = 256;
+const MAX_DEPTH: u8 = 3;
+
+// Box filtered, with the pixel position taken from the sampler's first 2D
+// dimension so it converges like the rest of the path
+fn render(scene: &Scene, camera: &Camera, kind: SamplerKind, samples: u32, seed: u32) -> Image<RgbF32> {
+    let mut image = Image::new(SIZE, SIZE);
+    image.par_rows_mut(|row, pixels| {
+        let mut sampler = kind.sampler(samples, seed);
+        for (col, pixel) in pixels.iter_mut().enumerate() {
+            let mut color = Vector3::new(0.0, 0.0, 0.0);
+            for index in 0..samples {
+                sampler.start_sample(row, col, index);
+                color += trace(scene, camera, sampler.as_mut(), row, col);
+            }
+            color /= samples as f32;
+            *pixel = RgbF32::new(color.x, color.y, color.z);
+        }
+    });
+    image
+}
+
+fn trace(scene: &Scene, camera: &Camera, sampler: &mut dyn Sampler, row: usize, col: usize) -> Vector3<f32> {
+    let (dx, dy) = sampler.get_2d();
+    let ray = camera.ray(pixel_to_position(camera, row as f32 + dy, col as f32 + dx, SIZE, SIZE), sampler);
+    get_color(&Rgb, sampler, scene, &ray, 0, MAX_DEPTH).total()
+}
+
+fn rmse(image: &Image<RgbF32>, reference: &Image<RgbF32>) -> f64 {
+    let sum: f64 = image
+        .pixels()
+        .iter()
+        .zip(reference.pixels())
+        .map(|(a, b)| {
+            let (dr, dg, db) = ((a.r - b.r) as f64, (a.g - b.g) as f64, (a.b - b.b) as f64);
+            dr * dr + dg * dg + db * db
+        })
+        .sum();
+    (sum / (3 * image.pixels().len()) as f64).sqrt()
+}
+
+// Least squares slope of log(rmse) against log(samples). Plain Monte Carlo
+// converges at -0.5; stratification and low discrepancy do better where
+// the integrand is smooth enough.
+fn slope(errors: &[(u32, f64)]) -> f64 {
+    let points: Vec<(f64, f64)> = errors.iter().map(|&(n, e)| ((n as f64).ln(), e.ln())).collect();
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / count;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / count;
+    let covariance: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let variance: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.0 - mean_x)).sum();
+    covariance / variance
+}
+
+// Renders the scene small with every sampler at power of two sample counts
+// and prints the RMSE of each against an independent reference with many
+// more samples
+pub fn report(scene: &Scene, camera: &Camera) {
+    let now = Instant::now();
+    // A different seed from the runs measured against it, so their errors
+    // are independent of its own
+    let reference = render(scene, camera, SamplerKind::Independent, REFERENCE_SAMPLES, 0x2545f491);
+    println!(
+        "reference: {}x{}, {} independent samples per pixel, {} ms",
+        SIZE,
+        SIZE,
+        REFERENCE_SAMPLES,
+        now.elapsed().as_millis()
+    );
+
+    let counts: Vec<u32> = (0..).map(|i| 1 << i).take_while(|&n| n <= MAX_SAMPLES).collect();
+    let errors: Vec<Vec<(u32, f64)>> = SamplerKind::ALL
+        .iter()
+        .map(|&kind| counts.iter().map(|&n| (n, rmse(&render(scene, camera, kind, n, 1), &reference))).collect())
+        .collect();
+
+    print!("{:>8}", "samples");
+    for kind in SamplerKind::ALL {
+        print!("{:>14}", kind.name());
+    }
+    println!();
+    for (i, n) in counts.iter().enumerate() {
+        print!("{:>8}", n);
+        for errors in &errors {
+            print!("{:>14.6}", errors[i].1);
+        }
+        println!();
+    }
+    print!("{:>8}", "slope");
+    for errors in &errors {
+        print!("{:>14.3}", slope(errors));
+    }
+    println!();
+}
@@ -1,44 +1,101 @@
-mod files;
-use std::{time::Instant, thread, sync::{Mutex, RwLock, Arc}};
+use std::{fs::File, io::{self, Write}, sync::Mutex, time::{Duration, Instant}, process::ExitCode};
 use cgmath::{Vector3, InnerSpace};
-use rand::{Rng, prelude::ThreadRng};
 
-use crate::files::write_image_file;
+use graphics_core::{
+    color::{Rgb8, RgbF32},
+    denoise::Denoiser,
+    film::{Film, Filter},
+    files::{write_image_file, BmpOptions, RowOrder},
+    gif::{write_gif_file, GifOptions},
+    ndc::point_to_ndc,
+    png::{write_apng_file, write_png_file},
+    quantize::{Dither, MAX_COLORS},
+    sequence::FrameSequence,
+    y4m::{Chroma, Y4mWriter},
+    Image,
+};
+
+mod animation;
+mod aov;
+mod convergence;
+mod raster;
+mod sampling;
+mod shaders;
+mod spectral;
+mod volume;
+
+use animation::{Curve, FrameRange, Motion, Sequence, Track};
+use aov::{to_rgb8, AovSelection, Aovs, OutputFormat, PixelSum};
+use sampling::{Pattern, PixelSampler, Sampler, SamplerKind};
+use shaders::Shading;
+use spectral::{ColorMode, ColorModel, Glass, PathColor, Rgb, Spectra, Spectral};
+use volume::{Media, Medium, Volume};
 
 const HEIGHT: usize = 2048;
 const WIDTH: usize = HEIGHT;
-const BYTES_PER_PIXEL: usize = 3;
-const ARRAY_LENGTH: usize = HEIGHT * WIDTH * BYTES_PER_PIXEL;
+// Distance from the camera to the screen plane, which spans -1..1
+const DIST_TO_SCREEN: f32 = 2.0;
 
+#[derive(Clone, Copy)]
 struct Intersection {
     distance: f32,
     normal: Vector3<f32>,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 struct Material {
     emittance: Vector3<f32>,
     reflectance: f32,
     specular: f32,
+    // Clear glass, which only reflects and refracts
+    glass: Option<Glass>,
+}
+
+impl Material {
+    // The denoiser's and the albedo AOV's idea of the surface color: the
+    // diffuse reflectance, tinted by the emitted color on surfaces that glow,
+    // which is where the color in this scene comes from
+    fn albedo(&self) -> Vector3<f32> {
+        let diffuse = self.reflectance * (1.0 - self.specular);
+        let brightest = self.emittance.x.max(self.emittance.y).max(self.emittance.z);
+        let tint = if brightest > 0.0 { self.emittance / brightest } else { Vector3::new(1.0, 1.0, 1.0) };
+        tint * diffuse
+    }
 }
 
 struct Sphere {
     center: Vector3<f32>,
     radius: f32,
     material: Material,
+    motion: Option<Motion>,
+    // A medium filling the sphere. Rays only get inside through glass, so
+    // it is entered and left where they refract.
+    interior: Option<Medium>,
 }
 
 impl Sphere {
+    // The center and radius at `time`
+    fn at(&self, time: f32) -> (Vector3<f32>, f32) {
+        match &self.motion {
+            Some(motion) => {
+                let (translation, scale) = motion.at(time);
+                (self.center + translation, self.radius * scale)
+            }
+            None => (self.center, self.radius),
+        }
+    }
+
     fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (center, radius) = self.at(ray.time);
         // geometric solution
-        let l = self.center - ray.origin; 
+        let l = center - ray.origin; 
         let tca = l.dot(ray.direction); 
         if tca < 0.0 {
             return None;
         }
 
         let d2 = l.dot(l) - tca * tca; 
-        let radius2 = self.radius * self.radius;
+        let radius2 = radius * radius;
         if d2 > radius2 {
             return None; 
         }
@@ -57,12 +114,12 @@ impl Sphere {
 
         let distance = t;
         let position = ray.origin + ray.direction * t;
-        let normal = (position - self.center).normalize();
+        let normal = (position - center).normalize();
         
-        return Some(Intersection {
+        Some(Intersection {
             distance,
             normal
-        });
+        })
     }
 }
 
@@ -75,52 +132,175 @@ struct DirectionalLight {
 struct Scene {
     spheres: Vec<Sphere>,
     lights: Vec<DirectionalLight>,
+    volumes: Vec<Volume>,
+}
+
+impl Scene {
+    // The index of the nearest sphere along the ray, and where it is hit
+    fn intersect(&self, ray: &Ray) -> Option<(usize, Intersection)> {
+        let mut nearest: Option<(usize, Intersection)> = None;
+        for (index, sphere) in self.spheres.iter().enumerate() {
+            if let Some(intersection) = sphere.intersect(ray) {
+                if nearest.as_ref().is_some_and(|(_, nearest)| intersection.distance >= nearest.distance) {
+                    continue;
+                }
+                nearest = Some((index, intersection));
+            }
+        }
+        nearest
+    }
+
+    // Every medium with the sphere it fills at `time`: the volumes, and
+    // the interiors of spheres
+    fn media(&self, time: f32) -> impl Iterator<Item = ((Vector3<f32>, f32), &Medium)> {
+        let volumes = self.volumes.iter().map(|volume| (volume.bounds(), &volume.medium));
+        let interiors =
+            self.spheres.iter().filter_map(move |sphere| sphere.interior.as_ref().map(|medium| (sphere.at(time), medium)));
+        volumes.chain(interiors)
+    }
+
+    // Where the ray first collides with a medium before `distance`, and the
+    // medium. Media that overlap collide independently, the first collision
+    // of any of them is the first collision in their sum.
+    fn scatter(&self, ray: &Ray, distance: f32, sampler: &mut dyn Sampler) -> Option<(f32, &Medium)> {
+        let mut nearest: Option<(f32, &Medium)> = None;
+        for (bounds, medium) in self.media(ray.time) {
+            if let Some(t) = medium.sample_collision(bounds, ray, distance, sampler) {
+                if nearest.is_none_or(|(nearest, _)| t < nearest) {
+                    nearest = Some((t, medium));
+                }
+            }
+        }
+        nearest
+    }
+
+    fn transmittance(&self, ray: &Ray, distance: f32, sampler: &mut dyn Sampler) -> f32 {
+        self.media(ray.time).map(|(bounds, medium)| medium.transmittance(bounds, ray, distance, sampler)).product()
+    }
+}
+
+// The times, in frames, the shutter opens and closes
+#[derive(Clone, Copy)]
+struct Shutter {
+    open: f32,
+    close: f32,
 }
 
 #[derive(Clone, Copy)]
 struct Camera {
     position: Vector3<f32>,
-    forward: Vector3<f32>
+    forward: Vector3<f32>,
+    shutter: Shutter,
+}
+
+impl Camera {
+    // A ray from the eye through `position` on the screen plane, at a
+    // uniformly sampled time while the shutter is open. That takes one 1D
+    // dimension from the sampler, unless the shutter opens and closes at once.
+    fn ray(&self, position: Vector3<f32>, sampler: &mut dyn Sampler) -> Ray {
+        let Shutter { open, close } = self.shutter;
+        let time = if close > open { open + (close - open) * sampler.get_1d() } else { open };
+        Ray { origin: position, direction: (position - self.position).normalize(), time }
+    }
 }
 
 struct Ray {
     origin: Vector3<f32>,
-    direction: Vector3<f32>
+    direction: Vector3<f32>,
+    // Everything the ray meets is where it is at this time
+    time: f32,
 }
 
-fn pixel_to_position(camera: &Camera, row: usize, col: usize, row_size: usize, col_size: usize) -> Vector3<f32> {
-    let dist_to_screen = 2.0;
+// `row` and `col` are fractional, (0.5, 0.5) is the center of the first pixel
+fn pixel_to_position(camera: &Camera, row: f32, col: f32, width: usize, height: usize) -> Vector3<f32> {
+    let (x, y) = point_to_ndc(row, col, width, height);
+    // The screen plane faces the camera, with its x axis level
+    let right = Vector3::new(0.0, 1.0, 0.0).cross(camera.forward).normalize();
+    let up = camera.forward.cross(right);
+    let point_in_screen_plane = right * x + up * y;
 
-    let x = (col as f32 - col_size as f32 / 2.0) / (0.5 * col_size as f32);
-    let y = (row as f32 - row_size as f32 / 2.0) / (0.5 * row_size as f32);
-    let z = 0.0;
-    let point_in_screen_plane = Vector3::new(x, y, z);
+    camera.position + camera.forward * DIST_TO_SCREEN + point_in_screen_plane
+}
+
+// The light coming back along a ray, split by how it got there
+#[derive(Clone, Copy)]
+struct Radiance<C = Vector3<f32>> {
+    // Emitted by what the ray hits, or the lights if it hits nothing
+    emission: C,
+    // Reflected straight from something emitting
+    direct: C,
+    // Reflected after more than one bounce
+    indirect: C,
+}
+
+impl<C: PathColor> Radiance<C> {
+    const ZERO: Radiance<C> = Radiance { emission: C::ZERO, direct: C::ZERO, indirect: C::ZERO };
+
+    fn total(&self) -> C {
+        self.emission + self.direct + self.indirect
+    }
+
+    fn map<D>(self, f: impl Fn(C) -> D) -> Radiance<D> {
+        Radiance { emission: f(self.emission), direct: f(self.direct), indirect: f(self.indirect) }
+    }
+}
 
-    camera.position + camera.forward * dist_to_screen + point_in_screen_plane
+// Each bounce takes one 1D dimension from the sampler for the choice
+// between a specular and a diffuse bounce, and one 2D dimension for the
+// diffuse direction, whichever way the choice goes. Tracking through media
+// takes as many 1D dimensions as it takes steps, before the bounce's own.
+// Glass draws the same, the 1D one choosing between reflection and
+// refraction.
+fn get_color<M: ColorModel>(
+    model: &M,
+    sampler: &mut dyn Sampler,
+    scene: &Scene,
+    ray: &Ray,
+    depth: u8,
+    max_depth: u8,
+) -> Radiance<M::Color> {
+    shade(model, sampler, scene, ray, scene.intersect(ray), depth, max_depth)
 }
 
-fn get_color(rng: &mut ThreadRng, scene: &Scene, ray: &Ray, depth: u8, max_depth: u8) -> Vector3<f32> {
-    let mut color: Vector3<f32> = Vector3::new(0.,0.,0.);
+// `get_color` for a ray whose surface is already known, so camera rays
+// aren't intersected again for their AOVs
+fn shade<M: ColorModel>(
+    model: &M,
+    sampler: &mut dyn Sampler,
+    scene: &Scene,
+    ray: &Ray,
+    surface: Option<(usize, Intersection)>,
+    depth: u8,
+    max_depth: u8,
+) -> Radiance<M::Color> {
+    let mut color = Radiance::ZERO;
     if depth == max_depth {
         return color;
     }
-    
-    let mut min_depth = f32::MAX;
-    let mut nearest_intersection = None;
-    let mut material = None;
-    for sphere in &scene.spheres {
-        if let Some(intersection) = sphere.intersect(ray) {
-            if intersection.distance >= min_depth {
-                continue;
-            }
-            min_depth = intersection.distance;
-            nearest_intersection = Some(intersection);
-            material = Some(sphere.material);
+
+    let distance = surface.as_ref().map_or(f32::INFINITY, |(_, intersection)| intersection.distance);
+    // On the last bounce light scattered by a medium can't be followed any
+    // further, so all that matters is how much gets through
+    let mut transmittance = 1.0;
+    if depth + 1 < max_depth {
+        if let Some((t, medium)) = scene.scatter(ray, distance, sampler) {
+            let new_ray = Ray {
+                origin: ray.origin + ray.direction * t,
+                direction: medium.scatter(ray.direction, sampler.get_2d()),
+                time: ray.time,
+            };
+            let incoming = get_color(model, sampler, scene, &new_ray, depth + 1, max_depth);
+            let albedo = model.albedo(medium.albedo());
+            color.direct = incoming.emission.mul_color(albedo);
+            color.indirect = (incoming.direct + incoming.indirect).mul_color(albedo);
+            return color;
         }
+    } else {
+        transmittance = scene.transmittance(ray, distance, sampler);
     }
 
-    if let Some(intersection) = nearest_intersection {
-        let material = material.unwrap();
+    if let Some((index, intersection)) = surface {
+        let material = scene.spheres[index].material;
         const PI: f32 = std::f32::consts::PI;
         const P: f32 = 1. / (2. * PI);
 
@@ -152,52 +332,327 @@ fn get_color(rng: &mut ThreadRng, scene: &Scene, ray: &Ray, depth: u8, max_depth
 
         // }
 
-        let specular_bounce = rng.gen_bool(material.specular as f64);
-        let new_direction = if specular_bounce {
-            ray.direction - 2.0 * (ray.direction.dot(intersection.normal)) * intersection.normal
+        let new_origin = ray.origin + ray.direction * intersection.distance;
+        let (new_ray, weight) = if let Some(glass) = material.glass {
+            let u = sampler.get_1d();
+            sampler.get_2d();
+            let (ior, weight) = model.refract(glass);
+            let new_direction = glass.scatter(ray.direction, intersection.normal, ior, u);
+            // Started off the surface, so it doesn't hit the sphere where it
+            // leaves it
+            let new_ray = Ray { origin: new_origin + new_direction * 1e-3, direction: new_direction, time: ray.time };
+            (new_ray, weight)
         } else {
-            loop {
-                let u1: f32 = rng.gen_range(0.0..1.0);
-                let u2: f32 = rng.gen_range(0.0..1.0);
+            let specular_bounce = sampler.get_1d() < material.specular;
+            let (u1, u2) = sampler.get_2d();
+            let new_direction = if specular_bounce {
+                ray.direction - 2.0 * (ray.direction.dot(intersection.normal)) * intersection.normal
+            } else {
                 let lambda = f32::acos(2.0*u1 - 1.0) - PI / 2.0;
                 let phi = 2.0 * PI * u2;
                 let rand_direction = Vector3::new(lambda.cos()*phi.cos(), lambda.cos()*phi.sin(), lambda.sin());
 
+                // Uniform over the sphere, flipped into the normal's hemisphere.
+                // Rejecting the other half instead would take a varying number
+                // of dimensions.
                 if rand_direction.dot(intersection.normal) >= 0.0 {
-                    break rand_direction;
+                    rand_direction
+                } else {
+                    -rand_direction
                 }
+            };
 
-            }
-        };
+            let new_ray = Ray {
+                origin: new_origin,
+                direction: new_direction,
+                time: ray.time,
+            };
 
-        let new_origin = ray.origin + ray.direction * intersection.distance;
-        let new_ray = Ray {
-            origin: new_origin,
-            direction: new_direction,
-        };
-
-        let cos_theta = new_ray.direction.dot(intersection.normal);
-        let brdf = material.reflectance / PI;
+            let cos_theta = new_ray.direction.dot(intersection.normal);
+            // TODO: https://google.github.io/filament/Filament.html#materialsystem/diffusebrdf
+            let brdf = material.reflectance / PI;
 
-        let incoming_bounce_color = get_color(rng, scene, &new_ray, depth + 1, max_depth);
+            (new_ray, M::Color::ONE * (brdf * cos_theta / P))
+        };
 
-        color += material.emittance + (brdf * incoming_bounce_color * cos_theta / P);
+        let incoming_bounce_color = get_color(model, sampler, scene, &new_ray, depth + 1, max_depth);
+        color.emission = model.illuminant(material.emittance);
+        color.direct = incoming_bounce_color.emission.mul_color(weight);
+        color.indirect = (incoming_bounce_color.direct + incoming_bounce_color.indirect).mul_color(weight);
     } else if depth != 0 {
         // Didn't hit anything so return the light color
         for light in &scene.lights {
             let cos_theta = ray.direction.dot(-light.direction).clamp(0.0, 1.0);
-            color += light.color * cos_theta * light.intensity;
+            color.emission += model.illuminant(light.color) * (cos_theta * light.intensity);
+        }
+    }
+
+    color.emission *= transmittance;
+    color.direct *= transmittance;
+    color.indirect *= transmittance;
+    color
+}
+
+// Parses the optional argument at `index`, printing the error if it doesn't
+fn parse_arg<T: std::str::FromStr + Default>(args: &[String], index: usize) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    match args.get(index).map_or(Ok(T::default()), |arg| arg.parse()) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            eprintln!("{}", err);
+            None
         }
     }
+}
+
+// The value after `name` among `args`, or `default` without the flag,
+// printing the error if it doesn't parse
+fn parse_flag<T: std::str::FromStr>(args: &[String], name: &str, default: T) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let Some(index) = args.iter().position(|arg| arg == name) else {
+        return Some(default);
+    };
+    match args.get(index + 1).map(|arg| arg.parse()) {
+        Some(Ok(value)) => Some(value),
+        Some(Err(err)) => {
+            eprintln!("invalid {}: {}", name, err);
+            None
+        }
+        None => {
+            eprintln!("missing value for {}", name);
+            None
+        }
+    }
+}
 
-    return color;
+#[derive(Default)]
+struct TraceSettings {
+    pattern: Pattern,
+    filter: Filter,
+    sampler_kind: SamplerKind,
+    color_mode: ColorMode,
 }
 
-// TODO: https://google.github.io/filament/Filament.html#materialsystem/diffusebrdf
-fn main() {
+const SAMPLES: usize = 32;
+
+// The filtered image, with the AOVs gathered along the way
+fn trace(scene: &Scene, camera: &Camera, settings: &TraceSettings, width: usize, height: usize) -> (Image<RgbF32>, Aovs) {
+    let pixel_sampler = PixelSampler::new(settings.pattern, SAMPLES);
+    let mut film = Film::new(width, height, settings.filter);
+    let seed = rand::random();
+
+    let material_ids = aov::material_ids(scene);
+    let passes = Mutex::new(Aovs::new(width, height));
+    let spectra = (settings.color_mode == ColorMode::Spectral).then(|| Spectra::new(scene));
+
+    film.par_rows(|row, film| {
+        let mut rng = rand::thread_rng();
+        let mut sampler = settings.sampler_kind.sampler(SAMPLES as u32, seed);
+        let mut sums = vec![PixelSum::default(); width];
+        for (col, sum) in sums.iter_mut().enumerate() {
+            // Each sample gets its own ray through its own point in the
+            // pixel, and the filter weighs it by where that point is
+            for (index, (dx, dy)) in pixel_sampler.offsets(&mut rng).into_iter().enumerate() {
+                sampler.start_sample(row, col, index as u32);
+                let (x, y) = (col as f32 + dx, row as f32 + dy);
+                let ray = camera.ray(pixel_to_position(camera, y, x, width, height), sampler.as_mut());
+                let surface = scene.intersect(&ray);
+                // Spectral paths take one more 1D dimension, for the hero
+                // wavelength, and arrive at the film as RGB like the others
+                let radiance = match &spectra {
+                    None => shade(&Rgb, sampler.as_mut(), scene, &ray, surface, 0, 3),
+                    Some(spectra) => {
+                        let model = Spectral::new(spectra, sampler.get_1d());
+                        shade(&model, sampler.as_mut(), scene, &ray, surface, 0, 3).map(|spectrum| model.to_rgb(spectrum))
+                    }
+                };
+                let color = radiance.total();
+                film.add_sample(x, y, [color.x, color.y, color.z]);
+                sum.add(scene, &material_ids, &ray, surface, (dx, dy), &radiance);
+            }
+        }
+        passes.lock().unwrap().set_row(row, &sums);
+    });
+    (film.develop(), passes.into_inner().unwrap())
+}
+
+// A frame as the video and image writers take it: raw 8 bit values like
+// the still renders, and top down where the film's rows go bottom up
+fn frame_pixels(image: &Image<RgbF32>) -> Image<Rgb8> {
+    let pixels = image.rows().rev().flatten().map(|&p| to_rgb8(p)).collect();
+    Image::from_pixels(image.width(), image.height(), pixels).unwrap()
+}
+
+const ANIMATE_FLAGS: [&str; 13] = [
+    "--frames", "--step", "--shutter", "--size", "--gif", "--apng", "--delay", "--loops", "--colors", "--dither", "--y4m",
+    "--chroma", "--color",
+];
+
+// Renders every `step`th frame of the sequence, denoised, to its own
+// numbered PNG, and also to an animated GIF or APNG if given a path for
+// one. Streaming Y4M video replaces the numbered PNGs. Each frame's shutter
+// opens at its frame number. Progress goes to stderr, so stdout can carry
+// the video.
+fn animate(mut scene: Scene, mut camera: Camera, glass_sphere: Sphere, args: &[String]) -> ExitCode {
+    let (sequence, flags) = match args.first() {
+        Some(arg) if !arg.starts_with("--") => (parse_arg::<Sequence>(args, 0), &args[1..]),
+        _ => (Some(Sequence::default()), args),
+    };
+    if let Some(flag) = flags.iter().step_by(2).find(|flag| !ANIMATE_FLAGS.contains(&flag.as_str())) {
+        eprintln!("unknown flag `{}`, expected {}", flag, ANIMATE_FLAGS.join(", "));
+        return ExitCode::FAILURE;
+    }
+    let (Some(sequence), Some(frames), Some(step), Some(shutter), Some(size)) = (
+        sequence,
+        parse_flag(flags, "--frames", FrameRange::default()),
+        parse_flag(flags, "--step", 1u32),
+        parse_flag(flags, "--shutter", 0.0f32),
+        parse_flag(flags, "--size", HEIGHT),
+    ) else {
+        return ExitCode::FAILURE;
+    };
+    if step == 0 || size == 0 || !shutter.is_finite() || shutter < 0.0 {
+        eprintln!("--step and --size need to be at least 1 and --shutter at least 0");
+        return ExitCode::FAILURE;
+    }
+    let (Some(gif), Some(apng), Some(delay), Some(loops), Some(colors), Some(dither)) = (
+        parse_flag(flags, "--gif", String::new()),
+        parse_flag(flags, "--apng", String::new()),
+        parse_flag(flags, "--delay", 40u64),
+        parse_flag(flags, "--loops", 0u32),
+        parse_flag(flags, "--colors", MAX_COLORS),
+        parse_flag(flags, "--dither", Dither::default()),
+    ) else {
+        return ExitCode::FAILURE;
+    };
+    if !(2..=MAX_COLORS).contains(&colors) {
+        eprintln!("--colors needs to be from 2 to {}", MAX_COLORS);
+        return ExitCode::FAILURE;
+    }
+    let (Some(y4m), Some(chroma), Some(color_mode)) = (
+        parse_flag(flags, "--y4m", String::new()),
+        parse_flag(flags, "--chroma", Chroma::default()),
+        parse_flag(flags, "--color", ColorMode::default()),
+    ) else {
+        return ExitCode::FAILURE;
+    };
+    if delay == 0 || delay > u32::MAX as u64 {
+        eprintln!("--delay needs to be from 1 to {} ms", u32::MAX);
+        return ExitCode::FAILURE;
+    }
+    let stream: Option<Box<dyn Write>> = match y4m.as_str() {
+        "" => None,
+        "-" => Some(Box::new(io::BufWriter::new(io::stdout().lock()))),
+        path => match File::create(path) {
+            Ok(file) => Some(Box::new(io::BufWriter::new(file))),
+            Err(err) => {
+                eprintln!("failed to create {}: {}", path, err);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    // One frame every `delay` milliseconds
+    let mut video = stream.map(|stream| Y4mWriter::new(stream, size, size).with_frame_rate(1000, delay as u32).with_chroma(chroma));
+    // Only kept when they go into an animation as well
+    let mut sequence_frames = (!gif.is_empty() || !apng.is_empty()).then(|| FrameSequence::new(size, size).with_plays(loops));
+
+    let animation = sequence.animation(frames);
+    if color_mode == ColorMode::Spectral {
+        scene.spheres.push(glass_sphere);
+    }
+    let settings = TraceSettings { color_mode, ..Default::default() };
+    for frame in (frames.first..=frames.last).step_by(step as usize) {
+        let time = frame as f32;
+        animation.apply(&mut scene, &mut camera, time);
+        camera.shutter = Shutter { open: time, close: time + shutter };
+
+        let now = Instant::now();
+        let (image, passes) = trace(&scene, &camera, &settings, size, size);
+        let denoised = frame_pixels(&Denoiser::new().denoise(&image, &passes.feature_buffers()));
+
+        if let Some(video) = &mut video {
+            if let Err(err) = video.write_frame(&denoised) {
+                eprintln!("failed to write frame {} to {}: {}", frame, y4m, err);
+                return ExitCode::FAILURE;
+            }
+            eprintln!("frame {} in {} ms", frame, now.elapsed().as_millis());
+        } else {
+            let path = format!("frame_{:04}.png", frame);
+            if let Err(err) = write_png_file(&path, &denoised) {
+                eprintln!("failed to write {}: {}", path, err);
+                return ExitCode::FAILURE;
+            }
+            eprintln!("{} in {} ms", path, now.elapsed().as_millis());
+        }
+        if let Some(sequence_frames) = &mut sequence_frames {
+            sequence_frames.push(denoised, Duration::from_millis(delay));
+        }
+    }
+
+    if let Some(Err(err)) = video.map(Y4mWriter::into_inner) {
+        eprintln!("failed to write {}: {}", y4m, err);
+        return ExitCode::FAILURE;
+    }
+    if let Some(sequence_frames) = &sequence_frames {
+        if !gif.is_empty() {
+            if let Err(err) = write_gif_file(&gif, sequence_frames, GifOptions { colors, dither }) {
+                eprintln!("failed to write {}: {}", gif, err);
+                return ExitCode::FAILURE;
+            }
+        }
+        if !apng.is_empty() {
+            if let Err(err) = write_apng_file(&apng, sequence_frames) {
+                eprintln!("failed to write {}: {}", apng, err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+enum Mode {
+    Trace,
+    Raster(Shading),
+    Convergence,
+    Animate,
+}
+
+// usage: lesson-7 [trace [center|stratified|halton|sobol|blue-noise] [box|tent|gaussian|mitchell|lanczos]
+//                        [independent|stratified|halton|sobol] [all|none|<aov>,<aov>...] [bmp|exr]
+//                        [vacuum|fog|cloud|smoke|all|milk] [shutter] [rgb|spectral]]
+//        lesson-7 animate [turntable|flythrough] [--frames <first>-<last>] [--step <n>] [--shutter <frames>] [--size <pixels>]
+//                         [--gif <path>] [--apng <path>] [--delay <ms>] [--loops <n>] [--colors <n>] [--dither none|floyd-steinberg]
+//                         [--y4m <path>|-] [--chroma 420|444] [--color rgb|spectral]
+// where loops is how many times the animation plays, 0 for forever, delay also sets the Y4M frame rate
+// and - streams the video to stdout
+// where shutter is how many frames the shutter stays open, 0 for no motion blur
+// and spectral and milk renders get a flint glass sphere added to the scene, filled for milk
+//        lesson-7 raster [lambert|phong|normals|textured|toon]
+//        lesson-7 convergence
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = match args.first().map(String::as_str) {
+        None | Some("trace") => Mode::Trace,
+        Some("raster") => match parse_arg(&args, 1) {
+            Some(shading) => Mode::Raster(shading),
+            None => return ExitCode::FAILURE,
+        },
+        Some("convergence") => Mode::Convergence,
+        Some("animate") => Mode::Animate,
+        Some(arg) => {
+            eprintln!("unknown mode `{}`, expected trace, raster, convergence or animate", arg);
+            return ExitCode::FAILURE;
+        }
+    };
+
     let camera = Camera {
         position: Vector3::new(0.0, 0.0, -10.0),
         forward: Vector3::new(0.0, 0.0, 1.0),
+        shutter: Shutter { open: 0.0, close: 0.0 },
     };
 
     const PURPLE: Vector3<f32> = Vector3::new(0.8, 0.1, 0.8);
@@ -210,34 +665,47 @@ fn main() {
         emittance: PURPLE,
         reflectance: 1.0,
         specular: 0.0,
+        glass: None,
     };
     let red_material = Material {
         emittance: RED,
         reflectance: 1.0,
         specular: 0.0,
+        glass: None,
     };
     let green_material = Material {
         emittance: GREEN,
         reflectance: 1.0,
         specular: 0.0,
+        glass: None,
     };
     let white_material = Material {
         emittance: WHITE,
         reflectance: 1.0,
         specular: 0.0,
+        glass: None,
     };
     let black_material = Material {
         emittance: BLACK,
         reflectance: 1.0,
         specular: 0.0,
+        glass: None,
     };
     let black_shiny_material = Material {
         emittance: BLACK,
         reflectance: 1.0,
         specular: 1.0,
+        glass: None,
+    };
+    let glass_material = Material {
+        emittance: BLACK,
+        reflectance: 1.0,
+        specular: 0.0,
+        glass: Some(Glass::FLINT),
     };
 
     let scene = Scene {
+        volumes: Vec::new(),
         lights: vec![
             DirectionalLight { direction: Vector3::new(-1.0, 0.0, 0.0), intensity: 0.5, color: Vector3::new(1.,1.,1.) },
             DirectionalLight { direction: Vector3::new(0.0, -1.0, 0.0), intensity: 0.25, color: Vector3::new(0.0,0.0,1.) },
@@ -252,104 +720,200 @@ fn main() {
                 center: Vector3::new(-4.5, -4.5, 10.0),
                 radius: 2.0,
                 material: black_shiny_material,
+                motion: None,
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(-4.5, 4.5, 10.0),
                 radius: 2.0,
                 material: green_material,
+                motion: Some(Motion::new(
+                    Track::new(Curve::Linear, &[(0.0, Vector3::new(0.0, 0.0, 0.0)), (1.0, Vector3::new(0.0, -1.2, 0.0))]),
+                    Track::new(Curve::Linear, &[(0.0, 1.0), (1.0, 0.9)]),
+                )),
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(4.0, 0.0, 4.5),
                 radius: 2.0,
                 material: black_material,
+                motion: None,
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(-1.0, 0.0, 5.0),
                 radius: 1.0,
                 material: red_material,
+                motion: None,
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(-3.0, 0.0, 6.0),
                 radius: 1.0,
                 material: black_material, 
+                motion: None,
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(-5.0, 0.0, 7.0),
                 radius: 1.0,
                 material: black_material,
+                motion: None,
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(0.0, 3.0, 15.0),
                 radius: 1.0,
                 material: white_material,
+                motion: None,
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(0.0, 5.0, 16.0),
                 radius: 1.0,
                 material: black_material,
+                motion: None,
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(0.0, -2.0, 3.0),
                 radius: 1.0,
                 material: purple_material,
+                motion: Some(Motion::new(
+                    Track::new(Curve::Linear, &[(0.0, Vector3::new(0.0, 0.0, 0.0)), (1.0, Vector3::new(1.5, 0.0, 0.0))]),
+                    Track::constant(1.0),
+                )),
+                interior: None,
             },
             Sphere {
                 center: Vector3::new(0.3, 0.3, 4.5),
                 radius: 1.0,
                 material: black_material,
+                motion: None,
+                interior: None,
             },
         ]
     };
+    // Only added to spectral renders, the only ones that show its
+    // dispersion. It sits in front of the white sphere, whose light it
+    // splits at the edges.
+    let glass_sphere = Sphere {
+        center: Vector3::new(0.0, 2.3, 9.0),
+        radius: 1.5,
+        material: glass_material,
+        motion: None,
+        interior: None,
+    };
 
-    let scene = Arc::new(RwLock::new(scene));
+    // Rows count up from y = -1, so the first row is the bottom of the image
+    let options = BmpOptions { row_order: RowOrder::BottomUp, ..Default::default() };
 
-    let image_bytes: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(Vec::with_capacity(ARRAY_LENGTH)));
-    unsafe {
-        image_bytes.lock().unwrap().set_len(ARRAY_LENGTH);
+    if let Mode::Convergence = mode {
+        convergence::report(&scene, &camera);
+        return ExitCode::SUCCESS;
     }
 
-    let now = Instant::now();
-    let mut results = Vec::new();
-    for row in 0..HEIGHT {
-        let scene = scene.clone();
-        let image_bytes = image_bytes.clone();
-        results.push(thread::spawn(move || {
-            let scene = scene.read().unwrap();
-            let mut rng = rand::thread_rng();
-            for col in 0..WIDTH {
-                let pixel_pointer: usize = WIDTH * BYTES_PER_PIXEL * row + BYTES_PER_PIXEL * col;
-                let blue_pointer = pixel_pointer;
-                let green_pointer = pixel_pointer + 1;
-                let red_pointer = pixel_pointer + 2;
-
-                let pixel_position = pixel_to_position(&camera, row, col, WIDTH, HEIGHT);
-
-                let ray = Ray {
-                    origin: pixel_position,
-                    direction: (pixel_position - camera.position).normalize(),
-                };
+    if let Mode::Animate = mode {
+        return animate(scene, camera, glass_sphere, &args[1..]);
+    }
 
-                const SAMPLES: u32 = 32;
-                let mut color = Vector3::new(0.0,0.0,0.0);
-                for _ in  0..SAMPLES {
-                    color += get_color(&mut rng, &scene, &ray, 0, 3);
-                }
-                color /= SAMPLES as f32;
+    if let Mode::Raster(shading) = mode {
+        let now = Instant::now();
+        let image = raster::render(&scene, &camera, shading, WIDTH, HEIGHT);
+        println!("{} ms", now.elapsed().as_millis());
+        if let Err(err) = write_image_file("raster.bmp", &image, options) {
+            eprintln!("failed to write raster.bmp: {}", err);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
 
-                let mut image_bytes = image_bytes.lock().unwrap();
-                image_bytes[blue_pointer] = (color.z * 255.0).round() as u8;
-                image_bytes[green_pointer] = (color.y * 255.0).round() as u8;
-                image_bytes[red_pointer] = (color.x * 255.0).round() as u8;
-            }
-        }));
+    let (Some(pattern), Some(filter), Some(sampler_kind), Some(aovs), Some(format), Some(media), Some(shutter), Some(color_mode)) = (
+        parse_arg::<Pattern>(&args, 1),
+        parse_arg::<Filter>(&args, 2),
+        parse_arg::<SamplerKind>(&args, 3),
+        parse_arg::<AovSelection>(&args, 4),
+        parse_arg::<OutputFormat>(&args, 5),
+        parse_arg::<Media>(&args, 6),
+        parse_arg::<f32>(&args, 7),
+        parse_arg::<ColorMode>(&args, 8),
+    ) else {
+        return ExitCode::FAILURE;
+    };
+    if !shutter.is_finite() || shutter < 0.0 {
+        eprintln!("invalid shutter `{}`, expected a number of frames of at least 0", shutter);
+        return ExitCode::FAILURE;
     }
-    for result in results {
-        result.join().unwrap();
+    let camera = Camera { shutter: Shutter { open: 0.0, close: shutter }, ..camera };
+    let mut scene = Scene { volumes: media.volumes(), ..scene };
+    if color_mode == ColorMode::Spectral || media.interior().is_some() {
+        scene.spheres.push(Sphere { interior: media.interior(), ..glass_sphere });
     }
+
+    let settings = TraceSettings { pattern, filter, sampler_kind, color_mode };
+    let now = Instant::now();
+    let (image, passes) = trace(&scene, &camera, &settings, WIDTH, HEIGHT);
     println!("{} ms", now.elapsed().as_millis());
-    {
-        let image_bytes = image_bytes.lock().unwrap();
-        let image_bytes = image_bytes.clone();
-        write_image_file(WIDTH, HEIGHT, image_bytes);
+
+    let now = Instant::now();
+    let denoised = Denoiser::new().denoise(&image, &passes.feature_buffers());
+    println!("denoised in {} ms", now.elapsed().as_millis());
+
+    // Written as the renderer computed them, without sRGB encoding
+    if let Err((path, err)) = passes.write(&aovs, format, &image, &denoised, options) {
+        eprintln!("failed to write {}: {}", path, err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graphics_core::{color::Rgba8, files::{read_bmp, write_image}};
+
+    #[test]
+    fn animation_frames_encode_like_still_renders() {
+        let values = [0.0, 0.2, 0.5, 1.0, 1.5, -0.1];
+        let pixels = values.iter().map(|&v| RgbF32::new(v, v * 0.5, 1.0 - v)).collect();
+        let image = Image::from_pixels(3, 2, pixels).unwrap();
+
+        // The still path: the bottom up film written as is to a BMP
+        let mut bmp = Vec::new();
+        let options = BmpOptions { row_order: RowOrder::BottomUp, ..Default::default() };
+        write_image(&mut bmp, &image.map(to_rgb8), options).unwrap();
+        let still: Image<Rgba8> = read_bmp(&bmp[..]).unwrap();
+
+        // The PNG and GIF writers take frames as Rgba8 and Rgb8
+        let frame = frame_pixels(&image);
+        assert_eq!(frame.convert::<Rgba8>().pixels(), still.pixels());
+        assert_eq!(frame.get(1, 2), Some(Rgb8::new(128, 64, 128)));
+
+        let mut from_frame = Y4mWriter::new(Vec::new(), 3, 2);
+        from_frame.write_frame(&frame).unwrap();
+        let mut from_still = Y4mWriter::new(Vec::new(), 3, 2);
+        from_still.write_frame(&still).unwrap();
+        assert_eq!(from_frame.into_inner().unwrap(), from_still.into_inner().unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sphere_interiors_only_count_inside_the_surface() {
+        let medium = Media::Milk.interior().unwrap();
+        let sigma_t = medium.absorption + medium.scattering;
+        let material = Material { emittance: Vector3::new(0.0, 0.0, 0.0), reflectance: 1.0, specular: 0.0, glass: Some(Glass::FLINT) };
+        let sphere = Sphere { center: Vector3::new(0.0, 0.0, 5.0), radius: 1.0, material, motion: None, interior: Some(medium) };
+        let scene = Scene { spheres: vec![sphere], lights: Vec::new(), volumes: Vec::new() };
+        let mut sampler = SamplerKind::Independent.sampler(1, 1);
+
+        // From outside the medium ends at the surface the ray hits
+        let outside = Ray { origin: Vector3::new(0.0, 0.0, 0.0), direction: Vector3::new(0.0, 0.0, 1.0), time: 0.0 };
+        let (_, hit) = scene.intersect(&outside).unwrap();
+        assert_eq!(scene.transmittance(&outside, hit.distance, sampler.as_mut()), 1.0);
+        assert!(scene.scatter(&outside, hit.distance, sampler.as_mut()).is_none());
+
+        // Refracted in, it goes through the medium up to where it leaves
+        let inside = Ray { origin: Vector3::new(0.0, 0.0, 4.5), direction: Vector3::new(0.0, 0.0, 1.0), time: 0.0 };
+        let (_, hit) = scene.intersect(&inside).unwrap();
+        let transmittance = scene.transmittance(&inside, hit.distance, sampler.as_mut());
+        assert!((transmittance - (-sigma_t * 1.5).exp()).abs() < 1e-5, "{}", transmittance);
+    }
+}
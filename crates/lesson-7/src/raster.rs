@@ -0,0 +1,68 @@
+use cgmath::{perspective, Matrix4, Point3, Rad, Vector3};
+use graphics_core::{
+    mesh::Mesh,
+    pipeline::{Framebuffer, Pipeline},
+    shader::FragmentShader,
+    Image, Rgb8,
+};
+
+use crate::{
+    shaders::{self, LambertShader, ModelShader, NormalShader, PhongShader, Shading, TexturedShader, ToonShader, Varying},
+    Camera, Scene, DIST_TO_SCREEN,
+};
+
+const RINGS: usize = 48;
+const SEGMENTS: usize = 96;
+
+// The ray tracer's camera as view and projection matrices. Its screen spans
+// -1..1 on both axes whatever the image size, hence the fixed aspect ratio.
+fn camera_matrices(camera: &Camera) -> (Matrix4<f32>, Matrix4<f32>) {
+    let eye = Point3::new(camera.position.x, camera.position.y, camera.position.z);
+    let view = Matrix4::look_to_lh(eye, camera.forward, Vector3::new(0.0, 1.0, 0.0));
+    let fov_y = Rad(2.0 * (1.0 / DIST_TO_SCREEN).atan());
+    // The scene is left-handed with the camera looking down +z, OpenGL style
+    // projections expect it to look down -z
+    let projection = perspective(fov_y, 1.0, 0.1, 100.0) * Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0);
+    (view, projection)
+}
+
+// Tessellates the spheres and draws them with the rasterization pipeline,
+// lit directly by the scene's lights. There are no bounces or shadows, so it
+// is a quick preview of what the ray tracer will frame.
+pub fn render(scene: &Scene, camera: &Camera, shading: Shading, width: usize, height: usize) -> Image<Rgb8> {
+    let (view, projection) = camera_matrices(camera);
+    let pipeline = Pipeline::new().with_view(view).with_projection(projection);
+    let mut target = Framebuffer::new(width, height);
+    target.clear(Rgb8::BLACK);
+
+    // Tessellated around the origin once and placed by the model matrix
+    let mesh = Mesh::uv_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0, RINGS, SEGMENTS);
+    let vertices = mesh.vertices();
+    let texture = shaders::checkerboard();
+    let lights = &scene.lights[..];
+    let eye = camera.position;
+
+    for sphere in &scene.spheres {
+        // Drawn where they are when the shutter opens, without motion blur
+        let (center, radius) = sphere.at(camera.shutter.open);
+        let model = Matrix4::from_translation(center) * Matrix4::from_scale(radius);
+        let pipeline = pipeline.with_model(model);
+        let vertex_shader = ModelShader {
+            model,
+            normal_matrix: pipeline.normal_matrix(),
+            view_projection: projection * view,
+        };
+        let material = sphere.material;
+        let mut draw = |fragment_shader: &dyn FragmentShader<Varying, Output = Rgb8>| {
+            pipeline.draw_shaded(&mut target, &vertex_shader, fragment_shader, &vertices, &mesh.triangles);
+        };
+        match shading {
+            Shading::Lambert => draw(&LambertShader { material, lights }),
+            Shading::Phong => draw(&PhongShader { material, lights, eye, shininess: 32.0 }),
+            Shading::Normals => draw(&NormalShader),
+            Shading::Textured => draw(&TexturedShader { material, lights, texture: &texture }),
+            Shading::Toon => draw(&ToonShader { material, lights, eye, bands: 3 }),
+        }
+    }
+    target.color
+}
@@ -0,0 +1,592 @@
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+
+// Where a pixel's samples go, as offsets in 0..1 from its top-left corner.
+// Every pattern but `Center` is randomized per pixel, so neighbouring
+// pixels don't repeat the same error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Pattern {
+    // Every sample through the pixel center, no anti-aliasing
+    Center,
+    // Multi-jittered: one sample in each cell of an m×n grid, and in each
+    // of its N columns and N rows
+    #[default]
+    Stratified,
+    // Halton bases 2 and 3, randomly shifted per pixel
+    Halton,
+    // The first two Sobol dimensions with a random digital shift per pixel,
+    // stratified for every power of two prefix
+    Sobol,
+    // A best-candidate point set, randomly shifted per pixel. The shift
+    // wraps around, and so does the distance the set was built with.
+    BlueNoise,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePatternError(String);
+
+impl fmt::Display for ParsePatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown sample pattern `{}`, expected center, stratified, halton, sobol or blue-noise", self.0)
+    }
+}
+
+impl std::error::Error for ParsePatternError {}
+
+impl FromStr for Pattern {
+    type Err = ParsePatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "center" => Ok(Pattern::Center),
+            "stratified" => Ok(Pattern::Stratified),
+            "halton" => Ok(Pattern::Halton),
+            "sobol" => Ok(Pattern::Sobol),
+            "blue-noise" => Ok(Pattern::BlueNoise),
+            _ => Err(ParsePatternError(s.to_string())),
+        }
+    }
+}
+
+pub struct PixelSampler {
+    pattern: Pattern,
+    count: usize,
+    // Built once, only the shift changes between pixels
+    blue_noise: Vec<(f32, f32)>,
+}
+
+impl PixelSampler {
+    pub fn new(pattern: Pattern, count: usize) -> PixelSampler {
+        let count = count.max(1);
+        let blue_noise = if pattern == Pattern::BlueNoise {
+            best_candidate(count, &mut rand::thread_rng())
+        } else {
+            Vec::new()
+        };
+        PixelSampler { pattern, count, blue_noise }
+    }
+
+    pub fn offsets(&self, rng: &mut impl Rng) -> Vec<(f32, f32)> {
+        let n = self.count;
+        match self.pattern {
+            Pattern::Center => vec![(0.5, 0.5); n],
+            Pattern::Stratified => multi_jittered(n, rng),
+            Pattern::Halton => {
+                let (sx, sy): (f32, f32) = (rng.gen(), rng.gen());
+                (0..n).map(|i| wrap(radical_inverse(i as u32, 2) + sx, radical_inverse(i as u32, 3) + sy)).collect()
+            }
+            Pattern::Sobol => {
+                let (sx, sy): (u32, u32) = (rng.gen(), rng.gen());
+                (0..n)
+                    .map(|i| {
+                        let (x, y) = sobol_2d(i as u32);
+                        (to_unit(x ^ sx), to_unit(y ^ sy))
+                    })
+                    .collect()
+            }
+            Pattern::BlueNoise => {
+                let (sx, sy): (f32, f32) = (rng.gen(), rng.gen());
+                self.blue_noise.iter().map(|&(x, y)| wrap(x + sx, y + sy)).collect()
+            }
+        }
+    }
+}
+
+fn wrap(x: f32, y: f32) -> (f32, f32) {
+    (x.fract(), y.fract())
+}
+
+// Top 24 bits, so the result stays below 1.0 as an f32
+pub(crate) fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+// Chiu, Shirley and Wang's multi-jittered sampling for any count, on the
+// most square m×n grid with m·n = count
+fn multi_jittered(count: usize, rng: &mut impl Rng) -> Vec<(f32, f32)> {
+    let m = (1..=(count as f32).sqrt() as usize).rev().find(|m| count.is_multiple_of(*m)).unwrap_or(1);
+    let n = count / m;
+    // The canonical arrangement: sample (i, j) in cell (i, j) and in
+    // subcolumn j and subrow i of it. The jitter stays a little short of 1,
+    // so rounding can't carry a sample into the next stratum.
+    let jitter = |rng: &mut dyn rand::RngCore| rng.gen::<f32>().min(1.0 - 1.0 / 4096.0);
+    let mut samples: Vec<(f32, f32)> = (0..m * n)
+        .map(|k| {
+            let (i, j) = (k / n, k % n);
+            let x = (j as f32 + (i as f32 + jitter(rng)) / m as f32) / n as f32;
+            let y = (i as f32 + (j as f32 + jitter(rng)) / n as f32) / m as f32;
+            (x, y)
+        })
+        .collect();
+    // Shuffling x between the cells of a column permutes its subcolumns,
+    // and y between the cells of a row its subrows, so both
+    // stratifications are kept
+    for j in 0..n {
+        for i in 0..m {
+            let k = rng.gen_range(i..m);
+            let x = samples[i * n + j].0;
+            samples[i * n + j].0 = samples[k * n + j].0;
+            samples[k * n + j].0 = x;
+        }
+    }
+    for i in 0..m {
+        for j in 0..n {
+            let k = rng.gen_range(j..n);
+            let y = samples[i * n + j].1;
+            samples[i * n + j].1 = samples[i * n + k].1;
+            samples[i * n + k].1 = y;
+        }
+    }
+    samples
+}
+
+fn radical_inverse(mut i: u32, base: u32) -> f32 {
+    let inverse_base = 1.0 / base as f32;
+    let (mut value, mut scale) = (0.0, inverse_base);
+    while i > 0 {
+        value += (i % base) as f32 * scale;
+        i /= base;
+        scale *= inverse_base;
+    }
+    value
+}
+
+// Dimension 0 is the bit-reversed index; dimension 1 uses the direction
+// numbers of the polynomial x + 1, v_k = v_(k-1) xor (v_(k-1) >> 1)
+fn sobol_2d(i: u32) -> (u32, u32) {
+    let (mut y, mut v) = (0, 1u32 << 31);
+    let mut bits = i;
+    while bits != 0 {
+        if bits & 1 == 1 {
+            y ^= v;
+        }
+        bits >>= 1;
+        v ^= v >> 1;
+    }
+    (i.reverse_bits(), y)
+}
+
+// Mitchell's best-candidate algorithm: each new point is the candidate
+// farthest from the points so far, out of a number of random candidates
+// growing with the set
+fn best_candidate(count: usize, rng: &mut impl Rng) -> Vec<(f32, f32)> {
+    const CANDIDATES_PER_POINT: usize = 10;
+    let toroidal = |a: (f32, f32), b: (f32, f32)| {
+        let dx = (a.0 - b.0).abs().min(1.0 - (a.0 - b.0).abs());
+        let dy = (a.1 - b.1).abs().min(1.0 - (a.1 - b.1).abs());
+        dx * dx + dy * dy
+    };
+    let mut points: Vec<(f32, f32)> = vec![(rng.gen(), rng.gen())];
+    while points.len() < count {
+        let best = (0..CANDIDATES_PER_POINT * points.len())
+            .map(|_| (rng.gen::<f32>(), rng.gen::<f32>()))
+            .map(|c| (c, points.iter().map(|&p| toroidal(c, p)).fold(f32::MAX, f32::min)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        points.push(best.0);
+    }
+    points
+}
+
+// Hands out the random numbers one path needs, one dimension at a time:
+// the first call after `start_sample` is dimension 0, the next dimension 1
+// and so on, so the same decision on every path of a pixel draws from the
+// same dimension. Low-discrepancy samplers spread each dimension evenly
+// over the pixel's samples.
+pub trait Sampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SamplerKind {
+    // Plain random numbers, what `get_color` used before
+    #[default]
+    Independent,
+    // One sample per stratum in every dimension, strata shuffled between
+    // dimensions
+    Stratified,
+    // A prime base per dimension, randomly rotated per pixel
+    Halton,
+    // Sobol with hash-based Owen scrambling and index shuffling
+    Sobol,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSamplerError(String);
+
+impl fmt::Display for ParseSamplerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown sampler `{}`, expected independent, stratified, halton or sobol", self.0)
+    }
+}
+
+impl std::error::Error for ParseSamplerError {}
+
+impl FromStr for SamplerKind {
+    type Err = ParseSamplerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(ParseSamplerError(s.to_string())),
+        }
+    }
+}
+
+impl SamplerKind {
+    pub const ALL: [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        }
+    }
+
+    // `samples_per_pixel` is only a hint for the stratified sampler, which
+    // needs to know how many strata to make. Equal seeds give equal
+    // sequences.
+    pub fn sampler(self, samples_per_pixel: u32, seed: u32) -> Box<dyn Sampler> {
+        let state = SampleState { seed, pixel: 0, index: 0, dimension: 0 };
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler(state)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler { state, count: samples_per_pixel.max(1) }),
+            SamplerKind::Halton => Box::new(HaltonSampler(state)),
+            SamplerKind::Sobol => Box::new(SobolSampler(state)),
+        }
+    }
+}
+
+// Every sampler is a pure function of the pixel, the sample index and the
+// dimension, so they can be created per thread without coordination
+struct SampleState {
+    seed: u32,
+    pixel: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, row: usize, col: usize, index: u32) {
+        self.pixel = hash_combine(hash_combine(self.seed, row as u32), col as u32);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    // A seed for the next dimension of this pixel
+    fn next_dimension(&mut self) -> (u32, u32) {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        (dimension, hash_combine(self.pixel, dimension))
+    }
+}
+
+struct IndependentSampler(SampleState);
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.0.start(row, col, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (_, seed) = self.0.next_dimension();
+        to_unit(hash_combine(seed, self.0.index))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (_, seed) = self.0.next_dimension();
+        let bits = hash_combine(seed, self.0.index);
+        (to_unit(bits), to_unit(hash(bits)))
+    }
+}
+
+struct StratifiedSampler {
+    state: SampleState,
+    count: u32,
+}
+
+impl StratifiedSampler {
+    // The stratum of this sample in the current dimension. Samples past
+    // `count` start a new, differently shuffled round.
+    fn stratum(&self, seed: u32) -> u32 {
+        let round = self.state.index / self.count;
+        permute(self.state.index % self.count, self.count, hash_combine(seed, round))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.state.start(row, col, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (_, seed) = self.state.next_dimension();
+        let jitter = to_unit(hash_combine(seed, self.state.index ^ 0x5bd1e995));
+        (self.stratum(seed) as f32 + jitter) / self.count as f32
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (_, seed) = self.state.next_dimension();
+        let count = self.count as usize;
+        let m = (1..=(count as f32).sqrt() as usize).rev().find(|m| count.is_multiple_of(*m)).unwrap_or(1);
+        let n = count / m;
+        let cell = self.stratum(seed) as usize;
+        let bits = hash_combine(seed, self.state.index ^ 0x5bd1e995);
+        let x = ((cell % n) as f32 + to_unit(bits)) / n as f32;
+        let y = ((cell / n) as f32 + to_unit(hash(bits))) / m as f32;
+        (x, y)
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107,
+    109, 113, 127, 131,
+];
+
+struct HaltonSampler(SampleState);
+
+impl HaltonSampler {
+    fn value(&self, dimension: u32, seed: u32) -> f32 {
+        // Past the table the bases repeat, decorrelated only by the rotation
+        let base = PRIMES[dimension as usize % PRIMES.len()];
+        (radical_inverse(self.0.index, base) + to_unit(seed)).fract()
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.0.start(row, col, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (dimension, seed) = self.0.next_dimension();
+        self.value(dimension * 2, seed)
+    }
+
+    // Each call takes two bases, so 1D and 2D dimensions never share one
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (dimension, seed) = self.0.next_dimension();
+        (self.value(dimension * 2, seed), self.value(dimension * 2 + 1, hash(seed)))
+    }
+}
+
+// Burley's "Practical Hash-based Owen Scrambling": the first two Sobol
+// dimensions for every call, each with its own Owen scramble and its own
+// shuffle of the sample order. The shuffle keeps power of two prefixes
+// together, so those stay well stratified.
+struct SobolSampler(SampleState);
+
+impl SobolSampler {
+    fn scrambled(&mut self) -> (f32, f32) {
+        let (_, seed) = self.0.next_dimension();
+        let index = nested_uniform_scramble(self.0.index, seed);
+        let (x, y) = sobol_2d(index);
+        (
+            to_unit(nested_uniform_scramble(x, hash_combine(seed, 1))),
+            to_unit(nested_uniform_scramble(y, hash_combine(seed, 2))),
+        )
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.0.start(row, col, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.scrambled().0
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        self.scrambled()
+    }
+}
+
+// Chris Wellons' lowbias32
+pub(crate) fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+pub(crate) fn hash_combine(seed: u32, value: u32) -> u32 {
+    hash(seed ^ value.wrapping_add(0x9e3779b9).wrapping_add(seed << 6).wrapping_add(seed >> 2))
+}
+
+// Kensler's hash-based permutation of 0..count, "Correlated Multi-Jittered
+// Sampling"
+fn permute(mut i: u32, count: u32, seed: u32) -> u32 {
+    let mut w = count - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < count {
+            return (i.wrapping_add(seed)) % count;
+        }
+    }
+}
+
+// An Owen scramble of the bits from the most significant down: each bit is
+// flipped or not depending on a hash of the bits above it
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multi_jittered_keeps_both_stratifications() {
+        let mut rng = rand::thread_rng();
+        for count in [1usize, 2, 6, 16, 24, 64] {
+            let m = (1..=(count as f32).sqrt() as usize).rev().find(|m| count.is_multiple_of(*m)).unwrap();
+            let n = count / m;
+            for _ in 0..100 {
+                let samples = multi_jittered(count, &mut rng);
+                let mut cells = vec![0; count];
+                let mut columns = vec![0; count];
+                let mut rows = vec![0; count];
+                for &(x, y) in &samples {
+                    assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                    // In f64, so the check itself doesn't round across a
+                    // stratum boundary
+                    let (x, y) = (x as f64, y as f64);
+                    cells[(y * m as f64) as usize * n + (x * n as f64) as usize] += 1;
+                    columns[(x * count as f64) as usize] += 1;
+                    rows[(y * count as f64) as usize] += 1;
+                }
+                assert!(cells.iter().all(|&c| c == 1), "{count} samples: {samples:?}");
+                assert!(columns.iter().all(|&c| c == 1), "{count} samples: {samples:?}");
+                assert!(rows.iter().all(|&c| c == 1), "{count} samples: {samples:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn radical_inverses() {
+        let base2 = [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875];
+        let base3 = [0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0, 4.0 / 9.0, 7.0 / 9.0, 2.0 / 9.0, 5.0 / 9.0];
+        for i in 0..8 {
+            assert!((radical_inverse(i, 2) - base2[i as usize]).abs() < 1e-6);
+            assert!((radical_inverse(i, 3) - base3[i as usize]).abs() < 1e-6);
+        }
+
+        // The sampler rotates every index of a pixel by the same amount, so
+        // relative to the first sample its first 2D dimension is bases 2
+        // and 3 again
+        let mut sampler = SamplerKind::Halton.sampler(8, 3);
+        let points: Vec<(f32, f32)> = (0..8)
+            .map(|i| {
+                sampler.start_sample(4, 5, i);
+                sampler.get_2d()
+            })
+            .collect();
+        for (i, &(x, y)) in points.iter().enumerate() {
+            assert!(((x - points[0].0).rem_euclid(1.0) - base2[i]).abs() < 1e-5, "{} {}", i, x);
+            assert!(((y - points[0].1).rem_euclid(1.0) - base3[i]).abs() < 1e-5, "{} {}", i, y);
+        }
+    }
+
+    #[test]
+    fn sobol_direction_numbers() {
+        let first = [(0.0, 0.0), (0.5, 0.5), (0.25, 0.75), (0.75, 0.25), (0.125, 0.625), (0.625, 0.125), (0.375, 0.375)];
+        for (i, &(x, y)) in first.iter().enumerate() {
+            let (sx, sy) = sobol_2d(i as u32);
+            assert_eq!((to_unit(sx), to_unit(sy)), (x, y), "{}", i);
+        }
+    }
+
+    #[test]
+    fn nested_uniform_scramble_permutes_aligned_blocks() {
+        for seed in [0, 1, 0x9e3779b9, 12345] {
+            for k in [1, 3, 6] {
+                // The first 2^k values go to one aligned block of 2^k, each
+                // to a different place in it
+                let outputs: Vec<u32> = (0..1u32 << k).map(|x| nested_uniform_scramble(x, seed)).collect();
+                assert!(outputs.iter().all(|&o| o >> k == outputs[0] >> k), "seed {} k {}", seed, k);
+                let mut low: Vec<u32> = outputs.iter().map(|&o| o & ((1 << k) - 1)).collect();
+                low.sort();
+                assert_eq!(low, (0..1u32 << k).collect::<Vec<_>>());
+            }
+            // Values sharing their top bits still share them after
+            let (a, b) = (nested_uniform_scramble(0xabc0_0001, seed), nested_uniform_scramble(0xabc0_ff00, seed));
+            assert_eq!(a >> 16, b >> 16);
+        }
+        assert_ne!(nested_uniform_scramble(5, 1), nested_uniform_scramble(5, 2));
+    }
+
+    #[test]
+    fn scrambled_sobol_stratifies_power_of_two_prefixes() {
+        for seed in [1, 77, 4242] {
+            for k in 0..=8 {
+                let count = 1usize << k;
+                let mut sampler = SamplerKind::Sobol.sampler(count as u32, seed);
+                // The first and a later 2D dimension of one pixel
+                let mut points = [Vec::new(), Vec::new()];
+                for i in 0..count {
+                    sampler.start_sample(2, 9, i as u32);
+                    points[0].push(sampler.get_2d());
+                    sampler.get_1d();
+                    points[1].push(sampler.get_2d());
+                }
+                for points in &points {
+                    for axis in [0, 1] {
+                        let mut strata = vec![0; count];
+                        for &(x, y) in points {
+                            strata[([x, y][axis] as f64 * count as f64) as usize] += 1;
+                        }
+                        assert!(strata.iter().all(|&n| n == 1), "seed {} {} samples: {:?}", seed, count, points);
+                    }
+                    // One point in every 2^a by 2^(k - a) box as well
+                    for a in 0..=k {
+                        let (columns, rows) = (1usize << a, count >> a);
+                        let mut boxes = vec![0; count];
+                        for &(x, y) in points {
+                            boxes[(y as f64 * rows as f64) as usize * columns + (x as f64 * columns as f64) as usize] += 1;
+                        }
+                        assert!(boxes.iter().all(|&n| n == 1), "seed {} {} samples in {}x{}", seed, count, columns, rows);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::{fmt, str::FromStr};
+
+use cgmath::{InnerSpace, Matrix4, Vector2, Vector3, Vector4};
+use graphics_core::{
+    mesh::MeshVertex,
+    raster::{InterpolatedFragment, Interpolate},
+    shader::{FragmentShader, Texture, TextureFilter, VertexShader},
+    Image, Rgb8,
+};
+
+use crate::{DirectionalLight, Material};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Shading {
+    // Direct diffuse light, the closest to what the ray tracer converges to
+    #[default]
+    Lambert,
+    Phong,
+    // World space normals mapped to colors, for checking the geometry
+    Normals,
+    // A checkerboard texture, for checking the texture coordinates
+    Textured,
+    // Diffuse light in flat bands with dark outlines
+    Toon,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseShadingError(String);
+
+impl fmt::Display for ParseShadingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown shading `{}`, expected lambert, phong, normals, textured or toon", self.0)
+    }
+}
+
+impl std::error::Error for ParseShadingError {}
+
+impl FromStr for Shading {
+    type Err = ParseShadingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lambert" => Ok(Shading::Lambert),
+            "phong" => Ok(Shading::Phong),
+            "normals" => Ok(Shading::Normals),
+            "textured" => Ok(Shading::Textured),
+            "toon" => Ok(Shading::Toon),
+            _ => Err(ParseShadingError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Varying {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub uv: Vector2<f32>,
+}
+
+impl Interpolate for Varying {
+    fn interpolate(values: [Varying; 3], weights: [f32; 3]) -> Varying {
+        Varying {
+            position: Vector3::interpolate(values.map(|v| v.position), weights),
+            normal: Vector3::interpolate(values.map(|v| v.normal), weights),
+            uv: Vector2::interpolate(values.map(|v| v.uv), weights),
+        }
+    }
+}
+
+// Transforms mesh vertices to clip space and passes their world space
+// position and normal on
+pub struct ModelShader {
+    pub model: Matrix4<f32>,
+    pub normal_matrix: Matrix4<f32>,
+    pub view_projection: Matrix4<f32>,
+}
+
+impl VertexShader for ModelShader {
+    type Input = MeshVertex;
+    type Varying = Varying;
+
+    fn vertex(&self, input: &MeshVertex) -> (Vector4<f32>, Varying) {
+        let world = self.model * input.position.extend(1.0);
+        let normal = (self.normal_matrix * input.normal.extend(0.0)).truncate();
+        let varying = Varying { position: world.truncate(), normal: normal.normalize(), uv: input.uv };
+        (self.view_projection * world, varying)
+    }
+}
+
+// Unclamped like the ray tracer's colors, `Rgb8::from_f32` clamps on output
+fn diffuse(material: &Material, lights: &[DirectionalLight], normal: Vector3<f32>) -> Vector3<f32> {
+    let mut color = Vector3::new(0.0, 0.0, 0.0);
+    for light in lights {
+        let cos_theta = normal.dot(-light.direction).max(0.0);
+        color += light.color * (light.intensity * cos_theta * material.reflectance * (1.0 - material.specular));
+    }
+    color
+}
+
+fn to_rgb8(color: Vector3<f32>) -> Rgb8 {
+    Rgb8::from_f32(color.x, color.y, color.z)
+}
+
+pub struct LambertShader<'a> {
+    pub material: Material,
+    pub lights: &'a [DirectionalLight],
+}
+
+impl FragmentShader<Varying> for LambertShader<'_> {
+    type Output = Rgb8;
+
+    fn fragment(&self, fragment: &InterpolatedFragment<Varying>) -> Option<Rgb8> {
+        let normal = fragment.varying.normal.normalize();
+        Some(to_rgb8(self.material.emittance + diffuse(&self.material, self.lights, normal)))
+    }
+}
+
+pub struct PhongShader<'a> {
+    pub material: Material,
+    pub lights: &'a [DirectionalLight],
+    pub eye: Vector3<f32>,
+    pub shininess: f32,
+}
+
+impl FragmentShader<Varying> for PhongShader<'_> {
+    type Output = Rgb8;
+
+    fn fragment(&self, fragment: &InterpolatedFragment<Varying>) -> Option<Rgb8> {
+        let normal = fragment.varying.normal.normalize();
+        let to_eye = (self.eye - fragment.varying.position).normalize();
+        let mut color = self.material.emittance + diffuse(&self.material, self.lights, normal);
+        // Even the matte materials get a small highlight so the lights'
+        // directions show
+        let specular = self.material.specular.max(0.2);
+        for light in self.lights {
+            let reflected = light.direction - 2.0 * light.direction.dot(normal) * normal;
+            let highlight = reflected.dot(to_eye).max(0.0).powf(self.shininess);
+            color += light.color * (light.intensity * specular * highlight);
+        }
+        Some(to_rgb8(color))
+    }
+}
+
+pub struct NormalShader;
+
+impl FragmentShader<Varying> for NormalShader {
+    type Output = Rgb8;
+
+    fn fragment(&self, fragment: &InterpolatedFragment<Varying>) -> Option<Rgb8> {
+        let normal = fragment.varying.normal.normalize();
+        Some(to_rgb8(normal * 0.5 + Vector3::new(0.5, 0.5, 0.5)))
+    }
+}
+
+pub struct TexturedShader<'a> {
+    pub material: Material,
+    pub lights: &'a [DirectionalLight],
+    pub texture: &'a Texture<Rgb8>,
+}
+
+// 16 squares around a sphere and 8 from pole to pole, square on the equator
+pub fn checkerboard() -> Texture<Rgb8> {
+    const SQUARE: usize = 16;
+    let mut image = Image::new(16 * SQUARE, 8 * SQUARE);
+    for row in 0..image.height() {
+        for col in 0..image.width() {
+            let dark = (row / SQUARE + col / SQUARE).is_multiple_of(2);
+            image.set(row, col, if dark { Rgb8::new(40, 40, 40) } else { Rgb8::WHITE });
+        }
+    }
+    Texture::new(image).with_filter(TextureFilter::Bilinear)
+}
+
+impl FragmentShader<Varying> for TexturedShader<'_> {
+    type Output = Rgb8;
+
+    fn fragment(&self, fragment: &InterpolatedFragment<Varying>) -> Option<Rgb8> {
+        let normal = fragment.varying.normal.normalize();
+        let [r, g, b, _] = self.texture.sample(fragment.varying.uv);
+        let color = self.material.emittance + diffuse(&self.material, self.lights, normal);
+        Some(to_rgb8(Vector3::new(color.x * r, color.y * g, color.z * b)))
+    }
+}
+
+pub struct ToonShader<'a> {
+    pub material: Material,
+    pub lights: &'a [DirectionalLight],
+    pub eye: Vector3<f32>,
+    pub bands: u32,
+}
+
+impl FragmentShader<Varying> for ToonShader<'_> {
+    type Output = Rgb8;
+
+    fn fragment(&self, fragment: &InterpolatedFragment<Varying>) -> Option<Rgb8> {
+        let normal = fragment.varying.normal.normalize();
+        let to_eye = (self.eye - fragment.varying.position).normalize();
+        // Grazing angles make the outline
+        if normal.dot(to_eye) < 0.3 {
+            return Some(Rgb8::BLACK);
+        }
+        let bands = self.bands.max(1) as f32;
+        let mut color = self.material.emittance;
+        for light in self.lights {
+            let cos_theta = normal.dot(-light.direction).max(0.0);
+            let level = (cos_theta * bands).ceil() / bands;
+            color += light.color * (light.intensity * level * self.material.reflectance * (1.0 - self.material.specular));
+        }
+        Some(to_rgb8(color))
+    }
+}
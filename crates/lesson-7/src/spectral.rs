@@ -0,0 +1,504 @@
+use std::{
+    cell::Cell,
+    fmt,
+    ops::{Add, AddAssign, Mul, MulAssign},
+    str::FromStr,
+};
+
+use cgmath::{ElementWise, InnerSpace, Vector3};
+
+use crate::Scene;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorMode {
+    #[default]
+    Rgb,
+    // Paths carry a few wavelengths instead of RGB, and the film gets their
+    // color through the CIE matching functions
+    Spectral,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseColorModeError(String);
+
+impl fmt::Display for ParseColorModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown color mode `{}`, expected rgb or spectral", self.0)
+    }
+}
+
+impl std::error::Error for ParseColorModeError {}
+
+impl FromStr for ColorMode {
+    type Err = ParseColorModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(ColorMode::Rgb),
+            "spectral" => Ok(ColorMode::Spectral),
+            _ => Err(ParseColorModeError(s.to_string())),
+        }
+    }
+}
+
+// Visible wavelengths in nanometers, all that's sampled and fitted
+const MIN_WAVELENGTH: f32 = 380.0;
+const MAX_WAVELENGTH: f32 = 780.0;
+const WAVELENGTHS: usize = 4;
+
+// CIE standard illuminant D65, every 10 nm from 380 to 780
+const D65: [f32; 41] = [
+    49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86, 115.92, 108.81, 109.35, 107.80, 104.79,
+    107.69, 104.41, 104.05, 100.00, 96.33, 95.79, 88.69, 90.01, 89.60, 87.70, 83.29, 83.70, 80.03, 80.21, 82.28, 78.28,
+    69.72, 71.61, 74.35, 61.60, 69.89, 75.09, 63.59, 46.42, 66.81, 63.38,
+];
+
+fn d65(wavelength: f32) -> f32 {
+    let x = ((wavelength - MIN_WAVELENGTH) / 10.0).clamp(0.0, (D65.len() - 1) as f32);
+    let i = (x as usize).min(D65.len() - 2);
+    let t = x - i as f32;
+    (D65[i] + (D65[i + 1] - D65[i]) * t) / 100.0
+}
+
+// The CIE 1931 matching functions, as the sums of Gaussians with a different
+// width either side fitted by Wyman, Sloan and Shirley
+fn xyz_matching(wavelength: f32) -> [f32; 3] {
+    let g = |mean: f32, below: f32, above: f32| {
+        let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    ]
+}
+
+fn xyz_to_linear_srgb([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    ]
+}
+
+fn sigmoid(x: f32) -> f32 {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+// Jakob and Hanika's smooth spectra: a sigmoid of a quadratic in the
+// wavelength, which goes from 0 to 1 and can match any color in the sRGB
+// gamut, more or less
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SigmoidSpectrum {
+    coefficients: [f32; 3],
+}
+
+impl SigmoidSpectrum {
+    fn constant(value: f32) -> SigmoidSpectrum {
+        let value = value.clamp(0.0, 1.0);
+        let c = if value <= 0.0 {
+            f32::NEG_INFINITY
+        } else if value >= 1.0 {
+            f32::INFINITY
+        } else {
+            (value - 0.5) / (value * (1.0 - value)).sqrt()
+        };
+        SigmoidSpectrum { coefficients: [0.0, 0.0, c] }
+    }
+
+    fn at(&self, wavelength: f32) -> f32 {
+        let x = (wavelength - MIN_WAVELENGTH) / (MAX_WAVELENGTH - MIN_WAVELENGTH);
+        let [a, b, c] = self.coefficients;
+        sigmoid(if c.is_infinite() { c } else { (a * x + b) * x + c })
+    }
+}
+
+const FIT_STEP: f32 = 5.0;
+
+// Everything a path can meet in the scene, as spectra, fitted once up front
+pub struct Spectra {
+    // D65 times the matching functions, at FIT_STEP
+    weights: Vec<[f64; 3]>,
+    // The linear sRGB of D65, which comes out as white
+    white: [f32; 3],
+    illuminants: Vec<(Vector3<f32>, f32, SigmoidSpectrum)>,
+    albedos: Vec<(Vector3<f32>, SigmoidSpectrum)>,
+}
+
+impl Spectra {
+    pub fn new(scene: &Scene) -> Spectra {
+        let steps = ((MAX_WAVELENGTH - MIN_WAVELENGTH) / FIT_STEP) as usize;
+        let weights: Vec<[f64; 3]> = (0..=steps)
+            .map(|i| {
+                let wavelength = MIN_WAVELENGTH + i as f32 * FIT_STEP;
+                xyz_matching(wavelength).map(|v| (v * d65(wavelength) * FIT_STEP) as f64)
+            })
+            .collect();
+        let xyz = [0, 1, 2].map(|c| weights.iter().map(|w| w[c]).sum::<f64>() as f32);
+        let mut spectra = Spectra { weights, white: xyz_to_linear_srgb(xyz), illuminants: Vec::new(), albedos: Vec::new() };
+
+        let lights = scene.spheres.iter().map(|sphere| sphere.material.emittance).chain(scene.lights.iter().map(|light| light.color));
+        for rgb in lights {
+            if !spectra.illuminants.iter().any(|(other, _, _)| *other == rgb) {
+                // Fitted at half the brightest channel, well inside what
+                // a sigmoid reaches, and scaled back up
+                let scale = 2.0 * rgb.x.max(rgb.y).max(rgb.z).max(0.0);
+                let spectrum = if scale > 0.0 { spectra.fit(rgb / scale) } else { SigmoidSpectrum::constant(0.0) };
+                spectra.illuminants.push((rgb, scale, spectrum));
+            }
+        }
+        for rgb in scene.media(0.0).map(|(_, medium)| medium.albedo()) {
+            if !spectra.albedos.iter().any(|(other, _)| *other == rgb) {
+                let spectrum = spectra.fit(rgb);
+                spectra.albedos.push((rgb, spectrum));
+            }
+        }
+        spectra
+    }
+
+    // The color of `spectrum` lit by D65, with D65 itself white
+    fn rgb(&self, coefficients: [f64; 3]) -> [f64; 3] {
+        let spectrum = SigmoidSpectrum { coefficients: coefficients.map(|c| c as f32) };
+        let mut xyz = [0.0f64; 3];
+        for (i, w) in self.weights.iter().enumerate() {
+            let s = spectrum.at(MIN_WAVELENGTH + i as f32 * FIT_STEP) as f64;
+            (0..3).for_each(|c| xyz[c] += s * w[c]);
+        }
+        let rgb = xyz_to_linear_srgb(xyz.map(|v| v as f32));
+        [0, 1, 2].map(|c| (rgb[c] / self.white[c]) as f64)
+    }
+
+    // Levenberg-Marquardt on the three coefficients, starting from the gray
+    // of the same average. Colors out of reach end up as close as they get.
+    fn fit(&self, rgb: Vector3<f32>) -> SigmoidSpectrum {
+        let target = [rgb.x, rgb.y, rgb.z].map(|v| v.clamp(0.0, 1.0) as f64);
+        if target[0] == target[1] && target[1] == target[2] {
+            return SigmoidSpectrum::constant(target[0] as f32);
+        }
+        let residual = |c: [f64; 3]| {
+            let rgb = self.rgb(c);
+            [0, 1, 2].map(|i| rgb[i] - target[i])
+        };
+        let cost = |r: [f64; 3]| r.iter().map(|v| v * v).sum::<f64>();
+
+        let mean = (target.iter().sum::<f64>() / 3.0).clamp(0.01, 0.99);
+        let mut c = [0.0, 0.0, (mean - 0.5) / (mean * (1.0 - mean)).sqrt()];
+        let mut r = residual(c);
+        let mut damping = 1e-3;
+        for _ in 0..200 {
+            if cost(r) < 1e-10 {
+                break;
+            }
+            // Jacobian by forward differences
+            let mut jacobian = [[0.0f64; 3]; 3];
+            for j in 0..3 {
+                let mut moved = c;
+                moved[j] += 1e-4;
+                let moved = residual(moved);
+                (0..3).for_each(|i| jacobian[i][j] = (moved[i] - r[i]) / 1e-4);
+            }
+            let mut normal = [[0.0f64; 3]; 3];
+            let mut gradient = [0.0f64; 3];
+            for a in 0..3 {
+                for b in 0..3 {
+                    normal[a][b] = (0..3).map(|i| jacobian[i][a] * jacobian[i][b]).sum();
+                }
+                normal[a][a] *= 1.0 + damping;
+                gradient[a] = -(0..3).map(|i| jacobian[i][a] * r[i]).sum::<f64>();
+            }
+            let Some(step) = solve(normal, gradient) else {
+                damping *= 10.0;
+                continue;
+            };
+            let next = [0, 1, 2].map(|i| c[i] + step[i]);
+            let next_r = residual(next);
+            if cost(next_r) < cost(r) {
+                (c, r) = (next, next_r);
+                damping = (damping * 0.3).max(1e-9);
+            } else {
+                damping *= 10.0;
+            }
+        }
+        SigmoidSpectrum { coefficients: c.map(|v| v as f32) }
+    }
+
+    fn illuminant(&self, rgb: Vector3<f32>) -> (f32, SigmoidSpectrum) {
+        match self.illuminants.iter().find(|(other, _, _)| *other == rgb) {
+            Some(&(_, scale, spectrum)) => (scale, spectrum),
+            None => {
+                let scale = 2.0 * rgb.x.max(rgb.y).max(rgb.z).max(0.0);
+                (scale, if scale > 0.0 { self.fit(rgb / scale) } else { SigmoidSpectrum::constant(0.0) })
+            }
+        }
+    }
+
+    fn albedo(&self, rgb: Vector3<f32>) -> SigmoidSpectrum {
+        match self.albedos.iter().find(|(other, _)| *other == rgb) {
+            Some(&(_, spectrum)) => spectrum,
+            None => self.fit(rgb),
+        }
+    }
+}
+
+// Cramer's rule, for the 3 by 3 systems of the fit
+fn solve(m: [[f64; 3]; 3], b: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(m);
+    if d.abs() < 1e-30 {
+        return None;
+    }
+    Some([0, 1, 2].map(|col| {
+        let mut replaced = m;
+        (0..3).for_each(|row| replaced[row][col] = b[row]);
+        det(replaced) / d
+    }))
+}
+
+// What paths carry: RGB, or a spectrum at the path's wavelengths
+pub trait PathColor: Copy + Add<Output = Self> + AddAssign + Mul<f32, Output = Self> + MulAssign<f32> {
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn mul_color(self, other: Self) -> Self;
+}
+
+impl PathColor for Vector3<f32> {
+    const ZERO: Self = Vector3::new(0.0, 0.0, 0.0);
+    const ONE: Self = Vector3::new(1.0, 1.0, 1.0);
+
+    fn mul_color(self, other: Self) -> Self {
+        self.mul_element_wise(other)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spectrum([f32; WAVELENGTHS]);
+
+impl Add for Spectrum {
+    type Output = Spectrum;
+
+    fn add(self, other: Spectrum) -> Spectrum {
+        Spectrum(std::array::from_fn(|i| self.0[i] + other.0[i]))
+    }
+}
+
+impl AddAssign for Spectrum {
+    fn add_assign(&mut self, other: Spectrum) {
+        *self = *self + other;
+    }
+}
+
+impl Mul<f32> for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(self, s: f32) -> Spectrum {
+        Spectrum(self.0.map(|v| v * s))
+    }
+}
+
+impl MulAssign<f32> for Spectrum {
+    fn mul_assign(&mut self, s: f32) {
+        *self = *self * s;
+    }
+}
+
+impl PathColor for Spectrum {
+    const ZERO: Self = Spectrum([0.0; WAVELENGTHS]);
+    const ONE: Self = Spectrum([1.0; WAVELENGTHS]);
+
+    fn mul_color(self, other: Self) -> Self {
+        Spectrum(std::array::from_fn(|i| self.0[i] * other.0[i]))
+    }
+}
+
+// Glass whose index of refraction follows Cauchy's equation, n = a + b / λ²
+// with λ in micrometers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glass {
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Glass {
+    // Dense flint, which splits colors more than most glass
+    pub const FLINT: Glass = Glass { a: 1.7280, b: 0.01342 };
+
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let micrometers = wavelength / 1000.0;
+        self.a + self.b / (micrometers * micrometers)
+    }
+
+    // Reflects with the Fresnel reflectance for `ior` and refracts
+    // otherwise, `u` picks which. Returns the new direction.
+    pub fn scatter(&self, direction: Vector3<f32>, normal: Vector3<f32>, ior: f32, u: f32) -> Vector3<f32> {
+        let entering = direction.dot(normal) < 0.0;
+        let (normal, eta) = if entering { (normal, 1.0 / ior) } else { (-normal, ior) };
+        let cos_i = -direction.dot(normal);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        let reflected = direction + 2.0 * cos_i * normal;
+        if sin2_t >= 1.0 {
+            return reflected;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+        let perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+        let fresnel = 0.5 * (parallel * parallel + perpendicular * perpendicular);
+        if u < fresnel {
+            reflected
+        } else {
+            (direction * eta + normal * (eta * cos_i - cos_t)).normalize()
+        }
+    }
+}
+
+// How colors given in RGB become what a path carries
+pub trait ColorModel {
+    type Color: PathColor;
+
+    // Light given off by something of this color
+    fn illuminant(&self, rgb: Vector3<f32>) -> Self::Color;
+
+    // How much of the light hitting something of this color it sends on
+    fn albedo(&self, rgb: Vector3<f32>) -> Self::Color;
+
+    // The index of refraction to trace `glass` with, and the weight of what
+    // comes through it
+    fn refract(&self, glass: Glass) -> (f32, Self::Color);
+}
+
+pub struct Rgb;
+
+impl ColorModel for Rgb {
+    type Color = Vector3<f32>;
+
+    fn illuminant(&self, rgb: Vector3<f32>) -> Vector3<f32> {
+        rgb
+    }
+
+    fn albedo(&self, rgb: Vector3<f32>) -> Vector3<f32> {
+        rgb
+    }
+
+    // One index for all colors, the one for green light
+    fn refract(&self, glass: Glass) -> (f32, Vector3<f32>) {
+        (glass.ior(550.0), Vector3::ONE)
+    }
+}
+
+// A path's wavelengths: the hero wavelength, sampled uniformly, and the rest
+// spaced evenly after it, wrapping around the visible range
+pub struct Spectral<'a> {
+    spectra: &'a Spectra,
+    wavelengths: [f32; WAVELENGTHS],
+    // Whether the path has gone through glass, and carries only the hero
+    hero_only: Cell<bool>,
+}
+
+impl<'a> Spectral<'a> {
+    pub fn new(spectra: &'a Spectra, u: f32) -> Spectral<'a> {
+        let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+        let hero = u * range;
+        let wavelengths = std::array::from_fn(|i| MIN_WAVELENGTH + (hero + i as f32 * range / WAVELENGTHS as f32) % range);
+        Spectral { spectra, wavelengths, hero_only: Cell::new(false) }
+    }
+
+    // Each wavelength's estimate of XYZ averaged, then linear sRGB
+    pub fn to_rgb(&self, spectrum: Spectrum) -> Vector3<f32> {
+        let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+        let mut xyz = [0.0; 3];
+        for (&wavelength, &value) in self.wavelengths.iter().zip(&spectrum.0) {
+            let matching = xyz_matching(wavelength);
+            (0..3).for_each(|c| xyz[c] += value * matching[c] * range / WAVELENGTHS as f32);
+        }
+        let rgb = xyz_to_linear_srgb(xyz);
+        let white = self.spectra.white;
+        Vector3::new(rgb[0] / white[0], rgb[1] / white[1], rgb[2] / white[2])
+    }
+}
+
+impl ColorModel for Spectral<'_> {
+    type Color = Spectrum;
+
+    fn illuminant(&self, rgb: Vector3<f32>) -> Spectrum {
+        let (scale, spectrum) = self.spectra.illuminant(rgb);
+        Spectrum(self.wavelengths.map(|wavelength| scale * spectrum.at(wavelength) * d65(wavelength)))
+    }
+
+    fn albedo(&self, rgb: Vector3<f32>) -> Spectrum {
+        let spectrum = self.spectra.albedo(rgb);
+        Spectrum(self.wavelengths.map(|wavelength| spectrum.at(wavelength)))
+    }
+
+    // Traced with the hero wavelength's index. The other wavelengths would
+    // have gone elsewhere, so from the first glass on only the hero goes on,
+    // counting for all of them.
+    fn refract(&self, glass: Glass) -> (f32, Spectrum) {
+        let mut weight = Spectrum::ZERO;
+        weight.0[0] = if self.hero_only.replace(true) { 1.0 } else { WAVELENGTHS as f32 };
+        (glass.ior(self.wavelengths[0]), weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_spectra() -> Spectra {
+        Spectra::new(&Scene { spheres: Vec::new(), lights: Vec::new(), volumes: Vec::new() })
+    }
+
+    #[test]
+    fn fit_round_trips_srgb_colors() {
+        let spectra = empty_spectra();
+        let colors = [
+            Vector3::new(0.8, 0.3, 0.2),
+            Vector3::new(0.2, 0.6, 0.3),
+            Vector3::new(0.25, 0.35, 0.7),
+            Vector3::new(0.9, 0.85, 0.4),
+            Vector3::new(0.5, 0.5, 0.5),
+        ];
+        for rgb in colors {
+            let spectrum = spectra.fit(rgb);
+            let fitted = spectra.rgb(spectrum.coefficients.map(|c| c as f64));
+            for (c, expected) in [rgb.x, rgb.y, rgb.z].into_iter().enumerate() {
+                assert!((fitted[c] as f32 - expected).abs() < 1e-3, "{:?} fitted as {:?}", rgb, fitted);
+            }
+        }
+    }
+
+    #[test]
+    fn unit_spectrum_under_d65_is_white() {
+        let spectra = empty_spectra();
+        let flat = SigmoidSpectrum::constant(1.0);
+        assert!((380..=780).step_by(10).all(|w| flat.at(w as f32) == 1.0));
+        for value in spectra.rgb(flat.coefficients.map(|c| c as f64)) {
+            assert!((value - 1.0).abs() < 1e-5, "{}", value);
+        }
+
+        // The same through the path wavelengths: mid gray light, as D65 at
+        // half strength, off a white surface averages out to mid gray
+        let count = 512;
+        let mut sum = Vector3::ZERO;
+        for i in 0..count {
+            let model = Spectral::new(&spectra, (i as f32 + 0.5) / count as f32);
+            let spectrum = model.illuminant(Vector3::new(0.5, 0.5, 0.5)).mul_color(model.albedo(Vector3::ONE));
+            sum += model.to_rgb(spectrum);
+        }
+        let mean = sum / count as f32;
+        assert!([mean.x, mean.y, mean.z].iter().all(|v| (v - 0.5).abs() < 0.01), "{:?}", mean);
+    }
+
+    #[test]
+    fn flint_bends_blue_more_than_red() {
+        let iors: Vec<f32> = (400..=700).step_by(50).map(|w| Glass::FLINT.ior(w as f32)).collect();
+        assert!(iors.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", iors);
+        assert!((Glass::FLINT.ior(587.6) - 1.767).abs() < 0.005);
+    }
+}
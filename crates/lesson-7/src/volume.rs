@@ -0,0 +1,433 @@
+use std::{f32::consts::PI, fmt, str::FromStr, sync::Arc};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{
+    sampling::{hash_combine, to_unit, Sampler},
+    Ray,
+};
+
+// Samples a direction scattered from `direction` by the Henyey–Greenstein
+// phase function. `g` runs from -1 for all light going back the way it came
+// to 1 for all going on, 0 scatters evenly. Sampling it exactly means the
+// phase function and its pdf cancel out.
+fn sample_henyey_greenstein(direction: Vector3<f32>, g: f32, (u1, u2): (f32, f32)) -> Vector3<f32> {
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * u1
+    } else {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+        ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    // Any two axes perpendicular to the direction
+    let helper = if direction.x.abs() > 0.9 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+    let u = direction.cross(helper).normalize();
+    let v = direction.cross(u);
+    (u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + direction * cos_theta).normalize()
+}
+
+// Densities on a cube of size³ points spanning 0..1 on each axis, looked
+// up with trilinear interpolation
+pub struct DensityGrid {
+    size: usize,
+    values: Vec<f32>,
+    max: f32,
+}
+
+impl DensityGrid {
+    // `density` is evaluated at each grid point, and should not be negative
+    pub fn new(size: usize, density: impl Fn(Vector3<f32>) -> f32) -> DensityGrid {
+        let scale = 1.0 / (size - 1) as f32;
+        let mut values = Vec::with_capacity(size * size * size);
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    values.push(density(Vector3::new(x as f32, y as f32, z as f32) * scale).max(0.0));
+                }
+            }
+        }
+        let max = values.iter().fold(0.0f32, |max, &v| max.max(v));
+        DensityGrid { size, values, max }
+    }
+
+    // A billowing puff, fractal value noise fading out towards the edge of
+    // the sphere that fits in the cube
+    pub fn smoke(size: usize, seed: u32) -> DensityGrid {
+        DensityGrid::new(size, |p| {
+            let radius = (p * 2.0 - Vector3::new(1.0, 1.0, 1.0)).magnitude();
+            let falloff = (1.0 - radius).max(0.0);
+            let mut noise = 0.0;
+            let (mut frequency, mut amplitude) = (3.0, 0.5);
+            for octave in 0..4 {
+                noise += amplitude * value_noise(p * frequency, hash_combine(seed, octave));
+                frequency *= 2.0;
+                amplitude *= 0.5;
+            }
+            ((noise - 0.4) * 4.0 + falloff - 0.3) * falloff * 3.0
+        })
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    // `p` is in 0..1 on each axis, outside the grid is empty
+    pub fn lookup(&self, p: Vector3<f32>) -> f32 {
+        if !(0.0..=1.0).contains(&p.x) || !(0.0..=1.0).contains(&p.y) || !(0.0..=1.0).contains(&p.z) {
+            return 0.0;
+        }
+        let last = self.size - 1;
+        let scaled = p * last as f32;
+        let (x, y, z) = (scaled.x.floor() as usize, scaled.y.floor() as usize, scaled.z.floor() as usize);
+        let (x, y, z) = (x.min(last - 1), y.min(last - 1), z.min(last - 1));
+        let (fx, fy, fz) = (scaled.x - x as f32, scaled.y - y as f32, scaled.z - z as f32);
+        let at = |dx: usize, dy: usize, dz: usize| self.values[((z + dz) * self.size + y + dy) * self.size + x + dx];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let row = |dy: usize, dz: usize| lerp(at(0, dy, dz), at(1, dy, dz), fx);
+        lerp(lerp(row(0, 0), row(1, 0), fy), lerp(row(0, 1), row(1, 1), fy), fz)
+    }
+}
+
+// Smoothly interpolated random values at the integer lattice, in 0..1
+fn value_noise(p: Vector3<f32>, seed: u32) -> f32 {
+    let (x, y, z) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (fx, fy, fz) = (smooth(p.x - x), smooth(p.y - y), smooth(p.z - z));
+    let lattice = |dx: i32, dy: i32, dz: i32| {
+        let seed = hash_combine(seed, (x as i32 + dx) as u32);
+        let seed = hash_combine(seed, (y as i32 + dy) as u32);
+        to_unit(hash_combine(seed, (z as i32 + dz) as u32))
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let row = |dy: i32, dz: i32| lerp(lattice(0, dy, dz), lattice(1, dy, dz), fx);
+    lerp(lerp(row(0, 0), row(1, 0), fy), lerp(row(0, 1), row(1, 1), fy), fz)
+}
+
+#[derive(Clone)]
+pub enum Density {
+    Uniform,
+    // Scales the coefficients, over the volume's bounding cube
+    Grid(Arc<DensityGrid>),
+}
+
+#[derive(Clone)]
+pub struct Medium {
+    // Per unit length at density 1
+    pub absorption: f32,
+    pub scattering: f32,
+    // Tints the scattered light
+    pub color: Vector3<f32>,
+    // Henyey–Greenstein asymmetry
+    pub g: f32,
+    pub density: Density,
+}
+
+impl Medium {
+    fn extinction(&self) -> f32 {
+        self.absorption + self.scattering
+    }
+
+    // How much of the light is scattered rather than absorbed at a collision
+    pub fn albedo(&self) -> Vector3<f32> {
+        self.color * (self.scattering / self.extinction())
+    }
+
+    pub fn scatter(&self, direction: Vector3<f32>, u: (f32, f32)) -> Vector3<f32> {
+        sample_henyey_greenstein(direction, self.g, u)
+    }
+
+    // The density at `p` of the medium filling the sphere `bounds`
+    fn density(&self, (center, radius): (Vector3<f32>, f32), p: Vector3<f32>) -> f32 {
+        match &self.density {
+            Density::Uniform => 1.0,
+            Density::Grid(grid) => {
+                let local = (p - center) / (2.0 * radius) + Vector3::new(0.5, 0.5, 0.5);
+                grid.lookup(local)
+            }
+        }
+    }
+
+    // Bounds the extinction anywhere inside
+    fn majorant(&self) -> f32 {
+        match &self.density {
+            Density::Uniform => self.extinction(),
+            Density::Grid(grid) => self.extinction() * grid.max(),
+        }
+    }
+
+    // Delta tracking through the sphere `bounds` the medium fills: steps
+    // through it as if its extinction were the majorant everywhere, and at
+    // each step keeps the collision with the probability of it being real.
+    // The distance of the first real one, if it comes before `distance`.
+    pub fn sample_collision(
+        &self,
+        bounds: (Vector3<f32>, f32),
+        ray: &Ray,
+        distance: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<f32> {
+        let (mut t, end) = interval(bounds, ray, distance)?;
+        let majorant = self.majorant();
+        if majorant <= 0.0 {
+            return None;
+        }
+        loop {
+            t -= (1.0 - sampler.get_1d()).ln() / majorant;
+            if t >= end {
+                return None;
+            }
+            if let Density::Uniform = self.density {
+                return Some(t);
+            }
+            let extinction = self.extinction() * self.density(bounds, ray.origin + ray.direction * t);
+            if sampler.get_1d() * majorant < extinction {
+                return Some(t);
+            }
+        }
+    }
+
+    // The fraction of light that makes it through the sphere `bounds` along
+    // the ray up to `distance`. Exact for uniform media, otherwise estimated
+    // by ratio tracking, which takes the same steps as delta tracking but
+    // weighs by the chance of each collision being fictitious instead of
+    // choosing.
+    pub fn transmittance(
+        &self,
+        bounds: (Vector3<f32>, f32),
+        ray: &Ray,
+        distance: f32,
+        sampler: &mut dyn Sampler,
+    ) -> f32 {
+        let Some((mut t, end)) = interval(bounds, ray, distance) else {
+            return 1.0;
+        };
+        let majorant = self.majorant();
+        if let Density::Uniform = self.density {
+            return (-majorant * (end - t)).exp();
+        }
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+        let mut transmittance = 1.0;
+        loop {
+            t -= (1.0 - sampler.get_1d()).ln() / majorant;
+            if t >= end {
+                return transmittance;
+            }
+            let extinction = self.extinction() * self.density(bounds, ray.origin + ray.direction * t);
+            transmittance *= 1.0 - extinction / majorant;
+        }
+    }
+}
+
+// The part of the ray between its origin and `distance` that is inside the
+// sphere at `center`
+fn interval((center, radius): (Vector3<f32>, f32), ray: &Ray, distance: f32) -> Option<(f32, f32)> {
+    let l = ray.origin - center;
+    let b = l.dot(ray.direction);
+    let discriminant = b * b - (l.dot(l) - radius * radius);
+    if discriminant <= 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (start, end) = ((-b - root).max(0.0), (-b + root).min(distance));
+    (start < end).then_some((start, end))
+}
+
+// A sphere filled with a medium. It has no surface, rays go in and out
+// without bending.
+pub struct Volume {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+    pub medium: Medium,
+}
+
+impl Volume {
+    pub fn bounds(&self) -> (Vector3<f32>, f32) {
+        (self.center, self.radius)
+    }
+
+    // Where the ray enters the volume before `distance` and how much of what
+    // is behind the volume it hides from there. Without any randomness, for
+    // the feature buffers, so uniform media are exact and grids are marched
+    // in fixed steps.
+    pub fn opacity(&self, ray: &Ray, distance: f32) -> Option<(f32, f32)> {
+        const STEPS: usize = 32;
+        let bounds = self.bounds();
+        let (start, end) = interval(bounds, ray, distance)?;
+        let optical_depth = match self.medium.density {
+            Density::Uniform => self.medium.extinction() * (end - start),
+            Density::Grid(_) => {
+                let step = (end - start) / STEPS as f32;
+                let at = |i: usize| ray.origin + ray.direction * (start + (i as f32 + 0.5) * step);
+                let density: f32 = (0..STEPS).map(|i| self.medium.density(bounds, at(i))).sum();
+                self.medium.extinction() * density * step
+            }
+        };
+        Some((start, 1.0 - (-optical_depth).exp()))
+    }
+}
+
+// The participating media the trace mode can add to the scene
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Media {
+    #[default]
+    Vacuum,
+    // Thin haze around the whole scene
+    Fog,
+    // A uniform sphere of cloud
+    Cloud,
+    // A puff of smoke from a density grid
+    Smoke,
+    // The fog, cloud and smoke together
+    All,
+    // The flint glass sphere, filled with a medium that scatters most of the
+    // light that gets in
+    Milk,
+}
+
+impl Media {
+    pub fn volumes(self) -> Vec<Volume> {
+        let fog = || Volume {
+            center: Vector3::new(0.0, 0.0, 5.0),
+            radius: 40.0,
+            medium: Medium {
+                absorption: 0.002,
+                scattering: 0.02,
+                color: Vector3::new(1.0, 1.0, 1.0),
+                g: 0.6,
+                density: Density::Uniform,
+            },
+        };
+        let cloud = || Volume {
+            center: Vector3::new(-1.0, 4.0, 8.5),
+            radius: 1.8,
+            medium: Medium {
+                absorption: 0.02,
+                scattering: 1.0,
+                color: Vector3::new(0.9, 0.9, 1.0),
+                g: 0.0,
+                density: Density::Uniform,
+            },
+        };
+        let smoke = || Volume {
+            center: Vector3::new(2.0, -4.0, 5.0),
+            radius: 2.2,
+            medium: Medium {
+                absorption: 0.3,
+                scattering: 2.0,
+                color: Vector3::new(0.8, 0.8, 0.8),
+                g: 0.3,
+                density: Density::Grid(Arc::new(DensityGrid::smoke(64, 7))),
+            },
+        };
+        match self {
+            Media::Vacuum => Vec::new(),
+            Media::Fog => vec![fog()],
+            Media::Cloud => vec![cloud()],
+            Media::Smoke => vec![smoke()],
+            Media::All => vec![fog(), cloud(), smoke()],
+            Media::Milk => Vec::new(),
+        }
+    }
+
+    // What fills the glass sphere, which is added to the scene for it
+    pub fn interior(self) -> Option<Medium> {
+        match self {
+            Media::Milk => Some(Medium {
+                absorption: 0.05,
+                scattering: 3.0,
+                color: Vector3::new(1.0, 0.97, 0.92),
+                g: 0.0,
+                density: Density::Uniform,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMediaError(String);
+
+impl fmt::Display for ParseMediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown media `{}`, expected vacuum, fog, cloud, smoke, all or milk", self.0)
+    }
+}
+
+impl std::error::Error for ParseMediaError {}
+
+impl FromStr for Media {
+    type Err = ParseMediaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vacuum" => Ok(Media::Vacuum),
+            "fog" => Ok(Media::Fog),
+            "cloud" => Ok(Media::Cloud),
+            "smoke" => Ok(Media::Smoke),
+            "all" => Ok(Media::All),
+            "milk" => Ok(Media::Milk),
+            _ => Err(ParseMediaError(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::SamplerKind;
+
+    fn medium(density: Density) -> Medium {
+        Medium { absorption: 0.3, scattering: 0.9, color: Vector3::new(1.0, 1.0, 1.0), g: 0.0, density }
+    }
+
+    fn ray(origin: Vector3<f32>) -> Ray {
+        Ray { origin, direction: Vector3::new(0.0, 0.0, 1.0), time: 0.0 }
+    }
+
+    #[test]
+    fn homogeneous_transmittance_is_exponential_in_distance() {
+        let mut sampler = SamplerKind::Independent.sampler(1, 1);
+        let uniform = medium(Density::Uniform);
+        let bounds = (Vector3::new(0.0, 0.0, 5.0), 2.0);
+        let sigma_t = 1.2f32;
+        // Through the center the slab is 4 long, and the ray only counts up
+        // to `distance`, from where it enters
+        for (origin, distance, inside) in [(0.0, f32::INFINITY, 4.0), (0.0, 4.5, 1.5), (4.0, f32::INFINITY, 3.0), (8.0, 10.0, 0.0)] {
+            let transmittance = uniform.transmittance(bounds, &ray(Vector3::new(0.0, 0.0, origin)), distance, sampler.as_mut());
+            let expected = (-sigma_t * inside).exp();
+            assert!((transmittance - expected).abs() < 1e-5, "{} against {}", transmittance, expected);
+        }
+
+        // Ratio tracking through a grid of density 1 everywhere averages out
+        // to the same
+        let grid = medium(Density::Grid(Arc::new(DensityGrid::new(4, |_| 1.0))));
+        let count = 20000;
+        let mut sum = 0.0;
+        for i in 0..count {
+            sampler.start_sample(0, 0, i);
+            sum += grid.transmittance(bounds, &ray(Vector3::new(0.0, 0.0, 0.0)), f32::INFINITY, sampler.as_mut());
+        }
+        let expected = (-sigma_t * 4.0f32).exp();
+        assert!((sum / count as f32 - expected).abs() < 2e-3, "{} against {}", sum / count as f32, expected);
+    }
+
+    #[test]
+    fn collisions_fall_inside_the_sphere() {
+        let mut sampler = SamplerKind::Independent.sampler(1, 1);
+        let bounds = (Vector3::new(0.0, 0.0, 5.0), 2.0);
+        let uniform = medium(Density::Uniform);
+        let mut collided = 0;
+        for i in 0..1000 {
+            sampler.start_sample(0, 0, i);
+            if let Some(t) = uniform.sample_collision(bounds, &ray(Vector3::new(0.0, 0.0, 0.0)), 6.0, sampler.as_mut()) {
+                assert!((3.0..6.0).contains(&t), "{}", t);
+                collided += 1;
+            }
+        }
+        // 1 - exp(-1.2 * 3) of the rays collide before the surface at 6
+        assert!((collided as f32 / 1000.0 - (1.0 - (-3.6f32).exp())).abs() < 0.05, "{}", collided);
+    }
+}